@@ -0,0 +1,350 @@
+//! Host allowlist enforcement below the URL layer. Allowlisted names are
+//! resolved once at startup and the client is pinned to those addresses, so a
+//! rebound or re-pointed DNS record cannot move traffic mid-session.
+
+use anyhow::{bail, Context, Result};
+use ipnet::IpNet;
+use reqwest::{redirect, ClientBuilder};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use url::{Host, Url};
+
+use crate::auth::AuthConfig;
+use crate::Profile;
+
+const MAX_REDIRECTS: usize = 10;
+
+pub trait Resolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>>;
+}
+
+/// The operating system's resolver.
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>> {
+        let addrs = (host, port).to_socket_addrs().with_context(|| format!("failed to resolve {host}"))?;
+        Ok(addrs.map(|a| a.ip()).collect())
+    }
+}
+
+/// Fixed answers, e.g. from `[safety.resolve]`; names not listed do not resolve.
+pub struct StaticResolver(HashMap<String, Vec<IpAddr>>);
+
+impl StaticResolver {
+    pub fn new(table: &HashMap<String, Vec<IpAddr>>) -> Self {
+        Self(table.iter().map(|(k, v)| (k.to_ascii_lowercase(), v.clone())).collect())
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, host: &str, _: u16) -> Result<Vec<IpAddr>> {
+        match self.0.get(&host.to_ascii_lowercase()) {
+            Some(ips) => Ok(ips.clone()),
+            None => bail!("{host} is not in [safety.resolve]"),
+        }
+    }
+}
+
+/// The profile's resolver: the static table when one is given, DNS otherwise.
+pub fn resolver(p: &Profile) -> Box<dyn Resolver> {
+    if p.safety.resolve.is_empty() {
+        Box::new(SystemResolver)
+    } else {
+        Box::new(StaticResolver::new(&p.safety.resolve))
+    }
+}
+
+/// Loopback, private, link-local and other addresses that never belong to a
+/// public sandbox.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified() || v4.is_broadcast()
+                // 100.64.0.0/10, carrier-grade NAT
+                || (v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_internal(IpAddr::V4(v4)),
+            None => {
+                v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.segments()[0] & 0xfe00 == 0xfc00 // unique local
+                    || v6.segments()[0] & 0xffc0 == 0xfe80 // link local
+            }
+        },
+    }
+}
+
+/// Whether `url` points at this machine or a private network: `localhost`
+/// or an internal IP literal.
+pub fn is_local(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(name)) => name.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => is_internal(ip.into()),
+        Some(Host::Ipv6(ip)) => is_internal(ip.into()),
+        None => false,
+    }
+}
+
+/// `safety.allowlist_hosts`: host names and CIDR ranges (a bare IP is a /32 or /128).
+#[derive(Debug, Clone)]
+pub struct Allowlist {
+    names: Vec<String>,
+    nets: Vec<IpNet>,
+    allow_private: bool,
+}
+
+impl Allowlist {
+    pub fn new(p: &Profile) -> Result<Self> {
+        let mut list = Self { names: Vec::new(), nets: Vec::new(), allow_private: p.safety.allow_private_targets };
+        for entry in &p.safety.allowlist_hosts {
+            let entry = entry.trim();
+            if let Ok(net) = entry.parse::<IpNet>() {
+                list.nets.push(net.trunc());
+            } else if let Ok(ip) = entry.trim_matches(['[', ']']).parse::<IpAddr>() {
+                list.nets.push(IpNet::from(ip));
+            } else if entry.contains('/') {
+                bail!("invalid CIDR in allowlist_hosts: {entry}");
+            } else {
+                list.names.push(entry.to_ascii_lowercase());
+            }
+        }
+        Ok(list)
+    }
+
+    fn names(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether traffic may go to `ip`. An address reached through an
+    /// allowlisted name only has to sit inside a CIDR entry when the
+    /// allowlist has any.
+    fn admit(&self, ip: IpAddr, by_name: bool) -> Result<()> {
+        if is_internal(ip) && !self.allow_private {
+            bail!("{ip} is a private or loopback address; set safety.allow_private_targets to target it");
+        }
+        if (!by_name || !self.nets.is_empty()) && !self.nets.iter().any(|n| n.contains(&ip)) {
+            bail!("{ip} is outside every allowlisted CIDR range");
+        }
+        Ok(())
+    }
+
+    /// Whether the URL's host may be targeted: a name listed as such (its
+    /// addresses are checked when pinning), a name whose every address sits
+    /// inside a CIDR entry, or an IP literal inside one.
+    pub fn check(&self, url: &Url, resolver: &dyn Resolver) -> Result<()> {
+        match url.host() {
+            Some(Host::Domain(name)) if self.names(name) => Ok(()),
+            Some(Host::Domain(name)) if self.nets.is_empty() => bail!("host not in allowlist: {name}"),
+            Some(Host::Domain(name)) => {
+                let port = url.port_or_known_default().unwrap_or(443);
+                self.addrs(resolver, name, port).map(drop).with_context(|| format!("host {name} is not listed by name"))
+            }
+            Some(Host::Ipv4(ip)) => self.admit(ip.into(), false),
+            Some(Host::Ipv6(ip)) => self.admit(ip.into(), false),
+            None => bail!("URL has no host: {url}"),
+        }
+    }
+
+    /// Resolve `name` and refuse it unless the allowlist admits every address.
+    fn addrs(&self, resolver: &dyn Resolver, name: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        let ips = resolver.resolve(name, port)?;
+        if ips.is_empty() {
+            bail!("{name} resolved to no addresses");
+        }
+        let by_name = self.names(name);
+        for &ip in &ips {
+            self.admit(ip, by_name).with_context(|| format!("{name} resolved to {ip}"))?;
+        }
+        Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+}
+
+/// Allowlisted names and the addresses they were pinned to at startup.
+pub struct Pinning {
+    allowlist: Allowlist,
+    hosts: Vec<(String, Vec<SocketAddr>)>,
+}
+
+impl Pinning {
+    /// Resolve the base host and the token endpoints (fatal on failure) and
+    /// every other allowlisted name (skipped with a warning, so redirects to
+    /// it are refused), keeping only addresses the allowlist admits.
+    pub fn resolve(p: &Profile, resolver: &dyn Resolver) -> Result<Self> {
+        let allowlist = Allowlist::new(p)?;
+        let base = Url::parse(&p.base_url).with_context(|| format!("invalid base_url: {}", p.base_url))?;
+        let port = base.port_or_known_default().unwrap_or(443);
+
+        let mut hosts: Vec<(String, Vec<SocketAddr>)> = Vec::new();
+        let token_urls = p.auth.iter().flat_map(AuthConfig::token_urls);
+        for (what, url) in std::iter::once(("base_url", p.base_url.as_str())).chain(token_urls.map(|u| ("auth token_url", u))) {
+            let url = Url::parse(url).with_context(|| format!("invalid {what}: {url}"))?;
+            allowlist.check(&url, resolver).with_context(|| format!("{what} {url}"))?;
+            let Some(Host::Domain(name)) = url.host() else { continue };
+            if hosts.iter().any(|(h, _)| h.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            let addrs = allowlist
+                .addrs(resolver, name, url.port_or_known_default().unwrap_or(443))
+                .with_context(|| format!("refusing to pin {what} host {name}"))?;
+            hosts.push((name.to_ascii_lowercase(), addrs));
+        }
+        for name in &allowlist.names {
+            if hosts.iter().any(|(h, _)| h == name) {
+                continue;
+            }
+            match allowlist.addrs(resolver, name, port) {
+                Ok(addrs) => hosts.push((name.clone(), addrs)),
+                Err(e) => tracing::warn!(target: "allowlist", host = %name, "not pinned, redirects to it will be refused: {e:#}"),
+            }
+        }
+        for (host, addrs) in &hosts {
+            let ips: Vec<String> = addrs.iter().map(|a| a.ip().to_string()).collect();
+            tracing::info!(target: "allowlist", %host, ips = %ips.join(","), "pinned");
+        }
+        Ok(Self { allowlist, hosts })
+    }
+
+    /// Where to connect for `url`: the addresses its host was pinned to, or
+    /// the address it names.
+    pub fn addrs(&self, url: &Url) -> Result<Vec<SocketAddr>> {
+        let port = url.port_or_known_default().unwrap_or(443);
+        match url.host() {
+            Some(Host::Domain(name)) => match self.hosts.iter().find(|(h, _)| h.eq_ignore_ascii_case(name)) {
+                Some((_, addrs)) => Ok(addrs.iter().map(|a| SocketAddr::new(a.ip(), port)).collect()),
+                None => bail!("{name} is not pinned"),
+            },
+            Some(Host::Ipv4(ip)) => Ok(vec![SocketAddr::new(ip.into(), port)]),
+            Some(Host::Ipv6(ip)) => Ok(vec![SocketAddr::new(ip.into(), port)]),
+            None => bail!("{url} has no host"),
+        }
+    }
+
+    /// Pin the client to the resolved addresses, bypass any proxy (it would
+    /// resolve names itself) and only follow redirects to pinned or admitted
+    /// hosts.
+    pub fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        let mut builder = builder.no_proxy();
+        for (host, addrs) in &self.hosts {
+            builder = builder.resolve_to_addrs(host, addrs);
+        }
+        let allowlist = self.allowlist.clone();
+        let pinned: Vec<String> = self.hosts.iter().map(|(h, _)| h.clone()).collect();
+        builder.redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            let admitted = match attempt.url().host() {
+                Some(Host::Domain(name)) => pinned.iter().any(|h| h.eq_ignore_ascii_case(name)),
+                Some(Host::Ipv4(ip)) => allowlist.admit(ip.into(), false).is_ok(),
+                Some(Host::Ipv6(ip)) => allowlist.admit(ip.into(), false).is_ok(),
+                None => false,
+            };
+            if admitted {
+                attempt.follow()
+            } else {
+                let host = attempt.url().host_str().unwrap_or_default().to_string();
+                attempt.error(format!("refused redirect to non-allowlisted host {host}"))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn list(entries: &[&str]) -> Allowlist {
+        let mut p = testutil::profile("http://127.0.0.1:1");
+        p.safety.allowlist_hosts = entries.iter().map(|e| e.to_string()).collect();
+        p.safety.allow_private_targets = false;
+        Allowlist::new(&p).unwrap()
+    }
+
+    fn answers(table: &[(&str, &[&str])]) -> StaticResolver {
+        let table = table.iter().map(|(h, ips)| (h.to_string(), ips.iter().map(|ip| ip.parse().unwrap()).collect())).collect();
+        StaticResolver::new(&table)
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn a_listed_name_passes_without_resolving() {
+        let list = list(&["sandbox.example.test", "203.0.113.0/24"]);
+        list.check(&url("https://SANDBOX.example.test/v1"), &answers(&[])).unwrap();
+    }
+
+    #[test]
+    fn an_unlisted_name_passes_only_if_every_address_is_in_a_range() {
+        let list = list(&["sandbox.example.test", "203.0.113.0/24"]);
+        let resolver = answers(&[
+            ("inside.test", &["203.0.113.7", "203.0.113.8"]),
+            ("straddles.test", &["203.0.113.7", "198.51.100.1"]),
+        ]);
+
+        list.check(&url("https://inside.test/"), &resolver).unwrap();
+        let err = list.check(&url("https://straddles.test/"), &resolver).unwrap_err();
+        assert!(format!("{err:#}").contains("198.51.100.1"), "{err:#}");
+        list.check(&url("https://nowhere.test/"), &resolver).unwrap_err();
+        list.check(&url("https://inside.test/"), &answers(&[("inside.test", &[])])).unwrap_err();
+    }
+
+    #[test]
+    fn an_unlisted_name_fails_without_ranges() {
+        let list = list(&["sandbox.example.test"]);
+        let resolver = answers(&[("other.test", &["203.0.113.7"])]);
+        let err = list.check(&url("https://other.test/"), &resolver).unwrap_err();
+        assert!(err.to_string().contains("not in allowlist"), "{err}");
+    }
+
+    #[test]
+    fn ip_literals_must_sit_inside_a_range() {
+        let list = list(&["203.0.113.0/24", "2001:db8::/32"]);
+        let resolver = answers(&[]);
+        list.check(&url("https://203.0.113.200/"), &resolver).unwrap();
+        list.check(&url("https://[2001:db8::1]/"), &resolver).unwrap();
+        list.check(&url("https://198.51.100.1/"), &resolver).unwrap_err();
+        list.check(&url("https://[2001:db9::1]/"), &resolver).unwrap_err();
+    }
+
+    #[test]
+    fn token_endpoints_are_pinned_to_their_checked_addresses() {
+        let mut p = testutil::profile("http://127.0.0.1:1");
+        p.safety.allowlist_hosts = vec!["127.0.0.0/8".into()];
+        p.auth = Some(toml::from_str(r#"
+            type = "oauth2"
+            token_url = "http://tokens.test:8080/token"
+            client_id = "fuzzkit"
+            client_secret = { env = "FUZZKIT_TEST_CLIENT_SECRET" }
+        "#).unwrap());
+
+        let pinning = Pinning::resolve(&p, &answers(&[("tokens.test", &["127.0.0.9"])])).unwrap();
+        assert_eq!(pinning.hosts, vec![("tokens.test".to_string(), vec!["127.0.0.9:8080".parse().unwrap()])]);
+
+        let err = Pinning::resolve(&p, &answers(&[("tokens.test", &["10.0.0.9"])])).err().unwrap();
+        assert!(format!("{err:#}").contains("10.0.0.9"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn redirects_off_the_allowlist_are_refused() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+        let server = MockServer::start().await;
+        let port = server.address().port();
+        let hop = |to: String| ResponseTemplate::new(302).insert_header("Location", to);
+        Mock::given(path("/away")).respond_with(hop(format!("http://elsewhere.test:{port}/ok"))).mount(&server).await;
+        Mock::given(path("/home")).respond_with(hop(format!("http://127.0.0.1:{port}/ok"))).mount(&server).await;
+        Mock::given(path("/ok")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let p = testutil::profile(&server.uri());
+        // Resolving elsewhere.test to the server itself: only the allowlist stands in the way.
+        let resolver = answers(&[("elsewhere.test", &["127.0.0.1"])]);
+        let client = Pinning::resolve(&p, &resolver).unwrap().apply(reqwest::Client::builder()).build().unwrap();
+
+        assert_eq!(client.get(format!("{}/home", server.uri())).send().await.unwrap().status(), 200);
+        let err = client.get(format!("{}/away", server.uri())).send().await.unwrap_err();
+        assert!(format!("{err:?}").contains("refused redirect to non-allowlisted host elsewhere.test"), "{err:?}");
+    }
+}
@@ -0,0 +1,425 @@
+//! Per-session artifact directory: `<root>/<profile>-<UTC timestamp>/` with
+//! `requests.jsonl` (every exchange), `findings.jsonl` (every exchange an
+//! oracle fired on), `profile.toml` (the exact profile used) and
+//! `session.json` (seed, mode, final counters, the corpus entries planned
+//! from and, in timestamp mode, the clock skews accepted). `buckets.json`
+//! groups the exchanges oracles fired on. Minimised findings add
+//! `minimized.jsonl` and, per finding, `minimized/<index>-original.http` and
+//! `minimized/<index>-minimized.http`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+use crate::bucket::Bucket;
+use crate::minimize::Shrunk;
+use crate::oracle::{Signal, Verdict};
+use crate::plan::Case;
+use crate::runner::Outcome;
+use crate::scheduler::Snapshot;
+use crate::transport::{Body, Request};
+use crate::Mode;
+
+/// Bodies are logged up to this many bytes; the hash always covers all of it.
+const PREVIEW_BYTES: usize = 2048;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BodyRecord {
+    pub len: u64,
+    pub sha256: String,
+    pub preview: String,
+    pub truncated: bool,
+}
+
+impl BodyRecord {
+    fn of(body: &Body) -> Self {
+        let mut hasher = Sha256::new();
+        body.for_each_chunk(|chunk| hasher.update(chunk));
+        let len = body.len();
+        Self {
+            len,
+            sha256: hex::encode(hasher.finalize()),
+            preview: String::from_utf8_lossy(&body.head(PREVIEW_BYTES)).into_owned(),
+            truncated: len > PREVIEW_BYTES as u64,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestRecord {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<BodyRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseRecord {
+    pub status: u16,
+    pub latency_ms: u64,
+    pub headers: Vec<(String, String)>,
+    pub body: BodyRecord,
+}
+
+/// One line of `requests.jsonl` / `findings.jsonl`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Exchange {
+    pub index: usize,
+    pub operator: String,
+    pub target: String,
+    /// As sent, credential redacted; the case itself when it was never sent
+    pub request: RequestRecord,
+    /// SHA-256 of the case's body before the transport stamped it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_sha256: Option<String>,
+    pub response: Option<ResponseRecord>,
+    pub error: Option<String>,
+    pub verdict: Verdict,
+    pub signals: Vec<Signal>,
+}
+
+impl Exchange {
+    pub fn of(o: &Outcome) -> Self {
+        let (response, error) = match &o.result {
+            Ok(r) => (
+                Some(ResponseRecord {
+                    status: r.status,
+                    latency_ms: r.elapsed.as_millis() as u64,
+                    headers: r.headers.clone(),
+                    body: BodyRecord::of(&Body::Bytes(r.body.clone())),
+                }),
+                None,
+            ),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+        Self {
+            index: o.index,
+            operator: o.case.operator.clone(),
+            target: o.case.target.clone(),
+            request: RequestRecord::of(o.result.as_ref().map_or(&o.case.request, |r| &r.sent)),
+            case_sha256: o.case.request.body.as_ref().map(|b| BodyRecord::of(b).sha256),
+            response,
+            error,
+            verdict: o.verdict(),
+            signals: o.signals.clone(),
+        }
+    }
+}
+
+/// One line of `minimized.jsonl`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MinimizedRecord {
+    pub index: usize,
+    pub oracle: String,
+    pub operator: String,
+    pub attempts: u32,
+    pub original_bytes: u64,
+    pub minimized_bytes: u64,
+    pub request: RequestRecord,
+}
+
+/// The request as raw HTTP/1.1 text, relative to `base_url`.
+fn http_text(req: &Request) -> Vec<u8> {
+    let mut target = req.path.clone();
+    if !req.query.is_empty() {
+        let mut q = url::form_urlencoded::Serializer::new(String::new());
+        q.extend_pairs(&req.query);
+        target = format!("{target}?{}", q.finish());
+    }
+    let mut out = format!("{} {target} HTTP/1.1\r\n", req.method).into_bytes();
+    for (k, v) in &req.headers {
+        out.extend_from_slice(format!("{k}: {v}\r\n").as_bytes());
+    }
+    if let Some(body) = &req.body {
+        out.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    if let Some(body) = &req.body {
+        body.for_each_chunk(|chunk| out.extend_from_slice(chunk));
+    }
+    out
+}
+
+impl RequestRecord {
+    fn of(req: &Request) -> Self {
+        // Long header and query values (oversized cases) are cut like bodies.
+        let cut = |kv: &[(String, String)]| -> Vec<(String, String)> {
+            kv.iter()
+                .map(|(k, v)| (k.clone(), v.chars().take(PREVIEW_BYTES).collect()))
+                .collect()
+        };
+        Self {
+            method: req.method.clone(),
+            path: req.path.clone(),
+            query: cut(&req.query),
+            headers: cut(&req.headers),
+            body: req.body.as_ref().map(BodyRecord::of),
+        }
+    }
+
+    /// Whether the record holds the whole request: no value was cut and the
+    /// body preview is the body itself, byte for byte.
+    pub fn complete(&self) -> bool {
+        let uncut = |kv: &[(String, String)]| kv.iter().all(|(_, v)| v.chars().count() < PREVIEW_BYTES);
+        let body = self.body.as_ref().is_none_or(|b| !b.truncated && hex::encode(Sha256::digest(b.preview.as_bytes())) == b.sha256);
+        uncut(&self.query) && uncut(&self.headers) && body
+    }
+}
+
+/// `session.json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub profile: String,
+    pub seed: u64,
+    pub mode: Mode,
+    pub started: String,
+    pub finished: String,
+    pub cases: usize,
+    pub issued: u32,
+    pub retries: u32,
+    pub completed: u32,
+    pub failed: u32,
+    /// Cases that failed at the transport level, less the oversized ones a
+    /// target may drop; the run succeeded when this is zero
+    #[serde(default)]
+    pub errors: u32,
+    /// OAuth2 token requests, charged to the budget like any other
+    #[serde(default)]
+    pub token_fetches: u32,
+    pub peak_in_flight: usize,
+    pub observed_rate: f64,
+    pub budget: u32,
+    pub concurrency: usize,
+    /// Corpus entries the cases were planned from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub corpus: Vec<String>,
+    /// Timestamp mode: the clock skews the API accepted, e.g. `-60s`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accepted_skews: Option<Vec<String>>,
+}
+
+pub struct Session {
+    pub dir: PathBuf,
+    profile: String,
+    started: OffsetDateTime,
+    requests: BufWriter<File>,
+    findings: BufWriter<File>,
+    minimized: BufWriter<File>,
+    accepted_skews: Option<Vec<String>>,
+}
+
+fn stamp(t: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        t.year(),
+        u8::from(t.month()),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
+}
+
+fn jsonl(path: &Path) -> Result<BufWriter<File>> {
+    let file = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+/// The profile name, safe to use in file names.
+pub fn safe_name(profile_name: &str) -> String {
+    profile_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+impl Session {
+    /// Create the session directory and copy the profile into it verbatim.
+    pub fn create(root: &Path, profile_name: &str, profile_path: &str) -> Result<Self> {
+        let started = OffsetDateTime::now_utc();
+        let safe_name = safe_name(profile_name);
+        fs::create_dir_all(root).with_context(|| format!("failed to create artifact root {}", root.display()))?;
+        // Two sessions can start within the same second (e.g. a quick replay).
+        let base = format!("{safe_name}-{}", stamp(started));
+        let mut dir = root.join(&base);
+        let mut n = 1;
+        while let Err(e) = fs::create_dir(&dir) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(e).with_context(|| format!("failed to create artifact dir {}", dir.display()));
+            }
+            dir = root.join(format!("{base}-{n}"));
+            n += 1;
+        }
+        fs::copy(profile_path, dir.join("profile.toml"))
+            .with_context(|| format!("failed to copy profile {profile_path} into {}", dir.display()))?;
+        Ok(Self {
+            requests: jsonl(&dir.join("requests.jsonl"))?,
+            findings: jsonl(&dir.join("findings.jsonl"))?,
+            minimized: jsonl(&dir.join("minimized.jsonl"))?,
+            dir,
+            profile: profile_name.to_string(),
+            started,
+            accepted_skews: None,
+        })
+    }
+
+    pub fn record(&mut self, o: &Outcome) -> Result<()> {
+        let line = serde_json::to_string(&Exchange::of(o))?;
+        writeln!(self.requests, "{line}")?;
+        if !o.signals.is_empty() {
+            writeln!(self.findings, "{line}")?;
+        }
+        Ok(())
+    }
+
+    /// The session directory's name, which identifies it in the bucket DB.
+    pub fn name(&self) -> String {
+        self.dir.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    pub fn buckets(&self, buckets: &[Bucket]) -> Result<()> {
+        fs::write(self.dir.join("buckets.json"), serde_json::to_string_pretty(buckets)?)?;
+        Ok(())
+    }
+
+    /// Record the clock skews a timestamp session found the API accepts.
+    pub fn skews(&mut self, accepted: Vec<String>) {
+        self.accepted_skews = Some(accepted);
+    }
+
+    /// Save a finding's original case and its shrunk form.
+    pub fn minimized(&mut self, o: &Outcome, shrunk: &Shrunk) -> Result<()> {
+        let dir = self.dir.join("minimized");
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        fs::write(dir.join(format!("{}-original.http", o.index)), http_text(&o.case.request))?;
+        fs::write(dir.join(format!("{}-minimized.http", o.index)), http_text(&shrunk.case.request))?;
+        let record = MinimizedRecord {
+            index: o.index,
+            oracle: shrunk.oracle.clone(),
+            operator: o.case.operator.clone(),
+            attempts: shrunk.attempts,
+            original_bytes: o.case.request.size(),
+            minimized_bytes: shrunk.case.request.size(),
+            request: RequestRecord::of(&shrunk.case.request),
+        };
+        writeln!(self.minimized, "{}", serde_json::to_string(&record)?)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self, seed: u64, mode: Mode, cases: usize, errors: u32, snap: &Snapshot, corpus: Vec<String>) -> Result<PathBuf> {
+        self.requests.flush()?;
+        self.findings.flush()?;
+        self.minimized.flush()?;
+        let rfc3339 = |t: OffsetDateTime| t.format(&time::format_description::well_known::Rfc3339).unwrap_or_default();
+        let record = SessionRecord {
+            profile: self.profile.clone(),
+            seed,
+            mode,
+            started: rfc3339(self.started),
+            finished: rfc3339(OffsetDateTime::now_utc()),
+            cases,
+            issued: snap.issued,
+            retries: snap.retries,
+            completed: snap.completed,
+            failed: snap.failed,
+            errors,
+            token_fetches: snap.token_fetches,
+            peak_in_flight: snap.peak_in_flight,
+            observed_rate: snap.rate(),
+            budget: snap.budget,
+            concurrency: snap.concurrency,
+            corpus,
+            accepted_skews: self.accepted_skews.take(),
+        };
+        fs::write(self.dir.join("session.json"), serde_json::to_string_pretty(&record)?)?;
+        Ok(self.dir)
+    }
+}
+
+/// A session's `buckets.json`; empty for sessions written before bucketing.
+pub fn load_buckets(dir: &Path) -> Result<Vec<Bucket>> {
+    match fs::read_to_string(dir.join("buckets.json")) {
+        Ok(raw) => serde_json::from_str(&raw).context("invalid buckets.json"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).context("failed to read buckets.json"),
+    }
+}
+
+/// A session's `minimized.jsonl`; empty when nothing was minimised.
+pub fn load_minimized(dir: &Path) -> Result<Vec<MinimizedRecord>> {
+    let log = match fs::read_to_string(dir.join("minimized.jsonl")) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("failed to read minimized.jsonl"),
+    };
+    log.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| serde_json::from_str(l).with_context(|| format!("minimized.jsonl line {}", n + 1)))
+        .collect()
+}
+
+/// Read back a session directory written by `Session`.
+pub fn load(dir: &Path) -> Result<(SessionRecord, Vec<Exchange>)> {
+    let session_path = dir.join("session.json");
+    let raw = fs::read_to_string(&session_path)
+        .with_context(|| format!("failed to read {} (is this a session directory?)", session_path.display()))?;
+    let session: SessionRecord = serde_json::from_str(&raw).context("invalid session.json")?;
+    let log = fs::read_to_string(dir.join("requests.jsonl")).context("failed to read requests.jsonl")?;
+    let exchanges = log
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| serde_json::from_str(l).with_context(|| format!("requests.jsonl line {}", n + 1)))
+        .collect::<Result<Vec<Exchange>>>()?;
+    Ok((session, exchanges))
+}
+
+/// Whether a regenerated case matches what was logged for it. Logs written
+/// before `case_sha256` hash the body as sent, which was the case's own.
+pub fn same_request(logged: &Exchange, case: &Case) -> bool {
+    let body = case.request.body.as_ref().map(|b| BodyRecord::of(b).sha256);
+    let logged_body = match (&logged.case_sha256, &logged.request.body) {
+        (Some(sha), _) => Some(sha.clone()),
+        (None, b) => b.as_ref().map(|b| b.sha256.clone()),
+    };
+    logged.operator == case.operator
+        && logged.request.method == case.request.method
+        && logged.request.path == case.request.path
+        && logged_body == body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan;
+    use crate::testutil;
+    use crate::transport::{Response, REDACTED};
+    use std::time::Duration;
+
+    #[test]
+    fn exchanges_log_the_request_as_sent_and_still_match_their_case() {
+        let case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        let mut sent = case.request.clone();
+        sent.headers.push(("Authorization".into(), REDACTED.into()));
+        sent.headers.push(("X-Env".into(), "sandbox".into()));
+        sent.body = Some(Body::Bytes(br#"{"ts":1700000000,"amount":150000,"period":"2024-01","pin":"A000000000B"}"#.to_vec()));
+        let resp = Response { status: 201, headers: Vec::new(), body: Vec::new(), elapsed: Duration::ZERO, sent };
+        let o = Outcome { index: 0, case: case.clone(), result: Ok(resp), signals: Vec::new() };
+
+        let logged = Exchange::of(&o);
+
+        assert!(logged.request.headers.contains(&("Authorization".into(), REDACTED.into())));
+        assert!(logged.request.headers.contains(&("X-Env".into(), "sandbox".into())));
+        assert!(logged.request.body.as_ref().unwrap().preview.starts_with(r#"{"ts":"#));
+        assert!(same_request(&logged, &case));
+        let mut other = case.clone();
+        other.request.body = Some(Body::Bytes(b"{}".to_vec()));
+        assert!(!same_request(&logged, &other));
+    }
+}
@@ -0,0 +1,307 @@
+//! Finding buckets: outcomes an oracle fired on, grouped by a signature of
+//! endpoint, status, normalised error message, leaked stack frames and
+//! first operator, so a server bug that fires hundreds of times reads as one line.
+//! A per-profile bucket DB next to the session directories remembers every
+//! bucket seen, so a rerun can tell new buckets from known ones.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::artifacts;
+use crate::endpoint::{self, Endpoint};
+use crate::oracle::Verdict;
+use crate::runner::Outcome;
+use crate::transport::Response;
+
+/// Error templates are cut to this many characters.
+const TEMPLATE_CHARS: usize = 120;
+/// Members that usually carry an error message, most specific first.
+const MESSAGE_KEYS: &[&str] = &["message", "error_description", "detail", "error", "title", "msg", "description"];
+/// Stack frames kept in a signature, innermost first.
+const TOP_FRAMES: usize = 3;
+
+/// What two outcomes must share to land in the same bucket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    /// `METHOD /path/{template}`, or the request line when no endpoint matches
+    pub endpoint: String,
+    /// `None` for transport errors
+    pub status: Option<u16>,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub frames: Vec<String>,
+    /// The first operator of a stacked havoc ID (`a+b+c` keys as `a`), so
+    /// stacks that share a trigger do not each open a bucket
+    pub operator: String,
+}
+
+impl Signature {
+    pub fn of(endpoints: &[Endpoint], o: &Outcome) -> Self {
+        let req = &o.case.request;
+        let endpoint = endpoint::of(endpoints, &req.method, &req.path)
+            .map_or_else(|| format!("{} {}", req.method, req.path), Endpoint::key);
+        let (status, message, frames) = match &o.result {
+            Ok(resp) => (Some(resp.status), error_template(resp).unwrap_or_default(), frames(&String::from_utf8_lossy(&resp.body))),
+            Err(e) => (None, normalize(&format!("{e:#}")), Vec::new()),
+        };
+        let operator = o.case.operator.split('+').next().unwrap_or_default().to_string();
+        Self { endpoint, status, message, frames, operator }
+    }
+
+    pub fn id(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        hex::encode(&Sha256::digest(json.as_bytes())[..6])
+    }
+}
+
+/// One bucket of a session, as written to `buckets.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bucket {
+    pub id: String,
+    pub signature: Signature,
+    /// Most severe verdict of any outcome in it
+    pub verdict: Verdict,
+    pub count: usize,
+    /// The smallest request among the most severe outcomes
+    pub representative: usize,
+    pub indices: Vec<usize>,
+    /// Not in the bucket DB before this session
+    pub new: bool,
+}
+
+/// Bucket every outcome an oracle fired on, in order of first appearance.
+pub fn group(endpoints: &[Endpoint], outcomes: &[Outcome]) -> Vec<Bucket> {
+    let mut buckets: Vec<Bucket> = Vec::new();
+    // (verdict, size) of each representative
+    let mut reps: Vec<(Verdict, u64)> = Vec::new();
+    for o in outcomes.iter().filter(|o| !o.signals.is_empty()) {
+        let signature = Signature::of(endpoints, o);
+        let id = signature.id();
+        let size = o.case.request.size();
+        match buckets.iter().position(|b| b.id == id) {
+            Some(i) => {
+                let b = &mut buckets[i];
+                b.count += 1;
+                b.indices.push(o.index);
+                b.verdict = b.verdict.max(o.verdict());
+                let (verdict, smallest) = reps[i];
+                if o.verdict() > verdict || (o.verdict() == verdict && size < smallest) {
+                    reps[i] = (o.verdict(), size);
+                    b.representative = o.index;
+                }
+            }
+            None => {
+                buckets.push(Bucket {
+                    id,
+                    signature,
+                    verdict: o.verdict(),
+                    count: 1,
+                    representative: o.index,
+                    indices: vec![o.index],
+                    new: true,
+                });
+                reps.push((o.verdict(), size));
+            }
+        }
+    }
+    buckets
+}
+
+/// A bucket as the DB remembers it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Known {
+    pub signature: Signature,
+    pub verdict: Verdict,
+    /// Session directory names
+    pub first_seen: String,
+    pub last_seen: String,
+    pub sessions: u32,
+    pub count: u64,
+}
+
+/// `<artifacts>/<profile>.buckets.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Db {
+    pub buckets: BTreeMap<String, Known>,
+}
+
+impl Db {
+    pub fn path(root: &Path, profile: &str) -> PathBuf {
+        root.join(format!("{}.buckets.json", artifacts::safe_name(profile)))
+    }
+
+    /// The DB at `path`, or an empty one if there is none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw).with_context(|| format!("invalid bucket DB {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read bucket DB {}", path.display())),
+        }
+    }
+
+    /// Flag each of the session's buckets new or known, without recording them.
+    pub fn flag(&self, buckets: &mut [Bucket]) {
+        for b in buckets {
+            b.new = !self.buckets.contains_key(&b.id);
+        }
+    }
+
+    /// Flag each of the session's buckets new or known, then record them.
+    pub fn merge(&mut self, session: &str, buckets: &mut [Bucket]) {
+        self.flag(buckets);
+        for b in buckets {
+            let known = self.buckets.entry(b.id.clone()).or_insert_with(|| Known {
+                signature: b.signature.clone(),
+                verdict: b.verdict,
+                first_seen: session.to_string(),
+                last_seen: String::new(),
+                sessions: 0,
+                count: 0,
+            });
+            known.verdict = known.verdict.max(b.verdict);
+            known.last_seen = session.to_string();
+            known.sessions += 1;
+            known.count += b.count as u64;
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("failed to write bucket DB {}", path.display()))
+    }
+}
+
+/// Mask what varies between two reports of the same error: quoted values,
+/// timestamps, UUIDs, hex IDs and numbers.
+pub fn normalize(text: &str) -> String {
+    static MASKS: LazyLock<[(Regex, &str); 5]> = LazyLock::new(|| {
+        let re = |p: &str| Regex::new(p).expect("valid mask pattern");
+        [
+            (re(r#""[^"]*"|'[^']*'"#), "'*'"),
+            (re(r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}(:\d{2}(\.\d+)?)?(Z|[+-]\d{2}:?\d{2})?"), "<ts>"),
+            (re(r"(?i)\b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b"), "<id>"),
+            (re(r"(?i)\b[0-9a-f]*\d[0-9a-f]*[a-f][0-9a-f]*\b|\b[0-9a-f]*[a-f][0-9a-f]*\d[0-9a-f]*\b"), "<id>"),
+            (re(r"\d+"), "#"),
+        ]
+    });
+    let masked = MASKS.iter().fold(text.to_string(), |t, (re, with)| re.replace_all(&t, *with).into_owned());
+    masked.chars().take(TEMPLATE_CHARS).collect()
+}
+
+/// The first message-like string in a JSON body.
+fn message(v: &Value) -> Option<&str> {
+    match v {
+        Value::Object(m) => MESSAGE_KEYS
+            .iter()
+            .find_map(|k| m.get(*k).and_then(Value::as_str))
+            .or_else(|| m.values().find_map(message)),
+        Value::Array(items) => items.iter().find_map(message),
+        _ => None,
+    }
+}
+
+/// The response's error message, normalised. Plain-text bodies count only
+/// on error statuses, by their first line.
+pub fn error_template(resp: &Response) -> Option<String> {
+    let text = match serde_json::from_slice::<Value>(&resp.body) {
+        Ok(v) => message(&v)?.to_string(),
+        Err(_) if resp.status >= 400 => {
+            String::from_utf8_lossy(&resp.body).lines().find(|l| !l.trim().is_empty())?.trim().to_string()
+        }
+        Err(_) => return None,
+    };
+    Some(normalize(&text))
+}
+
+/// The innermost frames of a leaked stack trace, without line numbers.
+fn frames(body: &str) -> Vec<String> {
+    // (pattern, whether the trace lists the innermost frame last)
+    static FRAMES: LazyLock<Vec<(Regex, bool)>> = LazyLock::new(|| {
+        let re = |p: &str| Regex::new(p).expect("valid frame pattern");
+        vec![
+            (re(r#"File "(?:[^"]*[/\\])?([^"/\\]+)", line \d+, in (\S+)"#), true), // Python
+            (re(r"\bat ([\w$.<>]+)\([\w$]+\.(?:java|kt|scala):\d+\)"), false),     // JVM
+            (re(r"\bat ([\w.<>`]+)\([^)]*\) in .+?:line \d+"), false),            // .NET
+            (re(r"(?m)^\s+at (\S+) \(.+?:\d+:\d+\)"), false),                     // Node
+            (re(r"(?m)^([\w./*()]+)\(.*\)\n\s+\S+\.go:\d+"), false),              // Go
+        ]
+    });
+    for (re, innermost_last) in FRAMES.iter() {
+        let mut found: Vec<String> = re
+            .captures_iter(body)
+            .map(|c| c.iter().skip(1).flatten().map(|m| m.as_str()).collect::<Vec<_>>().join(":"))
+            .collect();
+        if found.is_empty() {
+            continue;
+        }
+        if *innermost_last {
+            found.reverse();
+        }
+        found.truncate(TOP_FRAMES);
+        return found;
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oracle::Signal;
+    use crate::plan;
+    use crate::testutil;
+    use std::time::Duration;
+
+    /// A 500 on the test endpoint for a case made by `operator`.
+    fn outcome(index: usize, operator: &str) -> Outcome {
+        let mut case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        case.operator = operator.into();
+        let resp = Response {
+            status: 500,
+            headers: Vec::new(),
+            body: br#"{"error":"unexpected null at 'amount'"}"#.to_vec(),
+            elapsed: Duration::from_millis(5),
+            sent: case.request.clone(),
+        };
+        let signal = Signal { oracle: "status.5xx".into(), verdict: Verdict::Finding, detail: "server error 500".into() };
+        Outcome { index, case, result: Ok(resp), signals: vec![signal] }
+    }
+
+    #[test]
+    fn stacked_operators_bucket_by_their_first() {
+        let endpoints = testutil::profile("http://127.0.0.1:1").endpoints;
+        let outcomes = [
+            outcome(0, "null.set"),
+            outcome(1, "null.set+key.drop"),
+            outcome(2, "null.set+unicode.rtl+number.max"),
+            outcome(3, "key.drop+null.set"),
+        ];
+
+        let buckets = group(&endpoints, &outcomes);
+
+        let grouped: Vec<(&str, &[usize])> = buckets.iter().map(|b| (b.signature.operator.as_str(), b.indices.as_slice())).collect();
+        assert_eq!(grouped, [("null.set", &[0, 1, 2][..]), ("key.drop", &[3][..])]);
+    }
+
+    #[test]
+    fn flagging_does_not_record_buckets() {
+        let endpoints = testutil::profile("http://127.0.0.1:1").endpoints;
+        let mut db = Db::default();
+        let mut first = group(&endpoints, &[outcome(0, "null.set")]);
+        db.merge("run-1", &mut first);
+        let mut replayed = group(&endpoints, &[outcome(0, "null.set"), outcome(1, "key.drop")]);
+
+        db.flag(&mut replayed);
+
+        assert_eq!(replayed.iter().map(|b| b.new).collect::<Vec<_>>(), [false, true]);
+        assert_eq!(db.buckets.len(), 1);
+        let known = &db.buckets[&first[0].id];
+        assert_eq!((known.sessions, known.count, known.last_seen.as_str()), (1, 1, "run-1"));
+    }
+}
@@ -0,0 +1,355 @@
+//! Auth-bypass family: per endpoint, the baseline re-sent with its credential
+//! dropped, forged, swapped for another identity's or moved to the wrong
+//! place, and with the valid credential under a re-cased header name. A case
+//! only records how to tamper; the transport applies it to the live
+//! credential at send time, so tokens never reach the artifacts. Any 2xx to a
+//! tampered credential is an `auth.bypass` finding; a 401 or 403 to a
+//! re-cased header name is an `auth.case` anomaly.
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use serde_json::{Map, Value};
+use sha2::{Sha256, Sha384, Sha512};
+use time::OffsetDateTime;
+
+use crate::auth::{base64url, base64url_decode, AuthConfig, Credential, Scheme};
+use crate::plan::Case;
+use crate::transport::{HttpVersion, Request};
+
+/// Signs the wrong-key JWTs; the server cannot know it.
+const WRONG_KEY: &[u8] = b"api-fuzzkit-wrong-key";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forgery {
+    /// `exp` an hour in the past, re-signed with `[auth] jwt_key`
+    Expired,
+    /// A payload segment that is not JSON
+    Malformed,
+    /// `alg: none` and no signature
+    AlgNone,
+    /// Re-signed with HS256 under a key of ours
+    WrongKey,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Query,
+    Cookie,
+    Header,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Casing {
+    Lower,
+    Upper,
+}
+
+/// How a request presents its credential.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Tamper {
+    /// As `[auth]` configures it
+    #[default]
+    None,
+    Drop,
+    Jwt(Forgery),
+    /// The credential of the named `[[auth.identities]]` entry
+    Identity(String),
+    /// The credential moved to a place the scheme does not use
+    Location(Place),
+    /// The valid credential under its header name in another case, which
+    /// HTTP says must not matter; sent over HTTP/1.1, since HTTP/2 names are
+    /// lower case
+    Case(Casing),
+}
+
+impl Tamper {
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Tamper::None => "baseline",
+            Tamper::Drop => "auth.drop",
+            Tamper::Jwt(_) => "auth.jwt",
+            Tamper::Identity(_) => "auth.identity",
+            Tamper::Location(_) => "auth.location",
+            Tamper::Case(_) => "auth.case",
+        }
+    }
+
+    pub fn target(&self) -> String {
+        match self {
+            Tamper::None | Tamper::Drop => String::new(),
+            Tamper::Jwt(f) => match f {
+                Forgery::Expired => "expired",
+                Forgery::Malformed => "malformed",
+                Forgery::AlgNone => "alg-none",
+                Forgery::WrongKey => "wrong-key",
+            }
+            .into(),
+            Tamper::Identity(name) => name.clone(),
+            Tamper::Location(place) => format!("{place:?}").to_lowercase(),
+            Tamper::Case(casing) => format!("{casing:?}").to_lowercase(),
+        }
+    }
+}
+
+/// The family for one endpoint's baseline; empty unless `[auth]` declares a
+/// second identity.
+pub fn cases(auth: &AuthConfig, http_version: HttpVersion, base: &Case) -> Vec<Case> {
+    if auth.identities.is_empty() {
+        return Vec::new();
+    }
+    let mut tampers = Vec::new();
+    if !matches!(auth.scheme, Scheme::Mtls) {
+        tampers.push(Tamper::Drop);
+    }
+    if auth.jwt {
+        // Without the key an expired token would also fail on its signature.
+        if auth.jwt_key.is_some() {
+            tampers.push(Tamper::Jwt(Forgery::Expired));
+        }
+        tampers.extend([Forgery::Malformed, Forgery::AlgNone, Forgery::WrongKey].map(Tamper::Jwt));
+    }
+    tampers.extend(auth.identities.iter().map(|i| Tamper::Identity(i.name.clone())));
+    match (&auth.scheme, auth.header()) {
+        (Scheme::Mtls, _) => {}
+        (_, Some(_)) => tampers.extend([Place::Query, Place::Cookie].map(Tamper::Location)),
+        (_, None) => tampers.push(Tamper::Location(Place::Header)),
+    }
+    if auth.header().is_some() && http_version != HttpVersion::Http2 {
+        tampers.extend([Casing::Lower, Casing::Upper].map(Tamper::Case));
+    }
+    tampers
+        .into_iter()
+        .map(|t| Case {
+            operator: t.operator().into(),
+            target: t.target(),
+            request: Request { auth: t, ..base.request.clone() },
+        })
+        .collect()
+}
+
+/// What to send in place of `credential`, which for `Tamper::Identity` is
+/// already the other identity's. `jwt_key` re-signs expired tokens.
+pub fn apply(tamper: &Tamper, credential: Option<Credential>, jwt_key: Option<&[u8]>) -> Result<Option<Credential>> {
+    let Some(credential) = credential else { return Ok(None) };
+    Ok(match tamper {
+        Tamper::None | Tamper::Identity(_) => Some(credential),
+        Tamper::Drop => None,
+        Tamper::Jwt(forgery) => {
+            let Credential::Header(name, value) = credential else { bail!("a JWT forgery needs a bearer token") };
+            let (scheme, token) = value.split_once(' ').unwrap_or(("Bearer", &value));
+            Some(Credential::Header(name.clone(), format!("{scheme} {}", forge(*forgery, token, jwt_key)?)))
+        }
+        Tamper::Location(place) => {
+            let (param, token) = match credential {
+                Credential::Header(name, value) if name.eq_ignore_ascii_case("authorization") => {
+                    let token = value.split_once(' ').map_or(value.as_str(), |(_, t)| t);
+                    ("access_token".to_string(), token.to_string())
+                }
+                Credential::Header(name, value) | Credential::Query(name, value) => (name, value),
+            };
+            Some(match place {
+                Place::Query => Credential::Query(param, token),
+                Place::Cookie => Credential::Header("Cookie".into(), format!("{param}={token}")),
+                Place::Header => Credential::Header(param, token),
+            })
+        }
+        Tamper::Case(casing) => {
+            let Credential::Header(name, value) = credential else { bail!("header casing needs a credential in a header") };
+            let name = match casing {
+                Casing::Lower => name.to_lowercase(),
+                Casing::Upper => name.to_uppercase(),
+            };
+            Some(Credential::Header(name, value))
+        }
+    })
+}
+
+/// `token`, altered as `forgery` says.
+fn forge(forgery: Forgery, token: &str, jwt_key: Option<&[u8]>) -> Result<String> {
+    let segments: Vec<&str> = token.split('.').collect();
+    let decode = |segment: &str| base64url_decode(segment).and_then(|raw| serde_json::from_slice::<Map<String, Value>>(&raw).ok());
+    let (&[header, payload, signature], Some(mut head), Some(mut claims)) =
+        (segments.as_slice(), segments.first().and_then(|s| decode(s)), segments.get(1).and_then(|s| decode(s)))
+    else {
+        bail!("the bearer token is not a JWT, but [auth] sets jwt = true");
+    };
+    let encode = |m: &Map<String, Value>| base64url(Value::Object(m.clone()).to_string().as_bytes());
+    Ok(match forgery {
+        Forgery::Expired => {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            claims.insert("exp".into(), (now - 3_600).into());
+            for claim in ["iat", "nbf"] {
+                if claims.contains_key(claim) {
+                    claims.insert(claim.into(), (now - 7_200).into());
+                }
+            }
+            let Some(key) = jwt_key else { bail!("an expired token needs [auth] jwt_key to be re-signed") };
+            let signed = format!("{header}.{}", encode(&claims));
+            let alg = head.get("alg").and_then(Value::as_str).unwrap_or_default();
+            format!("{signed}.{}", base64url(&hmac(alg, key, signed.as_bytes())?))
+        }
+        Forgery::Malformed => format!("{header}.{}.{signature}", base64url(b"{\"sub\":")),
+        Forgery::AlgNone => {
+            head.insert("alg".into(), "none".into());
+            format!("{}.{payload}.", encode(&head))
+        }
+        Forgery::WrongKey => {
+            head.insert("alg".into(), "HS256".into());
+            let signed = format!("{}.{payload}", encode(&head));
+            format!("{signed}.{}", base64url(&hmac("HS256", WRONG_KEY, signed.as_bytes())?))
+        }
+    })
+}
+
+/// The JWS signature of `signed` under `key` for an HMAC `alg`.
+fn hmac(alg: &str, key: &[u8], signed: &[u8]) -> Result<Vec<u8>> {
+    fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], signed: &[u8]) -> Vec<u8> {
+        let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+        mac.update(signed);
+        mac.finalize().into_bytes().to_vec()
+    }
+    Ok(match alg {
+        "HS256" => mac::<Hmac<Sha256>>(key, signed),
+        "HS384" => mac::<Hmac<Sha384>>(key, signed),
+        "HS512" => mac::<Hmac<Sha512>>(key, signed),
+        _ => bail!("jwt_key re-signs HS256, HS384 and HS512 tokens, not {alg:?}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oracle::Oracles;
+    use crate::plan;
+    use crate::runner::Outcome;
+    use crate::testutil;
+    use crate::transport::REDACTED;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// `[auth]` with a bearer `token`, a second identity and `extra` keys.
+    fn auth(token: &str, extra: &str) -> AuthConfig {
+        std::env::set_var("FUZZKIT_TEST_BYPASS_TOKEN", token);
+        std::env::set_var("FUZZKIT_TEST_BYPASS_OTHER", "other-token");
+        std::env::set_var("FUZZKIT_TEST_BYPASS_JWT_KEY", "sandbox-key");
+        toml::from_str(&format!(
+            r#"
+            type = "bearer"
+            token = {{ env = "FUZZKIT_TEST_BYPASS_TOKEN" }}
+            {extra}
+            [[identities]]
+            name = "other"
+            type = "bearer"
+            token = {{ env = "FUZZKIT_TEST_BYPASS_OTHER" }}
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn jwt(alg: &str, claims: Value, key: &[u8]) -> String {
+        let head = serde_json::json!({"alg": alg, "typ": "JWT"});
+        let signed = format!("{}.{}", base64url(head.to_string().as_bytes()), base64url(claims.to_string().as_bytes()));
+        format!("{signed}.{}", base64url(&hmac(alg, key, signed.as_bytes()).unwrap()))
+    }
+
+    fn targets(auth: &AuthConfig, http_version: HttpVersion) -> Vec<String> {
+        let base = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        cases(auth, http_version, &base).iter().map(|c| format!("{} {}", c.operator, c.target)).collect()
+    }
+
+    #[test]
+    fn expired_tokens_are_sent_only_when_they_can_be_re_signed() {
+        assert!(!targets(&auth("t", "jwt = true"), HttpVersion::Auto).contains(&"auth.jwt expired".to_string()));
+        let all = targets(&auth("t", "jwt = true\njwt_key = { env = \"FUZZKIT_TEST_BYPASS_JWT_KEY\" }"), HttpVersion::Auto);
+        assert_eq!(
+            all,
+            [
+                "auth.drop ",
+                "auth.jwt expired",
+                "auth.jwt malformed",
+                "auth.jwt alg-none",
+                "auth.jwt wrong-key",
+                "auth.identity other",
+                "auth.location query",
+                "auth.location cookie",
+                "auth.case lower",
+                "auth.case upper",
+            ]
+        );
+        // HTTP/2 header names are lower case on the wire.
+        assert!(!targets(&auth("t", ""), HttpVersion::Http2).iter().any(|t| t.starts_with("auth.case")));
+    }
+
+    #[test]
+    fn expired_token_keeps_a_valid_signature() {
+        let key = b"sandbox-key";
+        let exp = OffsetDateTime::now_utc().unix_timestamp() + 3_600;
+        for alg in ["HS256", "HS384", "HS512"] {
+            let token = jwt(alg, serde_json::json!({"sub": "taxpayer", "exp": exp, "iat": exp - 7_200}), key);
+            let credential = Credential::Header("Authorization".into(), format!("Bearer {token}"));
+
+            let Some(Credential::Header(_, value)) = apply(&Tamper::Jwt(Forgery::Expired), Some(credential), Some(key)).unwrap() else {
+                panic!("no credential");
+            };
+
+            let forged = value.strip_prefix("Bearer ").unwrap();
+            let (signed, signature) = forged.rsplit_once('.').unwrap();
+            assert_eq!(signature, base64url(&hmac(alg, key, signed.as_bytes()).unwrap()), "{alg}");
+            let claims: Value = serde_json::from_slice(&base64url_decode(signed.split('.').nth(1).unwrap()).unwrap()).unwrap();
+            assert!(claims["exp"].as_i64().unwrap() < OffsetDateTime::now_utc().unix_timestamp(), "{alg}");
+            assert_eq!(claims["sub"], "taxpayer");
+        }
+        let rs256 = format!("{}.{}.c2ln", base64url(b"{\"alg\":\"RS256\"}"), base64url(b"{\"exp\":1}"));
+        let credential = Credential::Header("Authorization".into(), format!("Bearer {rs256}"));
+        assert!(apply(&Tamper::Jwt(Forgery::Expired), Some(credential), Some(key)).is_err());
+    }
+
+    /// A server that answers `status` and returns the raw request head it read.
+    async fn raw_server(status: u16) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                socket.read_exact(&mut byte).await.unwrap();
+                head.push(byte[0]);
+            }
+            let head = String::from_utf8(head).unwrap();
+            let len: usize = head
+                .lines()
+                .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                .unwrap_or(0);
+            socket.read_exact(&mut vec![0; len]).await.unwrap();
+            let reply = format!("HTTP/1.1 {status} Whatever\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            socket.write_all(reply.as_bytes()).await.unwrap();
+            head
+        });
+        (url, task)
+    }
+
+    #[tokio::test]
+    async fn header_case_sends_the_valid_credential_under_a_re_cased_name() {
+        for (casing, name, status, fired) in [(Casing::Upper, "AUTHORIZATION", 401, Some("auth.case")), (Casing::Lower, "authorization", 200, None)] {
+            let (url, server) = raw_server(status).await;
+            let mut p = testutil::profile(&url);
+            p.auth = Some(auth("s3cret", ""));
+            let case = cases(p.auth.as_ref().unwrap(), p.http_version, &plan::baseline(&p.endpoints[0]))
+                .into_iter()
+                .find(|c| c.request.auth == Tamper::Case(casing))
+                .unwrap();
+
+            let resp = testutil::transport(&p).send(&case.request).await.unwrap();
+            let head = server.await.unwrap();
+
+            assert!(head.contains(&format!("\r\n{name}: Bearer s3cret\r\n")), "{head}");
+            assert_eq!(head.matches("s3cret").count(), 1, "{head}");
+            assert!(head.contains("\r\nx-env: sandbox\r\n"), "{head}");
+            assert!(resp.sent.headers.contains(&(name.to_string(), REDACTED.to_string())), "{:?}", resp.sent.headers);
+            let mut o = Outcome { index: 0, case, result: Ok(resp), signals: Vec::new() };
+            Oracles::new(&p.oracles, &[]).unwrap().judge(&mut o);
+            assert_eq!(o.signals.iter().map(|s| s.oracle.as_str()).next(), fired);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+//! `import har` / `import curl`: requests captured from the sandbox portal
+//! become `[[endpoints.captures]]` on the profile endpoint whose method and
+//! path template they match, and are replayed and mutated like seed bodies.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use toml_edit::{value, ArrayOfTables, DocumentMut, InlineTable, Item, Table};
+use url::Url;
+
+use crate::auth::base64;
+use crate::bypass::Tamper;
+use crate::endpoint::Endpoint;
+use crate::transport::{Body, Request};
+
+/// One captured request. The method is the endpoint's.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capture {
+    /// Absolute URL as captured; its host must be allowlisted
+    pub url: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+impl Capture {
+    /// The capture as a `[[endpoints.captures]]` table.
+    fn table(&self) -> Table {
+        let mut table = Table::new();
+        table.insert("url", value(&self.url));
+        if !self.headers.is_empty() {
+            table.insert("headers", value(self.headers.iter().collect::<InlineTable>()));
+        }
+        if let Some(body) = &self.body {
+            table.insert("body", value(body));
+        }
+        table
+    }
+
+    /// The path of `url` below `base`'s path, if it is below it.
+    fn relative_path(url: &Url, base: &Url) -> Option<String> {
+        let prefix = base.path().trim_end_matches('/');
+        let rest = url.path().strip_prefix(prefix)?;
+        match rest {
+            "" => Some("/".into()),
+            r if r.starts_with('/') => Some(r.into()),
+            _ => None,
+        }
+    }
+
+    /// The request to send, relative to `base`.
+    pub fn request(&self, method: &str, base: &Url) -> Result<Request> {
+        let url = Url::parse(&self.url).with_context(|| format!("invalid capture URL: {}", self.url))?;
+        let path = Self::relative_path(&url, base)
+            .with_context(|| format!("capture {} is outside base_url {base}", self.url))?;
+        Ok(Request {
+            method: method.to_string(),
+            path,
+            query: url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect(),
+            headers: self.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            body: self.body.as_ref().map(|b| Body::Bytes(b.clone().into_bytes())),
+            auth: Tamper::None,
+            origin: None,
+            signed: false,
+        })
+    }
+
+    /// The body as JSON, when it is JSON.
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(self.body.as_deref()?).ok()
+    }
+}
+
+/// A request read from a HAR file or a curl command line.
+#[derive(Debug)]
+pub struct Entry {
+    pub method: String,
+    pub url: Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+/// Set by the client or the transport, never replayed from a capture.
+const TRANSPORT_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "te",
+    "upgrade",
+    "accept-encoding",
+];
+
+/// Cookies and credentials, dropped unless `--keep-credentials` is given.
+fn is_credential(name: &str) -> bool {
+    let name = name.to_ascii_lowercase().replace('_', "-");
+    matches!(name.as_str(), "cookie" | "authorization" | "proxy-authorization")
+        || ["auth", "token", "api-key", "apikey", "session", "csrf", "xsrf"].iter().any(|s| name.contains(s))
+}
+
+/// Query parameters that carry credentials: those named like credential
+/// headers (`access_token`, `api_key`), plus signatures and secrets.
+fn is_credential_param(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase().replace('_', "-");
+    is_credential(name)
+        || matches!(lower.as_str(), "key" | "sig" | "signature" | "password" | "passwd" | "code")
+        || lower.ends_with("-signature")
+        || lower.contains("secret")
+}
+
+impl Entry {
+    /// The capture to store, without transport headers (or pseudo-headers
+    /// such as HTTP/2's `:authority`) and, unless `keep_credentials`,
+    /// without cookies, auth headers and credential query parameters.
+    fn capture(self, keep_credentials: bool) -> Capture {
+        let mut headers = BTreeMap::new();
+        for (name, value) in self.headers {
+            let lower = name.to_ascii_lowercase();
+            if name.starts_with(':') || TRANSPORT_HEADERS.contains(&lower.as_str()) {
+                continue;
+            }
+            if is_credential(&name) && !keep_credentials {
+                tracing::warn!(target: "capture", url = %self.url, "dropping {name} header; pass --keep-credentials to keep it");
+                continue;
+            }
+            headers.insert(name, value);
+        }
+        let mut url = self.url;
+        if !keep_credentials && url.query_pairs().any(|(k, _)| is_credential_param(&k)) {
+            let (dropped, kept): (Vec<_>, Vec<_>) = url.query_pairs().into_owned().partition(|(k, _)| is_credential_param(k));
+            for (name, _) in &dropped {
+                tracing::warn!(target: "capture", url = %url.path(), "dropping {name} query parameter; pass --keep-credentials to keep it");
+            }
+            if kept.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(kept);
+            }
+        }
+        Capture { url: url.to_string(), headers, body: self.body }
+    }
+}
+
+/// Read a file, or stdin for `-`.
+fn read(path: &Path) -> Result<String> {
+    if path.as_os_str() == "-" {
+        let mut raw = String::new();
+        std::io::stdin().read_to_string(&mut raw).context("failed to read stdin")?;
+        return Ok(raw);
+    }
+    fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Every request of a HAR 1.2 log.
+pub fn har(path: &Path) -> Result<Vec<Entry>> {
+    har_entries(&read(path)?)
+}
+
+fn har_entries(raw: &str) -> Result<Vec<Entry>> {
+    let doc: Value = serde_json::from_str(raw).context("HAR file is not JSON")?;
+    let entries = doc["log"]["entries"].as_array().context("HAR file has no log.entries")?;
+    let mut out = Vec::new();
+    for (i, e) in entries.iter().enumerate() {
+        let req = &e["request"];
+        let (Some(method), Some(url)) = (req["method"].as_str(), req["url"].as_str()) else {
+            bail!("HAR entry {i} has no request method or URL");
+        };
+        let url = Url::parse(url).with_context(|| format!("HAR entry {i}: invalid URL {url}"))?;
+        let headers = req["headers"]
+            .as_array()
+            .map(|hs| {
+                hs.iter()
+                    .filter_map(|h| Some((h["name"].as_str()?.to_string(), h["value"].as_str()?.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let post = &req["postData"];
+        // Form posts may come as `params` only.
+        let body = post["text"].as_str().map(String::from).or_else(|| {
+            let params = post["params"].as_array()?;
+            let mut form = url::form_urlencoded::Serializer::new(String::new());
+            for p in params {
+                form.append_pair(p["name"].as_str()?, p["value"].as_str().unwrap_or_default());
+            }
+            Some(form.finish())
+        });
+        out.push(Entry { method: method.to_uppercase(), url, headers, body: body.filter(|b| !b.is_empty()) });
+    }
+    Ok(out)
+}
+
+/// Split shell input into commands, and each command into words: single
+/// quotes, double quotes, bash `$'...'` strings, backslash escapes and line
+/// continuations. Commands end at an unquoted newline, `;`, `&`, `|`, `&&` or `||`.
+fn commands(input: &str) -> Result<Vec<Vec<String>>> {
+    let mut commands = Vec::new();
+    let mut out = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\r' => out.extend(word.take()),
+            // `&&` and `||` end the command like `&` and `|` do.
+            '\n' | ';' | '&' | '|' => {
+                if c == '&' || c == '|' {
+                    chars.next_if_eq(&c);
+                }
+                out.extend(word.take());
+                commands.push(std::mem::take(&mut out));
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(next) => word.get_or_insert_with(String::new).push(next),
+                None => {}
+            },
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated ' in curl command"),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => w.extend(chars.next()),
+                        Some('\\') if chars.peek() == Some(&'\n') => {
+                            chars.next();
+                        }
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated \" in curl command"),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => w.push('\n'),
+                            Some('t') => w.push('\t'),
+                            Some('r') => w.push('\r'),
+                            Some('0') => w.push('\0'),
+                            Some('u') | Some('x') => {
+                                let hex: String = std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_hexdigit())).take(4).collect();
+                                w.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+                            }
+                            Some(c) => w.push(c),
+                            None => bail!("unterminated $' in curl command"),
+                        },
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated $' in curl command"),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    out.extend(word);
+    commands.push(out);
+    commands.retain(|c| !c.is_empty());
+    Ok(commands)
+}
+
+/// curl options that take a value we don't use.
+const IGNORED_WITH_VALUE: &[&str] = &[
+    "-o", "--output", "-m", "--max-time", "--connect-timeout", "-x", "--proxy", "--cacert", "--cert", "--key", "-w",
+    "--write-out", "--retry", "-c", "--cookie-jar", "--resolve",
+];
+
+/// Every request in pasted `curl` command lines, one or more per input.
+pub fn curl(path: &Path) -> Result<Vec<Entry>> {
+    let out = curl_commands(&read(path)?)?;
+    if out.is_empty() {
+        bail!("no curl command in {}", path.display());
+    }
+    Ok(out)
+}
+
+/// The requests of the commands that run `curl`; other commands, and `curl`
+/// as an argument (`-A curl`), are ignored.
+fn curl_commands(input: &str) -> Result<Vec<Entry>> {
+    let runs_curl = |cmd: &&Vec<String>| cmd[0] == "curl" || cmd[0].ends_with("/curl");
+    let mut out = Vec::new();
+    for (i, cmd) in commands(input)?.iter().filter(runs_curl).enumerate() {
+        out.push(curl_command(&cmd[1..]).with_context(|| format!("curl command {}", i + 1))?);
+    }
+    Ok(out)
+}
+
+fn curl_command(args: &[String]) -> Result<Entry> {
+    let (mut method, mut url, mut headers, mut data, mut get) = (None, None, Vec::new(), Vec::<String>::new(), false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // `-XPOST` and `--request=POST` spell the same option.
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f, Some(v.to_string())),
+            _ if arg.len() > 2 && !arg.starts_with("--") && matches!(arg.as_bytes()[..2], [b'-', b'X' | b'H' | b'd' | b'b' | b'u' | b'A']) => {
+                (&arg[..2], Some(arg[2..].to_string()))
+            }
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || inline.clone().or_else(|| args.next().cloned()).with_context(|| format!("{flag} needs a value"));
+        match flag {
+            "-X" | "--request" => method = Some(value()?.to_uppercase()),
+            "-H" | "--header" => {
+                let h = value()?;
+                let (name, v) = h.split_once(':').with_context(|| format!("malformed header: {h}"))?;
+                headers.push((name.trim().to_string(), v.trim().to_string()));
+            }
+            "-d" | "--data" | "--data-raw" | "--data-binary" | "--data-ascii" | "--data-urlencode" | "--json" => {
+                if flag == "--json" {
+                    headers.push(("Content-Type".into(), "application/json".into()));
+                }
+                data.push(value()?);
+            }
+            "-b" | "--cookie" => headers.push(("Cookie".into(), value()?)),
+            "-u" | "--user" => headers.push(("Authorization".into(), format!("Basic {}", base64(&value()?)))),
+            "-A" | "--user-agent" => headers.push(("User-Agent".into(), value()?)),
+            "-e" | "--referer" => headers.push(("Referer".into(), value()?)),
+            "-G" | "--get" => get = true,
+            "--url" => url = Some(value()?),
+            f if IGNORED_WITH_VALUE.contains(&f) => {
+                value()?;
+            }
+            f if f.starts_with('-') => {}
+            _ => url = Some(arg.clone()),
+        }
+    }
+    let url = url.context("no URL")?;
+    let mut url = Url::parse(&url).with_context(|| format!("invalid URL: {url}"))?;
+    let mut body = (!data.is_empty()).then(|| data.join("&"));
+    if get {
+        if let Some(q) = body.take() {
+            let query = url.query().map_or(q.clone(), |old| format!("{old}&{q}"));
+            url.set_query(Some(&query));
+        }
+    }
+    let method = method.unwrap_or_else(|| if body.is_some() { "POST" } else { "GET" }.into());
+    Ok(Entry { method, url, headers, body })
+}
+
+/// Attach each entry to the endpoint it matches and render the profile
+/// (`raw`, whose parsed endpoints are `endpoints`) with the new captures.
+/// Only the captures are inserted; the rest of the profile, comments
+/// included, is kept as written. Entries that match no endpoint are
+/// skipped; hosts are left for the guardrails to check.
+pub fn attach(raw: &str, base_url: &str, endpoints: &[Endpoint], entries: Vec<Entry>, keep_credentials: bool) -> Result<String> {
+    let base = Url::parse(base_url).with_context(|| format!("invalid base_url: {base_url}"))?;
+    let mut doc: DocumentMut = raw.parse().context("invalid TOML in profile")?;
+    let list = doc
+        .get_mut("endpoints")
+        .and_then(Item::as_array_of_tables_mut)
+        .context("profile has no [[endpoints]]")?;
+    let mut attached = 0;
+    for entry in entries {
+        let path = Capture::relative_path(&entry.url, &base);
+        let found = path.as_deref().and_then(|path| {
+            endpoints.iter().position(|ep| ep.matches(&entry.method, path))
+        });
+        let Some(i) = found else {
+            tracing::warn!(target: "capture", "skipping {} {}: no matching endpoint", entry.method, entry.url);
+            continue;
+        };
+        if entry.url.host_str() != base.host_str() {
+            tracing::warn!(target: "capture", url = %entry.url, "not on base_url's host; guardrails will refuse it unless allowlisted");
+        }
+        let capture = entry.capture(keep_credentials).table();
+        let table = list.get_mut(i).context("[[endpoints]] entry is missing")?;
+        match table.entry("captures").or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new())) {
+            Item::ArrayOfTables(captures) => captures.push(capture),
+            Item::Value(toml_edit::Value::Array(captures)) => captures.push(capture.into_inline_table()),
+            _ => bail!("endpoints.captures of {} is not an array", endpoints[i].path),
+        }
+        attached += 1;
+    }
+    if attached == 0 {
+        bail!("no captured request matches a profile endpoint");
+    }
+    tracing::info!(target: "capture", "attached {attached} captures");
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    const HAR: &str = r#"{"log": {"version": "1.2", "entries": [
+        {"request": {
+            "method": "post",
+            "url": "http://127.0.0.1:9/v1/returns?access_token=t0k&period=2024-01&X-Amz-Signature=abc",
+            "headers": [
+                {"name": ":authority", "value": "127.0.0.1:9"},
+                {"name": "Host", "value": "127.0.0.1:9"},
+                {"name": "Content-Type", "value": "application/json"},
+                {"name": "Authorization", "value": "Bearer t0k"},
+                {"name": "X-Api-Key", "value": "k3y"},
+                {"name": "X-Request-Id", "value": "r1"}
+            ],
+            "postData": {"mimeType": "application/json", "text": "{\"pin\":\"A1\",\"amount\":-0}"}
+        }},
+        {"request": {
+            "method": "POST",
+            "url": "http://127.0.0.1:9/v1/returns",
+            "headers": [],
+            "postData": {"mimeType": "application/x-www-form-urlencoded", "params": [
+                {"name": "pin", "value": "A 1"},
+                {"name": "period", "value": "2024-01"}
+            ]}
+        }}
+    ]}}"#;
+
+    const CURL: &str = r#"
+$ echo ready && curl 'http://127.0.0.1:9/v1/returns?api_key=k3y' \
+    -H 'Content-Type: application/json' -A curl \
+    --data-raw '{"pin":"A1","note":"pipe | and; semicolon"}'
+/usr/bin/curl -XPOST -u user:pass http://127.0.0.1:9/v1/returns?sig=s1g&&curl --url http://127.0.0.1:9/v1/returns -G -d period=2024-01
+"#;
+
+    fn header<'a>(capture: &'a Capture, name: &str) -> Option<&'a str> {
+        capture.headers.get(name).map(String::as_str)
+    }
+
+    #[test]
+    fn har_entries_keep_method_url_headers_and_body() {
+        let entries = har_entries(HAR).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].method, "POST");
+        assert_eq!(entries[0].body.as_deref(), Some(r#"{"pin":"A1","amount":-0}"#));
+        assert_eq!(entries[1].body.as_deref(), Some("pin=A+1&period=2024-01"));
+    }
+
+    #[test]
+    fn curl_commands_split_only_where_a_command_starts() {
+        let entries = curl_commands(CURL).unwrap();
+
+        assert_eq!(entries.len(), 3, "{entries:?}");
+        assert_eq!(entries[0].method, "POST");
+        assert_eq!(entries[0].body.as_deref(), Some(r#"{"pin":"A1","note":"pipe | and; semicolon"}"#));
+        assert!(entries[0].headers.contains(&("User-Agent".into(), "curl".into())));
+        assert_eq!(entries[1].url.as_str(), "http://127.0.0.1:9/v1/returns?sig=s1g");
+        assert_eq!(entries[2].method, "GET");
+        assert_eq!(entries[2].url.query(), Some("period=2024-01"));
+    }
+
+    #[test]
+    fn credentials_are_stripped_from_headers_and_query() {
+        let mut entries = har_entries(HAR).unwrap();
+        let capture = entries.remove(0).capture(false);
+
+        assert_eq!(capture.url, "http://127.0.0.1:9/v1/returns?period=2024-01");
+        assert_eq!(capture.headers.keys().collect::<Vec<_>>(), ["Content-Type", "X-Request-Id"]);
+
+        let curl = curl_commands(CURL).unwrap();
+        let captures: Vec<Capture> = curl.into_iter().map(|e| e.capture(false)).collect();
+        assert_eq!(captures[0].url, "http://127.0.0.1:9/v1/returns");
+        assert_eq!(header(&captures[1], "Authorization"), None);
+        assert_eq!(captures[1].url, "http://127.0.0.1:9/v1/returns");
+
+        let kept = har_entries(HAR).unwrap().remove(0).capture(true);
+        assert!(kept.url.contains("access_token=t0k"), "{}", kept.url);
+        assert_eq!(header(&kept, "Authorization"), Some("Bearer t0k"));
+        assert_eq!(header(&kept, "Host"), None);
+    }
+
+    #[test]
+    fn attach_inserts_captures_and_keeps_the_rest_of_the_profile() {
+        let p = testutil::profile("http://127.0.0.1:9");
+        let raw = "# the sandbox profile\nname = \"t\" # inline note\n\n[[endpoints]]\n# returns\npath = \"/v1/returns\"\nmethod = \"POST\"\n";
+
+        let out = attach(raw, &p.base_url, &p.endpoints, har_entries(HAR).unwrap(), false).unwrap();
+
+        assert!(out.starts_with(raw), "{out}");
+        let doc: toml::Table = toml::from_str(&out).unwrap();
+        let captures: Vec<Capture> = doc["endpoints"][0]["captures"].clone().try_into().unwrap();
+        assert_eq!(captures.len(), 2);
+        assert_eq!(captures[0].body.as_deref(), Some(r#"{"pin":"A1","amount":-0}"#));
+        assert_eq!(header(&captures[0], "X-Request-Id"), Some("r1"));
+    }
+}
@@ -0,0 +1,281 @@
+//! `[corpus]`: a directory of seed and interesting bodies that grows across
+//! runs. A sent case is kept when its response shows something its endpoint
+//! has not produced before (a status, a header set, an error-message
+//! template or a body shape), and later runs mutate the kept bodies as well
+//! as `seed_body`. Entries are plain JSON files named by content hash, so a
+//! corpus can be committed and shared.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::bucket;
+use crate::endpoint::{self, Endpoint};
+use crate::runner::Outcome;
+use crate::transport::{Body, Response};
+
+/// Larger bodies (oversized cases) are never kept.
+const MAX_ENTRY_BYTES: usize = 64 * 1024;
+
+/// `[corpus]`
+#[derive(Debug, Deserialize)]
+pub struct CorpusConfig {
+    /// Directory of entries, created on first use
+    pub dir: PathBuf,
+}
+
+/// One kept body. The ID is the file name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(skip)]
+    pub id: String,
+    /// `METHOD /path/{template}` of the endpoint it was sent to
+    pub endpoint: String,
+    pub operator: String,
+    pub target: String,
+    /// What was new about its response when it was kept
+    pub features: Vec<String>,
+    pub body: Value,
+}
+
+#[derive(Debug, Default)]
+pub struct Corpus {
+    dir: Option<PathBuf>,
+    entries: Vec<Entry>,
+    /// Entries present when the corpus was opened: the ones cases are planned from
+    planned: usize,
+    seen: HashMap<String, HashSet<String>>,
+}
+
+impl Corpus {
+    /// Load every entry in `dir` (creating it if needed), in ID order.
+    pub fn open(cfg: &CorpusConfig) -> Result<Self> {
+        let dir = &cfg.dir;
+        fs::create_dir_all(dir).with_context(|| format!("failed to create corpus dir {}", dir.display()))?;
+        let mut entries = Vec::new();
+        for file in fs::read_dir(dir).with_context(|| format!("failed to read corpus dir {}", dir.display()))? {
+            let path = file?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let raw = fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            let mut entry: Entry =
+                serde_json::from_str(&raw).with_context(|| format!("invalid corpus entry {}", path.display()))?;
+            entry.id = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut corpus = Self { dir: Some(dir.clone()), planned: entries.len(), ..Self::default() };
+        for e in &entries {
+            corpus.seen.entry(e.endpoint.clone()).or_default().extend(e.features.iter().cloned());
+        }
+        corpus.entries = entries;
+        Ok(corpus)
+    }
+
+    /// The profile's corpus, or an empty one that keeps nothing.
+    pub fn for_profile(cfg: Option<&CorpusConfig>) -> Result<Self> {
+        cfg.map_or_else(|| Ok(Self::default()), Self::open)
+    }
+
+    /// Plan from exactly the entries `ids`, as a replayed session did.
+    pub fn only(mut self, ids: &[String]) -> Result<Self> {
+        if let Some(missing) = ids.iter().find(|id| !self.entries.iter().any(|e| &e.id == *id)) {
+            bail!("corpus entry {missing} used by the original session is missing");
+        }
+        self.entries.retain(|e| ids.contains(&e.id));
+        self.planned = self.entries.len();
+        Ok(self)
+    }
+
+    /// IDs of the entries cases are planned from.
+    pub fn planned(&self) -> Vec<String> {
+        self.entries[..self.planned].iter().map(|e| e.id.clone()).collect()
+    }
+
+    /// Bodies planned for `ep`, in ID order.
+    pub fn bodies(&self, ep: &Endpoint) -> Vec<&Value> {
+        let key = ep.key();
+        self.entries[..self.planned].iter().filter(|e| e.endpoint == key).map(|e| &e.body).collect()
+    }
+
+    /// Keep the outcome's body if its response is novel for its endpoint.
+    /// Only JSON bodies are kept, since only those can be mutated again.
+    pub fn consider(&mut self, endpoints: &[Endpoint], o: &Outcome) -> Result<bool> {
+        let Some(dir) = &self.dir else { return Ok(false) };
+        let (Ok(resp), Some(Body::Bytes(raw))) = (&o.result, &o.case.request.body) else { return Ok(false) };
+        if raw.len() > MAX_ENTRY_BYTES {
+            return Ok(false);
+        }
+        let ep = endpoint::of(endpoints, &o.case.request.method, &o.case.request.path);
+        let (Some(ep), Ok(body)) = (ep, serde_json::from_slice::<Value>(raw)) else {
+            return Ok(false);
+        };
+        let endpoint = ep.key();
+        let seen = self.seen.entry(endpoint.clone()).or_default();
+        let novel: Vec<String> = features(resp).into_iter().filter(|f| !seen.contains(f)).collect();
+        if novel.is_empty() {
+            return Ok(false);
+        }
+        seen.extend(novel.iter().cloned());
+        let mut hasher = Sha256::new();
+        hasher.update(endpoint.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(body.to_string().as_bytes());
+        let id = hex::encode(&hasher.finalize()[..8]);
+        let entry = Entry {
+            id: id.clone(),
+            endpoint,
+            operator: o.case.operator.clone(),
+            target: o.case.target.clone(),
+            features: novel,
+            body,
+        };
+        let path = dir.join(format!("{id}.json"));
+        if path.exists() {
+            return Ok(false);
+        }
+        fs::write(&path, serde_json::to_string_pretty(&entry)? + "\n")
+            .with_context(|| format!("failed to write corpus entry {}", path.display()))?;
+        self.entries.push(entry);
+        Ok(true)
+    }
+
+    pub fn dir(&self) -> Option<&PathBuf> {
+        self.dir.as_ref()
+    }
+}
+
+/// What a response looks like, coarsely enough that two responses to the
+/// same bug share every feature.
+fn features(resp: &Response) -> Vec<String> {
+    let mut names: Vec<String> = resp.headers.iter().map(|(k, _)| k.to_ascii_lowercase()).collect();
+    names.sort();
+    names.dedup();
+    let json = serde_json::from_slice::<Value>(&resp.body).ok();
+    let mut out = vec![format!("status:{}", resp.status), format!("headers:{}", names.join(","))];
+    if let Some(t) = bucket::error_template(resp) {
+        out.push(format!("error:{t}"));
+    }
+    let shape = match &json {
+        Some(v) => hex::encode(&Sha256::digest(shape(v).as_bytes())[..6]),
+        None if resp.body.is_empty() => "empty".into(),
+        None => {
+            let ct = resp.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case("content-type"));
+            ct.map_or("text".into(), |(_, v)| v.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+        }
+    };
+    out.push(format!("shape:{shape}"));
+    out
+}
+
+/// Keys and value types, with array items merged, e.g. `{id:num,tags:[str]}`.
+fn shape(v: &Value) -> String {
+    match v {
+        Value::Null => "null".into(),
+        Value::Bool(_) => "bool".into(),
+        Value::Number(_) => "num".into(),
+        Value::String(_) => "str".into(),
+        Value::Array(items) => {
+            let mut kinds: Vec<String> = items.iter().map(shape).collect();
+            kinds.sort();
+            kinds.dedup();
+            format!("[{}]", kinds.join("|"))
+        }
+        Value::Object(m) => {
+            let members: Vec<String> = m.iter().map(|(k, v)| format!("{k}:{}", shape(v))).collect();
+            format!("{{{}}}", members.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan;
+    use crate::testutil;
+    use std::time::Duration;
+
+    /// A corpus in a fresh directory of its own.
+    fn scratch(name: &str) -> CorpusConfig {
+        let dir = std::env::temp_dir().join(format!("fuzzkit-corpus-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        CorpusConfig { dir }
+    }
+
+    /// `body` sent to the test endpoint, answered with `status`, `headers` and `resp`.
+    fn outcome(body: &str, status: u16, headers: &[&str], resp: &str) -> Outcome {
+        let mut case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        case.request.body = Some(Body::Bytes(body.into()));
+        let resp = Response {
+            status,
+            headers: headers.iter().map(|k| (k.to_string(), "x".to_string())).collect(),
+            body: resp.into(),
+            elapsed: Duration::from_millis(5),
+            sent: case.request.clone(),
+        };
+        Outcome { index: 0, case, result: Ok(resp), signals: Vec::new() }
+    }
+
+    #[test]
+    fn keeps_bodies_whose_response_shows_a_new_feature() {
+        let endpoints = testutil::profile("http://127.0.0.1:1").endpoints;
+        let mut corpus = Corpus::open(&scratch("novelty")).unwrap();
+        let mut keep = |o: Outcome| {
+            let kept = corpus.consider(&endpoints, &o).unwrap();
+            kept.then(|| corpus.entries.last().unwrap().features.clone())
+        };
+        let ct = &["Content-Type"];
+
+        let first = keep(outcome(r#"{"amount":1}"#, 200, ct, r#"{"id":1}"#)).unwrap();
+        assert_eq!(first.len(), 3, "{first:?}");
+        // Same status, header names and shape: nothing new.
+        assert_eq!(keep(outcome(r#"{"amount":2}"#, 200, ct, r#"{"id":2}"#)), None);
+
+        assert_eq!(keep(outcome(r#"{"amount":3}"#, 201, ct, r#"{"id":3}"#)).unwrap(), ["status:201"]);
+        assert_eq!(
+            keep(outcome(r#"{"amount":4}"#, 200, &["content-type", "X-Trace"], r#"{"id":4}"#)).unwrap(),
+            ["headers:content-type,x-trace"]
+        );
+        let shaped = keep(outcome(r#"{"amount":5}"#, 200, ct, r#"{"id":5,"tags":["a"]}"#)).unwrap();
+        assert_eq!(shaped.len(), 1);
+        assert!(shaped[0].starts_with("shape:"), "{shaped:?}");
+
+        let error = r#"{"message":"field 'amount' must be at most 100 (got 150)"}"#;
+        let invalid = keep(outcome(r#"{"amount":6}"#, 422, ct, error)).unwrap();
+        assert!(invalid.contains(&"error:field '*' must be at most # (got #)".to_string()), "{invalid:?}");
+        // Quoted names and numbers are masked, so this is the same template.
+        let same = r#"{"message":"field 'period' must be at most 12 (got 13)"}"#;
+        assert_eq!(keep(outcome(r#"{"amount":7}"#, 422, ct, same)), None);
+        let other = r#"{"message":"field 'period' is required"}"#;
+        assert_eq!(keep(outcome(r#"{"amount":8}"#, 422, ct, other)).unwrap(), ["error:field '*' is required"]);
+    }
+
+    #[test]
+    fn entries_are_named_by_content_hash_and_reload_with_what_they_saw() {
+        let cfg = scratch("reload");
+        let endpoints = testutil::profile("http://127.0.0.1:1").endpoints;
+        let mut corpus = Corpus::open(&cfg).unwrap();
+        assert!(corpus.consider(&endpoints, &outcome(r#"{ "amount": 1 }"#, 200, &[], "")).unwrap());
+
+        let body = serde_json::json!({"amount": 1});
+        let id = hex::encode(&Sha256::digest(format!("POST /v1/returns\n{body}").as_bytes())[..8]);
+        let path = cfg.dir.join(format!("{id}.json"));
+        let saved: Entry = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.body, body);
+
+        let mut reopened = Corpus::open(&cfg).unwrap();
+        assert_eq!(reopened.planned(), [id]);
+        assert_eq!(reopened.bodies(&endpoints[0]), [&body]);
+        // What the entry saw is not new to the reloaded corpus...
+        assert!(!reopened.consider(&endpoints, &outcome(r#"{"amount":2}"#, 200, &[], "")).unwrap());
+        // ...and the same body is not stored twice, however it was spelled.
+        assert!(!reopened.consider(&endpoints, &outcome(r#"{"amount":1}"#, 500, &[], "")).unwrap());
+        assert_eq!(fs::read_dir(&cfg.dir).unwrap().count(), 1);
+        fs::remove_dir_all(&cfg.dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! `[[endpoints]]`: the routes a profile fuzzes, each with its own method,
+//! seed body, path/query parameters and captured requests.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::capture::Capture;
+use crate::schema::{Generation, Schema};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamLocation {
+    /// Fills a `{name}` placeholder in the path template
+    Path,
+    Query,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    #[serde(rename = "in")]
+    pub location: ParamLocation,
+    /// Value used in the baseline request
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Schema>,
+}
+
+impl Param {
+    fn text(&self) -> String {
+        match &self.value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Endpoint {
+    /// Path template relative to `base_url`, e.g. `/v1/returns/{pin}`
+    pub path: String,
+    pub method: String,
+    /// JSON body the mutation engine starts from (a TOML table)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed_body: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+    /// Relative share of the request budget; every endpoint defaults to 1
+    #[serde(default = "default_share")]
+    pub budget_share: f64,
+    /// Body schema; when present, cases past the fixed ones are generated from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Schema>,
+    #[serde(default)]
+    pub generation: Generation,
+    /// Requests captured from real traffic (`import har`, `import curl`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub captures: Vec<Capture>,
+}
+
+fn default_share() -> f64 {
+    1.0
+}
+
+/// Percent-encode everything outside RFC 3986 unreserved characters.
+pub fn encode_segment(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+impl Endpoint {
+    /// The path with every `{name}` placeholder filled from the path params.
+    pub fn path(&self) -> String {
+        let mut path = self.path.clone();
+        for p in self.params.iter().filter(|p| p.location == ParamLocation::Path) {
+            path = path.replace(&format!("{{{}}}", p.name), &encode_segment(&p.text()));
+        }
+        path
+    }
+
+    pub fn query(&self) -> Vec<(String, String)> {
+        self.params
+            .iter()
+            .filter(|p| p.location == ParamLocation::Query)
+            .map(|p| (p.name.clone(), p.text()))
+            .collect()
+    }
+
+    /// `METHOD /path/{template}`, naming the endpoint in corpora and buckets.
+    pub fn key(&self) -> String {
+        format!("{} {}", self.method.to_uppercase(), self.path)
+    }
+
+    /// Whether a request with `method` and `path` (relative to `base_url`)
+    /// fits this endpoint; placeholders match any one non-empty segment.
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        let segments = |p: &str| p.split('?').next().unwrap_or_default().trim_end_matches('/').split('/').map(str::to_string).collect::<Vec<_>>();
+        let (t, p) = (segments(&self.path), segments(path));
+        self.method.eq_ignore_ascii_case(method)
+            && t.len() == p.len()
+            && t.iter().zip(&p).all(|(t, p)| t == p || (t.starts_with('{') && t.ends_with('}') && !p.is_empty()))
+    }
+
+    /// The first placeholder in the template no path param fills.
+    pub fn unbound(&self) -> Option<&str> {
+        let bound = |name: &str| self.params.iter().any(|p| p.location == ParamLocation::Path && p.name == name);
+        let mut rest = self.path.as_str();
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..].find('}')? + open;
+            let name = &rest[open + 1..close];
+            if !bound(name) {
+                return Some(name);
+            }
+            rest = &rest[close + 1..];
+        }
+        None
+    }
+}
+
+/// The endpoint a request was planned for, by method and path template.
+pub fn of<'a>(endpoints: &'a [Endpoint], method: &str, path: &str) -> Option<&'a Endpoint> {
+    endpoints.iter().find(|ep| ep.matches(method, path))
+}
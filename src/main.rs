@@ -1,23 +1,332 @@
 use anyhow::{bail, Context, Result};
-use clap::Parser;
-use serde::Deserialize;
-use std::{fs, path::Path};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use tracing_subscriber::{fmt, EnvFilter};
 
+mod allowlist;
+mod artifacts;
+mod auth;
+mod bucket;
+mod bypass;
+mod capture;
+mod corpus;
+mod endpoint;
+mod minimize;
+mod mutate;
+mod openapi;
+mod oracle;
+mod oversize;
+mod plan;
+mod race;
+mod report;
+mod rng;
+mod runner;
+mod sandbox;
+mod schema;
+mod scheduler;
+mod signing;
+#[cfg(test)]
+mod testutil;
+mod timestamp;
+mod transport;
+mod uniqueness;
+mod wire;
+
+use oracle::Verdict;
+use oversize::ByteSize;
+use scheduler::Scheduler;
+use std::sync::Arc;
+use std::time::Duration;
+use transport::{HttpVersion, Transport};
+
 #[derive(Parser, Debug)]
 #[command(name="api-fuzzkit", version, about="Sandbox API fuzzing toolkit")]
 struct Args {
     /// Path to target profile TOML
-    #[arg(short, long, default_value = "profiles/kra-sandbox.toml")]
+    #[arg(short, long, global = true, default_value = "profiles/kra-sandbox.toml")]
     profile: String,
 
-    /// Require sandbox mode
-    #[arg(long, default_value_t = true)]
-    sandbox: bool,
+    /// Attest whether the target is a sandbox; required to send traffic
+    #[arg(long, global = true, value_enum)]
+    sandbox: Option<Attestation>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Load the profile and check guardrails; send nothing
+    Validate,
+    /// List the cases a run would send, without sending them
+    Plan {
+        /// Seed for case generation (random if omitted)
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Show at most this many cases (defaults to the request budget)
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Execute a session and write its artifacts
+    Run {
+        /// What to run against the endpoint
+        #[arg(long, value_enum, default_value_t = Mode::Fuzz)]
+        mode: Mode,
+        /// Seed for case generation (random if omitted; always logged)
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Root directory for per-session artifacts
+        #[arg(long, default_value = "artifacts")]
+        artifacts: PathBuf,
+        /// Exit 3 when any case is judged this severe
+        #[arg(long, value_enum, default_value_t = FailOn::Finding)]
+        fail_on: FailOn,
+    },
+    /// Re-send stored cases, regenerated byte-for-byte from their seed and index
+    Replay {
+        /// Case index as printed by the original run (repeatable)
+        #[arg(long = "case", required = true)]
+        cases: Vec<usize>,
+        /// Seed of the original run
+        #[arg(long, required_unless_present = "from")]
+        seed: Option<u64>,
+        /// Artifact directory of the original run; supplies seed and profile
+        #[arg(long, conflicts_with = "seed")]
+        from: Option<PathBuf>,
+        /// Root directory for per-session artifacts
+        #[arg(long, default_value = "artifacts")]
+        artifacts: PathBuf,
+        /// Exit 3 when any case is judged this severe
+        #[arg(long, value_enum, default_value_t = FailOn::Finding)]
+        fail_on: FailOn,
+    },
+    /// Render results from an artifact directory
+    Report {
+        /// Session artifact directory
+        dir: PathBuf,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+        /// Write the report here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Draft a profile from an existing API description
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImportSource {
+    /// OpenAPI 3 or Swagger 2 spec, JSON or YAML
+    Openapi {
+        spec: PathBuf,
+        /// Write the profile here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Profile name (defaults to the spec title)
+        #[arg(long)]
+        name: Option<String>,
+        /// Target URL, when the spec's servers are relative or wrong
+        #[arg(long)]
+        base_url: Option<String>,
+        /// Operations with other methods are skipped
+        #[arg(long, value_delimiter = ',', default_value = "GET,POST")]
+        methods: Vec<String>,
+        /// Generate bodies that break one schema constraint each, instead of valid ones
+        #[arg(long)]
+        violate: bool,
+    },
+    /// HAR file exported from the browser; requests are attached to the
+    /// matching endpoints of --profile
+    Har {
+        file: PathBuf,
+        /// Write the updated profile here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Keep cookies and auth headers
+        #[arg(long)]
+        keep_credentials: bool,
+    },
+    /// `curl` command lines (e.g. "Copy as cURL"), or `-` for stdin; requests
+    /// are attached to the matching endpoints of --profile
+    Curl {
+        file: PathBuf,
+        /// Write the updated profile here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Keep cookies and auth headers
+        #[arg(long)]
+        keep_credentials: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Attestation {
+    /// The target is a sandbox
+    Yes,
+    /// The target is not a sandbox; nothing will be sent
+    No,
+}
+
+impl Command {
+    fn sends_traffic(&self) -> bool {
+        matches!(self, Command::Run { .. } | Command::Replay { .. })
+    }
+}
 
-    /// Dry run (don’t send requests)
-    #[arg(long, default_value_t = false)]
-    dry_run: bool,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Mutated and oversized cases
+    Fuzz,
+    /// Replay a captured request and probe clock skew (needs [clock])
+    Timestamp,
+    /// Re-use idempotency keys and nonces and send duplicates at once (needs [uniqueness])
+    Uniqueness,
+    /// Release synchronized bursts of one request for TOCTOU bugs (needs [race])
+    Race,
+    /// Stored cases re-sent by `replay`; not a `--mode`
+    #[value(skip)]
+    Replay,
+}
+
+impl Mode {
+    const ALL: [Mode; 5] = [Mode::Fuzz, Mode::Timestamp, Mode::Uniqueness, Mode::Race, Mode::Replay];
+
+    /// The name in logs, `session.json` and reports; kept stable across variant renames.
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Fuzz => "fuzz",
+            Mode::Timestamp => "timestamp",
+            Mode::Uniqueness => "uniqueness",
+            Mode::Race => "race",
+            Mode::Replay => "replay",
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Mode {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(d)?;
+        Mode::ALL.into_iter().find(|m| m.as_str() == name).ok_or_else(|| de::Error::custom(format!("unknown session mode '{name}'")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    /// Console summary
+    Text,
+    /// Summary to paste into a ticket or wiki
+    Markdown,
+    /// Self-contained page to hand to API owners
+    Html,
+    /// SARIF 2.1.0 for code-scanning dashboards
+    Sarif,
+    /// JUnit XML: a suite per endpoint, a failing test per bucket
+    Junit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FailOn {
+    Anomaly,
+    Finding,
+    /// Exit 0 whatever the oracles said
+    Never,
+}
+
+impl FailOn {
+    fn threshold(self) -> Option<Verdict> {
+        match self {
+            FailOn::Anomaly => Some(Verdict::Anomaly),
+            FailOn::Finding => Some(Verdict::Finding),
+            FailOn::Never => None,
+        }
+    }
+}
+
+/// Exit codes CI can branch on. Other errors exit 1; usage errors exit 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// Ran and nothing reached the `--fail-on` threshold
+    Clean = 0,
+    /// Some case was judged at or above the `--fail-on` threshold
+    Findings = 3,
+    /// Guardrails, DNS pinning or the sandbox probe refused the session
+    Refused = 4,
+    /// Cases failed at the transport level (target down, timeouts), oversized ones aside
+    Transport = 5,
+}
+
+impl Exit {
+    /// What an error that ended the process means.
+    fn of(e: &anyhow::Error) -> ExitCode {
+        if e.downcast_ref::<Refused>().is_some() {
+            Exit::Refused.into()
+        } else if transport::is_transport(e) {
+            Exit::Transport.into()
+        } else {
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(e: Exit) -> Self {
+        ExitCode::from(e as u8)
+    }
+}
+
+/// How a session ended, for its exit code.
+struct Ended {
+    worst: Verdict,
+    /// Cases that failed at the transport level, less those the target may drop
+    failed: u32,
+}
+
+impl Ended {
+    fn of(outcomes: &[runner::Outcome]) -> Self {
+        Self {
+            worst: outcomes.iter().map(|o| o.verdict()).max().unwrap_or(Verdict::Pass),
+            failed: outcomes.iter().filter(|o| o.result.is_err() && !o.case.may_drop()).count() as u32,
+        }
+    }
+
+    /// Findings outrank transport failures: they stand even if the run was cut short.
+    fn exit(&self, fail_on: FailOn) -> Exit {
+        if fail_on.threshold().is_some_and(|t| self.worst >= t) {
+            Exit::Findings
+        } else if self.failed > 0 {
+            Exit::Transport
+        } else {
+            Exit::Clean
+        }
+    }
+}
+
+/// Error context marking a refusal to send traffic, so it exits 4.
+#[derive(Debug)]
+struct Refused;
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("refused to run")
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +336,29 @@ struct Limits {
     request_budget: u32,
     max_rate_per_sec: u32,
     allowed_methods: Vec<String>,
+    /// Retries per case on transport errors; each one is charged to `request_budget`
+    #[serde(default)]
+    retries: u32,
+    /// Sizes the oversized-payload generator grows bodies, headers, queries and fields to
+    #[serde(default = "oversize::default_ladder")]
+    payload_ladder: Vec<ByteSize>,
+    /// Requests held back from `request_budget` to shrink findings; a tenth of it by default
+    #[serde(default)]
+    minimize_budget: Option<u32>,
+    /// Largest `[race]` burst allowed above `concurrency` or `max_rate_per_sec`; none by default
+    #[serde(default)]
+    burst_ceiling: Option<usize>,
+}
+
+impl Limits {
+    fn minimize_budget(&self) -> u32 {
+        self.minimize_budget.unwrap_or(self.request_budget / 10)
+    }
+
+    /// Requests left for planned cases once the minimiser's share is set aside.
+    fn case_budget(&self) -> u32 {
+        self.request_budget.saturating_sub(self.minimize_budget())
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,14 +370,44 @@ struct Safety {
     allowlist_hosts: Vec<String>,
     #[serde(default)] // key->value map for forced headers (optional in v1)
     force_headers: std::collections::HashMap<String, String>,
+    /// Hard ceiling on body + header + query bytes for any single request
+    #[serde(default = "oversize::default_max_payload")]
+    max_payload_bytes: ByteSize,
+    /// Forced header that must echo a `--sandbox yes` attestation
+    #[serde(default)]
+    sandbox_header: sandbox::EchoHeader,
+    /// Endpoint that must confirm sandbox mode before any fuzz traffic
+    #[serde(default)]
+    sandbox_probe: Option<sandbox::Probe>,
+    /// Allow loopback and private-range targets (local mocks)
+    #[serde(default)]
+    allow_private_targets: bool,
+    /// Static host -> IPs answers used instead of DNS
+    #[serde(default)]
+    resolve: std::collections::HashMap<String, Vec<std::net::IpAddr>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Profile {
     name: String,
     base_url: String,
-    endpoint: String,
-    method: String,
+    endpoints: Vec<endpoint::Endpoint>,
+    #[serde(default)]
+    http_version: HttpVersion,
+    #[serde(default)]
+    clock: Option<timestamp::Clock>,
+    #[serde(default)]
+    uniqueness: Option<uniqueness::Uniqueness>,
+    #[serde(default)]
+    race: Option<race::Race>,
+    #[serde(default)]
+    oracles: oracle::OracleConfig,
+    #[serde(default)]
+    corpus: Option<corpus::CorpusConfig>,
+    #[serde(default)]
+    auth: Option<auth::AuthConfig>,
+    #[serde(default)]
+    signing: Option<signing::Signing>,
     limits: Limits,
     timeouts: Timeouts,
     safety: Safety,
@@ -53,7 +415,8 @@ struct Profile {
 
 fn init_logging() {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    fmt().with_env_filter(filter).init();
+    // Logs go to stderr: stdout carries imported profiles and reports.
+    fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
 }
 
 fn load_profile(path: &str) -> Result<Profile> {
@@ -64,23 +427,63 @@ fn load_profile(path: &str) -> Result<Profile> {
 }
 
 fn enforce_guardrails(p: &Profile, args: &Args) -> Result<()> {
-    // 1) Sandbox flag
-    if p.safety.require_sandbox_flag && !args.sandbox {
-        bail!("sandbox flag required: re-run with --sandbox");
-    }
+    guardrails(p, args).context(Refused)
+}
 
-    // 2) Host allowlist
-    let host = url::Url::parse(&p.base_url)
-        .ok()
-        .and_then(|u| u.host_str().map(|s| s.to_string()))
-        .ok_or_else(|| anyhow::anyhow!("invalid base_url: {}", p.base_url))?;
-    if !p.safety.allowlist_hosts.iter().any(|h| h.eq_ignore_ascii_case(&host)) {
-        bail!("base_url host not in allowlist: {host}");
+fn guardrails(p: &Profile, args: &Args) -> Result<()> {
+    // 1) Sandbox attestation, echoed by a forced header
+    if args.command.sends_traffic() {
+        match args.sandbox {
+            Some(Attestation::No) => bail!("target attested as not a sandbox (--sandbox no); refusing to send"),
+            None if p.safety.require_sandbox_flag => {
+                bail!("sandbox attestation required: re-run with --sandbox yes")
+            }
+            _ => {}
+        }
+    }
+    if args.sandbox == Some(Attestation::Yes) || p.safety.require_sandbox_flag {
+        p.safety.sandbox_header.check(&p.safety.force_headers)?;
     }
 
-    // 3) Method safety
-    if !p.limits.allowed_methods.iter().any(|m| m.eq_ignore_ascii_case(&p.method)) {
-        bail!("HTTP method '{}' not allowed by policy", p.method);
+    // 2) Host allowlist: names as listed, anything else by address inside a
+    //    CIDR range (listed names are resolved and checked when pinning)
+    let base = url::Url::parse(&p.base_url).with_context(|| format!("invalid base_url: {}", p.base_url))?;
+    let allowlist = allowlist::Allowlist::new(p)?;
+    let resolver = allowlist::resolver(p);
+    allowlist.check(&base, &*resolver).with_context(|| format!("base_url {}", p.base_url))?;
+
+    // 3) Endpoints: method safety, complete path templates, usable budget shares,
+    //    seeds that satisfy their schema, captures from allowlisted hosts.
+    //    Requests the tool makes on its own (the sandbox probe) obey the same methods.
+    if p.endpoints.is_empty() {
+        bail!("profile has no [[endpoints]]");
+    }
+    let allowed = |method: &str| p.limits.allowed_methods.iter().any(|m| m.eq_ignore_ascii_case(method));
+    if let Some(probe) = &p.safety.sandbox_probe {
+        if !allowed("GET") {
+            bail!("HTTP method 'GET' for sandbox_probe {} not allowed by policy", probe.path);
+        }
+    }
+    for ep in &p.endpoints {
+        if !allowed(&ep.method) {
+            bail!("HTTP method '{}' for {} not allowed by policy", ep.method, ep.path);
+        }
+        if let Some(name) = ep.unbound() {
+            bail!("path template {} has no path param for {{{name}}}", ep.path);
+        }
+        if !(ep.budget_share.is_finite() && ep.budget_share > 0.0) {
+            bail!("budget_share for {} must be > 0", ep.path);
+        }
+        if let (Some(schema), Some(seed)) = (&ep.schema, &ep.seed_body) {
+            if !schema.accepts(seed) {
+                tracing::warn!(path = %ep.path, "seed_body breaks its schema; violations start from the schema example");
+            }
+        }
+        for (i, capture) in ep.captures.iter().enumerate() {
+            let url = url::Url::parse(&capture.url).with_context(|| format!("invalid URL in capture #{i} of {}", ep.path))?;
+            allowlist.check(&url, &*resolver).with_context(|| format!("capture #{i} of {}", ep.path))?;
+            capture.request(&ep.method, &base)?;
+        }
     }
 
     // 4) Rate ceiling
@@ -88,37 +491,417 @@ fn enforce_guardrails(p: &Profile, args: &Args) -> Result<()> {
         bail!("rate_per_sec exceeds policy ceiling");
     }
 
-    // 5) Request budget > 0
+    // 5) Request budget > 0, with room for cases after the minimiser's share
     if p.limits.request_budget == 0 {
         bail!("request_budget must be > 0");
     }
+    if p.limits.case_budget() == 0 {
+        bail!("minimize_budget must be below request_budget");
+    }
+
+    // 6) Scheduler needs at least one slot and one token per second
+    if p.limits.concurrency == 0 || p.limits.rate_per_sec == 0 {
+        bail!("concurrency and rate_per_sec must be > 0");
+    }
+
+    // 7) Oversized payloads stay under the safety ceiling
+    if let Some(rung) = p.limits.payload_ladder.iter().find(|s| **s > p.safety.max_payload_bytes) {
+        bail!("payload_ladder rung {rung} exceeds max_payload_bytes ({})", p.safety.max_payload_bytes);
+    }
+
+    // 8) Client secrets only go to an allowlisted token endpoint, over TLS
+    //    unless it is a local token server
+    for token_url in p.auth.iter().flat_map(auth::AuthConfig::token_urls) {
+        let url = url::Url::parse(token_url).with_context(|| format!("invalid auth token_url: {token_url}"))?;
+        allowlist.check(&url, &*resolver).with_context(|| format!("auth token_url {url}"))?;
+        if url.scheme() != "https" && !allowlist::is_local(&url) {
+            bail!("auth token_url {url} must use https unless it is a local token server");
+        }
+    }
+
+    // 9) A race burst puts all its requests in flight in the same instant:
+    //    it must fit concurrency and the rate ceiling, or an explicit burst_ceiling
+    if let Some(race) = &p.race {
+        let n = race.requests;
+        if n < 2 {
+            bail!("[race] requests must be at least 2");
+        }
+        let within = n <= p.limits.concurrency && n <= p.limits.max_rate_per_sec as usize;
+        match p.limits.burst_ceiling {
+            _ if within => {}
+            Some(ceiling) if n <= ceiling => {}
+            Some(ceiling) => bail!("[race] burst of {n} exceeds burst_ceiling ({ceiling})"),
+            None => bail!(
+                "[race] burst of {n} exceeds concurrency ({}) or max_rate_per_sec ({}); set limits.burst_ceiling to allow it",
+                p.limits.concurrency,
+                p.limits.max_rate_per_sec
+            ),
+        }
+        if n as u32 > p.limits.case_budget() {
+            bail!("[race] burst of {n} does not fit the request budget");
+        }
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
+fn session_banner(p: &Profile, mode: Mode, seed: u64) {
+    tracing::info!(target = "session",
+        name = %p.name,
+        base = %p.base_url,
+        endpoints = p.endpoints.len(),
+        budget = p.limits.request_budget,
+        rate = p.limits.rate_per_sec,
+        concurrency = p.limits.concurrency,
+        seed,
+        "guardrails OK; {mode} mode"
+    );
+}
+
+/// Send `cases` (or run the timestamp, uniqueness or race mode), judge every outcome and write
+/// the session's artifacts.
+async fn execute(
+    profile: &Profile,
+    profile_path: &str,
+    artifacts_root: &Path,
+    mode: Mode,
+    seed: u64,
+    cases: Vec<(usize, plan::Case)>,
+    corpus: &mut corpus::Corpus,
+) -> Result<Ended> {
+    let mut oracles = oracle::Oracles::new(&profile.oracles, &plan::baselines(profile))?;
+    let sched = Scheduler::new(&profile.limits);
+    let transport = Arc::new(Transport::new(profile, &sched)?);
+    // Confirm the sandbox before anything is written, so a refused session leaves no run dir.
+    if let Some(probe) = &profile.safety.sandbox_probe {
+        probe.confirm(&transport, &sched).await?;
+    }
+    let mut session = artifacts::Session::create(artifacts_root, &profile.name, profile_path)?;
+    tracing::info!(target: "session", dir = %session.dir.display(), "writing artifacts");
+    let reporter = sched.spawn_reporter(Duration::from_secs(5));
+
+    let mut outcomes = match mode {
+        Mode::Timestamp => timestamp::run(profile, Arc::clone(&transport), Arc::clone(&sched)).await?,
+        Mode::Uniqueness => uniqueness::run(profile, Arc::clone(&transport), Arc::clone(&sched)).await?,
+        Mode::Race => race::run(profile, Arc::clone(&transport), Arc::clone(&sched)).await?,
+        Mode::Fuzz | Mode::Replay => runner::run(Arc::clone(&transport), Arc::clone(&sched), profile.limits.retries, cases).await,
+    };
+
+    let mut verdicts = [0usize; 3];
+    let mut kept = 0;
+    for o in &mut outcomes {
+        oracles.judge(o);
+        verdicts[o.verdict() as usize] += 1;
+        println!("{}", o.report());
+        session.record(o)?;
+        // Only fresh fuzz sessions grow the corpus; replays re-send known cases.
+        if mode == Mode::Fuzz && corpus.consider(&profile.endpoints, o)? {
+            kept += 1;
+        }
+    }
+
+    if mode == Mode::Timestamp {
+        session.skews(timestamp::accepted_skews(&outcomes));
+    }
+
+    let mut buckets = bucket::group(&profile.endpoints, &outcomes);
+    let db_path = bucket::Db::path(artifacts_root, &profile.name);
+    let mut db = bucket::Db::load(&db_path)?;
+    // A replay re-sends findings already counted by the session they came from.
+    if mode == Mode::Replay {
+        db.flag(&mut buckets);
+    } else {
+        db.merge(&session.name(), &mut buckets);
+    }
+
+    // Shrink each finding bucket's representative with what budget is left.
+    if mode == Mode::Fuzz {
+        for b in buckets.iter().filter(|b| b.verdict == Verdict::Finding) {
+            let Some(o) = outcomes.iter().find(|o| o.index == b.representative) else { continue };
+            if let Some(shrunk) = minimize::shrink(&transport, &sched, &oracles, o).await {
+                println!(
+                    "#{} minimized for {}: {} -> {} bytes in {} requests",
+                    o.index,
+                    shrunk.oracle,
+                    o.case.request.size(),
+                    shrunk.case.request.size(),
+                    shrunk.attempts
+                );
+                session.minimized(o, &shrunk)?;
+            }
+            let snap = sched.snapshot();
+            if snap.issued >= snap.budget {
+                break;
+            }
+        }
+    }
+    reporter.abort();
+    session.buckets(&buckets)?;
+    db.save(&db_path)?;
+
+    println!(
+        "{} cases: {} pass, {} anomaly, {} finding",
+        outcomes.len(),
+        verdicts[Verdict::Pass as usize],
+        verdicts[Verdict::Anomaly as usize],
+        verdicts[Verdict::Finding as usize]
+    );
+    let fresh: Vec<&bucket::Bucket> = buckets.iter().filter(|b| b.new).collect();
+    println!("{} buckets, {} new", buckets.len(), fresh.len());
+    for b in fresh {
+        let status = b.signature.status.map_or_else(|| "error".into(), |s| s.to_string());
+        println!("  new {} {} x{} {} -> {} [{}] e.g. #{}", b.id, b.verdict, b.count, b.signature.endpoint, status, b.signature.operator, b.representative);
+    }
+    let snap = sched.snapshot();
+    snap.log("session complete");
+    if let Some(corpus_dir) = corpus.dir().filter(|_| mode == Mode::Fuzz) {
+        println!("corpus: {kept} new entries in {}", corpus_dir.display());
+    }
+    let ended = Ended::of(&outcomes);
+    let dir = session.finish(seed, mode, outcomes.len(), ended.failed, &snap, corpus.planned())?;
+    println!("artifacts: {}", dir.display());
+    Ok(ended)
+}
+
+/// Write an imported profile or a report to `output`, or stdout.
+fn write_output(output: Option<&Path>, text: &str) -> Result<()> {
+    match output {
+        Some(path) => fs::write(path, text).with_context(|| format!("failed to write {}", path.display())),
+        None => {
+            print!("{text}");
+            Ok(())
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
     init_logging();
     let args = Args::parse();
-    let profile = load_profile(&args.profile)?;
+    match dispatch(&args).await {
+        Ok(exit) => exit.into(),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            Exit::of(&e)
+        }
+    }
+}
 
-    enforce_guardrails(&profile, &args)?;
+async fn dispatch(args: &Args) -> Result<Exit> {
+    match &args.command {
+        Command::Validate => {
+            let profile = load_profile(&args.profile)?;
+            enforce_guardrails(&profile, args)?;
+            if let Some(auth) = &profile.auth {
+                auth.check()?;
+            }
+            if let Some(signing) = &profile.signing {
+                signing.check()?;
+            }
+            println!("{}: profile OK, guardrails pass", args.profile);
+        }
+        Command::Plan { seed, limit } => {
+            let profile = load_profile(&args.profile)?;
+            enforce_guardrails(&profile, args)?;
+            let seed = seed.unwrap_or_else(rng::fresh_seed);
+            let count = limit.unwrap_or(profile.limits.case_budget() as usize);
+            let corpus = corpus::Corpus::for_profile(profile.corpus.as_ref())?;
+            let cases = plan::Planner::new(&profile, &corpus, seed).cases(count);
+            for (i, c) in &cases {
+                println!("#{i} {c}");
+            }
+            println!("{} cases planned with seed {seed}. No requests sent.", cases.len());
+        }
+        Command::Run { mode, seed, artifacts, fail_on } => {
+            let profile = load_profile(&args.profile)?;
+            enforce_guardrails(&profile, args)?;
+            let seed = seed.unwrap_or_else(rng::fresh_seed);
+            session_banner(&profile, *mode, seed);
+            let mut corpus = corpus::Corpus::for_profile(profile.corpus.as_ref())?;
+            let cases = plan::Planner::new(&profile, &corpus, seed).cases(profile.limits.case_budget() as usize);
+            let ended = execute(&profile, &args.profile, artifacts, *mode, seed, cases, &mut corpus).await?;
+            return Ok(ended.exit(*fail_on));
+        }
+        Command::Replay { cases, seed, from, artifacts, fail_on } => {
+            let (profile_path, seed, stored, entries) = match from {
+                Some(dir) => {
+                    let (session, exchanges) = artifacts::load(dir)?;
+                    if !matches!(session.mode, Mode::Fuzz | Mode::Replay) {
+                        bail!("only fuzz sessions can be replayed (this one ran in {} mode)", session.mode);
+                    }
+                    let profile_path = dir.join("profile.toml").to_string_lossy().into_owned();
+                    (profile_path, session.seed, exchanges, Some(session.corpus))
+                }
+                None => (args.profile.clone(), seed.expect("clap requires --seed without --from"), Vec::new(), None),
+            };
+            let profile = load_profile(&profile_path)?;
+            enforce_guardrails(&profile, args)?;
+            session_banner(&profile, Mode::Replay, seed);
+            // The corpus has grown since; plan from the entries the session used.
+            let mut corpus = corpus::Corpus::for_profile(profile.corpus.as_ref())?;
+            if let Some(ids) = entries {
+                corpus = corpus.only(&ids)?;
+            }
+            let planner = plan::Planner::new(&profile, &corpus, seed);
+            let mut replay = Vec::new();
+            for &index in cases {
+                let case = planner
+                    .case(index)
+                    .with_context(|| format!("seed {seed} has no case #{index} for this profile"))?;
+                if let Some(original) = stored.iter().find(|x| x.index == index) {
+                    if !artifacts::same_request(original, &case) {
+                        tracing::warn!(index, "regenerated case differs from the stored request");
+                    }
+                }
+                replay.push((index, case));
+            }
+            let ended = execute(&profile, &profile_path, artifacts, Mode::Replay, seed, replay, &mut corpus).await?;
+            return Ok(ended.exit(*fail_on));
+        }
+        Command::Report { dir, format, output } => {
+            let text = match format {
+                ReportFormat::Text => report::text(dir)?,
+                _ => {
+                    // The profile the session ran with, not whatever --profile is now.
+                    let profile = load_profile(&dir.join("profile.toml").to_string_lossy())?;
+                    let summary = report::Summary::load(dir, &profile)?;
+                    match format {
+                        ReportFormat::Markdown => report::markdown(&summary)?,
+                        ReportFormat::Html => report::html(&summary)?,
+                        ReportFormat::Sarif => report::sarif(&summary)?,
+                        _ => report::junit(&summary)?,
+                    }
+                }
+            };
+            write_output(output.as_deref(), &text)?;
+        }
+        Command::Import { source: ImportSource::Openapi { spec, output, name, base_url, methods, violate } } => {
+            let opts = openapi::Options {
+                name: name.clone(),
+                base_url: base_url.clone(),
+                methods: methods.clone(),
+                generation: if *violate { schema::Generation::Violate } else { schema::Generation::Valid },
+            };
+            let profile = openapi::import(spec, &opts)?;
+            write_output(output.as_deref(), &profile)?;
+        }
+        Command::Import { source: ImportSource::Har { file, output, keep_credentials } } => {
+            let profile = load_profile(&args.profile)?;
+            let raw = fs::read_to_string(&args.profile)?;
+            let entries = capture::har(file)?;
+            let updated = capture::attach(&raw, &profile.base_url, &profile.endpoints, entries, *keep_credentials)?;
+            write_output(output.as_deref(), &updated)?;
+        }
+        Command::Import { source: ImportSource::Curl { file, output, keep_credentials } } => {
+            let profile = load_profile(&args.profile)?;
+            let raw = fs::read_to_string(&args.profile)?;
+            let entries = capture::curl(file)?;
+            let updated = capture::attach(&raw, &profile.base_url, &profile.endpoints, entries, *keep_credentials)?;
+            write_output(output.as_deref(), &updated)?;
+        }
+    }
+    Ok(Exit::Clean)
+}
 
-    // v1: just show planned session; no networking yet
-    tracing::info!(target = "session",
-        name = %profile.name,
-        base = %profile.base_url,
-        endpoint = %profile.endpoint,
-        method = %profile.method,
-        budget = profile.limits.request_budget,
-        rate = profile.limits.rate_per_sec,
-        concurrency = profile.limits.concurrency,
-        "guardrails OK; {} mode",
-        if args.dry_run { "dry-run" } else { "execution" }
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if args.dry_run {
-        println!("(dry-run) Ready to plan test cases. No requests will be sent.");
-    } else {
-        println!("Execution would start here (transport not wired yet).");
+    #[test]
+    fn mode_names_round_trip_and_match_the_cli() {
+        for mode in Mode::ALL {
+            let json = serde_json::to_string(&mode).unwrap();
+            assert_eq!(json, format!("\"{}\"", mode.as_str()));
+            assert_eq!(serde_json::from_str::<Mode>(&json).unwrap(), mode);
+            if let Some(value) = mode.to_possible_value() {
+                assert_eq!(value.get_name(), mode.as_str());
+            }
+        }
+        assert!(Mode::Replay.to_possible_value().is_none());
+        assert!(serde_json::from_str::<Mode>("\"chaos\"").is_err());
+    }
+
+    #[test]
+    fn sandbox_probe_obeys_allowed_methods() {
+        let mut p = testutil::profile("http://127.0.0.1:1");
+        p.safety.sandbox_probe = Some(toml::from_str(r#"path = "/health""#).unwrap());
+        let args = Args::parse_from(["api-fuzzkit", "validate"]);
+        guardrails(&p, &args).unwrap();
+
+        p.limits.allowed_methods = vec!["POST".into()];
+        let err = guardrails(&p, &args).unwrap_err();
+        assert!(err.to_string().contains("sandbox_probe"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn refused_sandbox_probe_leaves_no_run_dir() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+        let server = MockServer::start().await;
+        Mock::given(path("/health")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        p.safety.sandbox_probe = Some(toml::from_str(r#"path = "/health""#).unwrap());
+        let root = std::env::temp_dir().join(format!("fuzzkit-probe-{}", std::process::id()));
+        let mut corpus = corpus::Corpus::for_profile(None).unwrap();
+
+        let err = execute(&p, "profile.toml", &root, Mode::Fuzz, 1, Vec::new(), &mut corpus).await.err().expect("the probe should refuse");
+
+        assert!(err.downcast_ref::<Refused>().is_some(), "{err:#}");
+        assert!(format!("{err:#}").contains("returned 503"), "{err:#}");
+        assert!(!root.exists(), "a refused session wrote {}", root.display());
+    }
+
+    #[tokio::test]
+    async fn replays_do_not_count_in_the_bucket_db() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(500)).mount(&server).await;
+        let p = testutil::profile(&server.uri());
+        let root = std::env::temp_dir().join(format!("fuzzkit-buckets-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let profile_path = root.join("profile.toml");
+        fs::write(&profile_path, "name = \"test\"\n").unwrap();
+        let mut corpus = corpus::Corpus::for_profile(None).unwrap();
+        let case = || vec![(0, plan::baseline(&p.endpoints[0]))];
+        let db = || bucket::Db::load(&bucket::Db::path(&root, &p.name)).unwrap();
+
+        execute(&p, profile_path.to_str().unwrap(), &root, Mode::Fuzz, 1, case(), &mut corpus).await.unwrap();
+        let after_fuzz: Vec<(u32, u64)> = db().buckets.values().map(|k| (k.sessions, k.count)).collect();
+        execute(&p, profile_path.to_str().unwrap(), &root, Mode::Replay, 1, case(), &mut corpus).await.unwrap();
+        let after_replay: Vec<(u32, u64)> = db().buckets.values().map(|k| (k.sessions, k.count)).collect();
+
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(after_fuzz, [(1, 1)]);
+        assert_eq!(after_replay, after_fuzz);
+    }
+
+    /// Run `cases` against a port nothing listens on and return the exit
+    /// code and SARIF `executionSuccessful`.
+    async fn unreachable(name: &str, cases: Vec<(usize, plan::Case)>) -> (Exit, bool) {
+        let p = testutil::profile("http://127.0.0.1:1");
+        let root = std::env::temp_dir().join(format!("fuzzkit-{name}-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let profile_path = root.join("profile.toml");
+        fs::write(&profile_path, "name = \"test\"\n").unwrap();
+        let mut corpus = corpus::Corpus::for_profile(None).unwrap();
+
+        let ended = execute(&p, profile_path.to_str().unwrap(), &root, Mode::Fuzz, 1, cases, &mut corpus).await.unwrap();
+
+        let dir = fs::read_dir(&root).unwrap().map(|e| e.unwrap().path()).find(|d| d.join("session.json").exists()).unwrap();
+        let sarif: serde_json::Value = serde_json::from_str(&report::sarif(&report::Summary::load(&dir, &p).unwrap()).unwrap()).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        (ended.exit(FailOn::Finding), sarif["runs"][0]["invocations"][0]["executionSuccessful"].as_bool().unwrap())
+    }
+
+    #[tokio::test]
+    async fn dropped_oversized_cases_are_not_transport_failures() {
+        let p = testutil::profile("http://127.0.0.1:1");
+        let oversize: Vec<_> = oversize::cases(&p, &p.endpoints[0], &plan::baseline(&p.endpoints[0]))
+            .into_iter()
+            .enumerate()
+            .collect();
+        assert!(!oversize.is_empty());
+        assert_eq!(unreachable("oversize", oversize).await, (Exit::Clean, true));
+
+        let baseline = vec![(0, plan::baseline(&p.endpoints[0]))];
+        assert_eq!(unreachable("baseline", baseline).await, (Exit::Transport, false));
     }
-    Ok(())
 }
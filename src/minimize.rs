@@ -0,0 +1,356 @@
+//! Delta-debugging minimisation of findings: shrink a case's query, headers
+//! and body while the oracle that fired on it keeps firing, then send the
+//! result once more and keep it only if the oracle fires again. Every attempt
+//! is a real request through the scheduler, so it is paced by `rate_per_sec`
+//! and charged to `request_budget` (out of the `minimize_budget` held back
+//! for it).
+
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+use crate::oracle::{Oracles, Verdict};
+use crate::plan::Case;
+use crate::runner::{self, Outcome};
+use crate::scheduler::Scheduler;
+use crate::transport::{Body, Request, Transport};
+
+/// Requests one finding may spend, the final check included.
+const MAX_ATTEMPTS: u32 = 64;
+/// Filled bodies are halved until this small, then minimised byte by byte.
+const MAX_BYTE_BODY: u64 = 64 << 10;
+/// Query and header values shorter than this are left alone.
+const MIN_SHORTEN: usize = 16;
+
+/// A smaller case the same oracle still fires on.
+pub struct Shrunk {
+    pub oracle: String,
+    pub attempts: u32,
+    pub case: Case,
+}
+
+/// The oracle worth minimising for: the first one that raised a finding.
+pub fn target(o: &Outcome) -> Option<&str> {
+    o.signals.iter().find(|s| s.verdict == Verdict::Finding).map(|s| s.oracle.as_str())
+}
+
+struct Probe<'a> {
+    transport: Arc<Transport>,
+    sched: Arc<Scheduler>,
+    oracles: &'a Oracles,
+    oracle: String,
+    index: usize,
+    attempts: u32,
+    spent: bool,
+}
+
+impl Probe<'_> {
+    /// Send `case` and report whether the oracle fires again. False for
+    /// every attempt once the budget or this finding's share, less the
+    /// final check, is spent.
+    async fn fires(&mut self, case: &Case) -> bool {
+        let left = self.sched.snapshot();
+        if self.attempts + 1 >= MAX_ATTEMPTS || left.issued + 1 >= left.budget {
+            self.spent = true;
+        }
+        if self.spent {
+            return false;
+        }
+        self.send(case).await
+    }
+
+    /// Send `case` once more, whatever this finding has spent, and report
+    /// whether the oracle fires on it.
+    async fn confirm(&mut self, case: &Case) -> bool {
+        self.spent = false;
+        self.send(case).await
+    }
+
+    async fn send(&mut self, case: &Case) -> bool {
+        let sent = runner::run(Arc::clone(&self.transport), Arc::clone(&self.sched), 0, vec![(self.index, case.clone())]).await;
+        let Some(o) = sent.into_iter().next() else {
+            self.spent = true;
+            return false;
+        };
+        self.attempts += 1;
+        o.result.is_ok_and(|resp| self.oracles.fires(&self.oracle, case, &resp))
+    }
+
+    /// ddmin: drop ever smaller chunks of `items` while the case `build`
+    /// makes from what is left still fires.
+    async fn ddmin<T: Clone>(&mut self, mut items: Vec<T>, build: impl Fn(&[T]) -> Case) -> Vec<T> {
+        if items.is_empty() || self.fires(&build(&[])).await {
+            return Vec::new();
+        }
+        let mut n = 2;
+        while items.len() >= 2 && !self.spent {
+            let chunk = items.len().div_ceil(n);
+            let mut reduced = false;
+            for start in (0..items.len()).step_by(chunk) {
+                let end = (start + chunk).min(items.len());
+                let rest: Vec<T> = items[..start].iter().chain(&items[end..]).cloned().collect();
+                if self.fires(&build(&rest)).await {
+                    items = rest;
+                    n = (n - 1).max(2);
+                    reduced = true;
+                    break;
+                }
+            }
+            if !reduced {
+                if n >= items.len() {
+                    break;
+                }
+                n = (n * 2).min(items.len());
+            }
+        }
+        items
+    }
+
+    /// Halve `s` while the case `build` makes from it still fires.
+    async fn shorten(&mut self, mut s: String, build: impl Fn(&str) -> Case) -> String {
+        while s.len() >= MIN_SHORTEN && !self.spent {
+            let half: String = s.chars().take(s.chars().count() / 2).collect();
+            if !self.fires(&build(&half)).await {
+                break;
+            }
+            s = half;
+        }
+        s
+    }
+
+    /// Drop, then shorten, the `pairs` that `with` puts into the case.
+    async fn pairs(&mut self, case: Case, with: fn(&Case, Vec<(String, String)>) -> Case, pairs: Vec<(String, String)>) -> Case {
+        let mut kept = self.ddmin(pairs, |keep| with(&case, keep.to_vec())).await;
+        for i in 0..kept.len() {
+            let value = kept[i].1.clone();
+            let short = self
+                .shorten(value, |v| {
+                    let mut next = kept.clone();
+                    next[i].1 = v.to_string();
+                    with(&case, next)
+                })
+                .await;
+            kept[i].1 = short;
+        }
+        with(&case, kept)
+    }
+
+    /// Halve the filler of a `Filled` body, then minimise what is left as bytes.
+    async fn body(&mut self, case: Case) -> Case {
+        let bytes = match &case.request.body {
+            Some(Body::Filled { prefix, fill, len, suffix }) => {
+                let edges = (prefix.len() + suffix.len()) as u64;
+                let filled = |len: u64| with_body(&case, Body::Filled { prefix: prefix.clone(), fill: *fill, len, suffix: suffix.clone() });
+                let mut len = *len;
+                while len > edges && !self.spent {
+                    let next = edges + (len - edges) / 2;
+                    if !self.fires(&filled(next)).await {
+                        break;
+                    }
+                    len = next;
+                }
+                let shrunk = filled(len);
+                if len > MAX_BYTE_BODY {
+                    return shrunk;
+                }
+                shrunk.request.body.as_ref().map_or_else(Vec::new, |b| b.head(len as usize))
+            }
+            Some(Body::Bytes(b)) => b.clone(),
+            None => return case,
+        };
+        // Members are dropped from a parsed document, which is only the same
+        // request if it serialises back to the same bytes (no duplicate keys,
+        // `-0`, whitespace or key order of its own); otherwise shrink the text.
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(doc) if serde_json::to_vec(&doc).ok().as_ref() == Some(&bytes) => self.json(&case, doc).await,
+            _ => {
+                let kept = self.ddmin(bytes, |keep| with_body(&case, Body::Bytes(keep.to_vec()))).await;
+                with_body(&case, Body::Bytes(kept))
+            }
+        }
+    }
+
+    /// Drop object members and array items, outermost first, then shorten strings.
+    async fn json(&mut self, case: &Case, mut doc: Value) -> Case {
+        let render = |d: &Value| with_body(case, Body::Bytes(d.to_string().into_bytes()));
+        let mut work = vec![String::new()];
+        while let Some(at) = work.pop() {
+            if self.spent {
+                break;
+            }
+            let Some(node) = doc.pointer(&at).cloned() else { continue };
+            let base = doc.clone();
+            let replaced = |v: Value| {
+                let mut d = base.clone();
+                if let Some(slot) = d.pointer_mut(&at) {
+                    *slot = v;
+                }
+                d
+            };
+            let next = match node {
+                Value::Object(m) => {
+                    let members: Vec<(String, Value)> = m.into_iter().collect();
+                    let kept = self.ddmin(members, |keep| render(&replaced(Value::Object(keep.iter().cloned().collect::<Map<_, _>>())))).await;
+                    let escape = |k: &str| k.replace('~', "~0").replace('/', "~1");
+                    work.extend(kept.iter().map(|(k, _)| format!("{at}/{}", escape(k))));
+                    Value::Object(kept.into_iter().collect())
+                }
+                Value::Array(items) => {
+                    let kept = self.ddmin(items, |keep| render(&replaced(Value::Array(keep.to_vec())))).await;
+                    work.extend((0..kept.len()).map(|i| format!("{at}/{i}")));
+                    Value::Array(kept)
+                }
+                Value::String(s) => Value::String(self.shorten(s, |v| render(&replaced(Value::String(v.into())))).await),
+                other => other,
+            };
+            doc = replaced(next);
+        }
+        render(&doc)
+    }
+}
+
+fn with_body(case: &Case, body: Body) -> Case {
+    Case { request: Request { body: Some(body), ..case.request.clone() }, ..case.clone() }
+}
+
+fn with_query(case: &Case, query: Vec<(String, String)>) -> Case {
+    Case { request: Request { query, ..case.request.clone() }, ..case.clone() }
+}
+
+fn with_headers(case: &Case, headers: Vec<(String, String)>) -> Case {
+    Case { request: Request { headers, ..case.request.clone() }, ..case.clone() }
+}
+
+/// Shrink the finding `o` until a step no longer reproduces it or its share
+/// of the budget runs out. `None` when nothing could be removed, or when the
+/// smaller case no longer fires when sent again.
+pub async fn shrink(transport: &Arc<Transport>, sched: &Arc<Scheduler>, oracles: &Oracles, o: &Outcome) -> Option<Shrunk> {
+    let oracle = target(o)?.to_string();
+    let mut probe = Probe {
+        transport: Arc::clone(transport),
+        sched: Arc::clone(sched),
+        oracles,
+        oracle: oracle.clone(),
+        index: o.index,
+        attempts: 0,
+        spent: false,
+    };
+    let case = o.case.clone();
+    let query = case.request.query.clone();
+    let case = probe.pairs(case, with_query, query).await;
+    let headers = case.request.headers.clone();
+    let case = probe.pairs(case, with_headers, headers).await;
+    let case = probe.body(case).await;
+    if case.request.size() >= o.case.request.size() || !probe.confirm(&case).await {
+        return None;
+    }
+    Some(Shrunk { oracle, attempts: probe.attempts, case })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oracle::Signal;
+    use crate::plan;
+    use crate::testutil;
+    use crate::Profile;
+    use std::time::{Duration, Instant};
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A server that answers 500 to bodies containing `trigger`, at most
+    /// `times` times, and 200 to everything else.
+    async fn server(trigger: &str, times: Option<u64>) -> MockServer {
+        let server = MockServer::start().await;
+        let fails = Mock::given(body_string_contains(trigger)).respond_with(ResponseTemplate::new(500));
+        match times {
+            Some(n) => fails.up_to_n_times(n).with_priority(1).mount(&server).await,
+            None => fails.with_priority(1).mount(&server).await,
+        }
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        server
+    }
+
+    /// A `status.5xx` finding on `body`, as the run judged it.
+    fn finding(p: &Profile, body: &str) -> Outcome {
+        let mut case = plan::baseline(&p.endpoints[0]);
+        case.request.query = vec![("page".into(), "1".into()), ("trace".into(), "x".repeat(40))];
+        case.request.headers = vec![("X-Request-Id".into(), "r".repeat(40))];
+        case.request.body = Some(Body::Bytes(body.into()));
+        let signal = Signal { oracle: "status.5xx".into(), verdict: Verdict::Finding, detail: "server error 500".into() };
+        Outcome { index: 0, case, result: Err(anyhow::anyhow!("not sent")), signals: vec![signal] }
+    }
+
+    async fn minimise(p: &Profile, sched: &Arc<Scheduler>, o: &Outcome) -> Option<Shrunk> {
+        let transport = Arc::new(testutil::transport(p));
+        let oracles = Oracles::new(&p.oracles, &[]).unwrap();
+        shrink(&transport, sched, &oracles, o).await
+    }
+
+    #[tokio::test]
+    async fn the_minimised_case_still_fires_when_sent() {
+        let server = server("boom", None).await;
+        let p = testutil::profile(&server.uri());
+        let o = finding(&p, r#"{"a":"padding padding padding","b":[1,2,3],"c":"boom"}"#);
+
+        let shrunk = minimise(&p, &Scheduler::new(&p.limits), &o).await.unwrap();
+
+        assert_eq!(shrunk.case.request.body.as_ref().unwrap().head(64), br#"{"c":"boom"}"#);
+        assert!(shrunk.case.request.query.is_empty() && shrunk.case.request.headers.is_empty());
+        let received = server.received_requests().await.unwrap();
+        assert_eq!(received.len() as u32, shrunk.attempts);
+        // The last request sent is the confirmation of the result.
+        assert_eq!(received.last().unwrap().body, br#"{"c":"boom"}"#);
+    }
+
+    #[tokio::test]
+    async fn bodies_that_do_not_round_trip_are_minimised_as_text() {
+        // Only the spelling with a space fires, so re-serialising would lose it.
+        let server = server(r#""c": "boom""#, None).await;
+        let p = testutil::profile(&server.uri());
+        let o = finding(&p, r#"{"c": "boom", "a": [1, 2, 3], "b": "padding padding"}"#);
+
+        let shrunk = minimise(&p, &Scheduler::new(&p.limits), &o).await.unwrap();
+
+        let body = String::from_utf8(shrunk.case.request.body.as_ref().unwrap().head(64)).unwrap();
+        assert_eq!(body, r#""c": "boom""#);
+    }
+
+    #[tokio::test]
+    async fn a_result_that_no_longer_fires_is_dropped() {
+        // The bug goes away after its first reproduction.
+        let server = server("boom", Some(1)).await;
+        let p = testutil::profile(&server.uri());
+        let o = finding(&p, r#"{"a":"padding padding padding","b":[1,2,3],"c":"boom"}"#);
+
+        assert!(minimise(&p, &Scheduler::new(&p.limits), &o).await.is_none());
+        assert!(!server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shrinking_stays_within_minimize_budget_and_rate() {
+        let server = server("boom", None).await;
+        let mut p = testutil::profile(&server.uri());
+        p.limits.request_budget = 12;
+        p.limits.minimize_budget = Some(3);
+        p.limits.rate_per_sec = 20;
+        let sched = Scheduler::new(&p.limits);
+        let transport = Arc::new(testutil::transport(&p));
+        // The planned cases take everything but the minimiser's share.
+        let planned = (0..p.limits.case_budget() as usize).map(|i| (i, plan::baseline(&p.endpoints[0]))).collect();
+        runner::run(Arc::clone(&transport), Arc::clone(&sched), 0, planned).await;
+        let o = finding(&p, r#"{"a":"padding padding padding","b":[1,2,3],"c":"boom"}"#);
+
+        let started = Instant::now();
+        let shrunk = minimise(&p, &sched, &o).await;
+        let elapsed = started.elapsed();
+
+        // Dropping the query and headers takes two; the last is the final check.
+        let shrunk = shrunk.expect("confirmed within the budget");
+        assert!(shrunk.case.request.query.is_empty() && shrunk.case.request.headers.is_empty());
+        assert_eq!(shrunk.attempts, 3);
+        assert_eq!(sched.snapshot().issued, p.limits.request_budget);
+        assert_eq!(server.received_requests().await.unwrap().len(), 12);
+        // Three requests at 20 per second are at least two intervals apart.
+        assert!(elapsed >= Duration::from_millis(2 * 50), "{elapsed:?}");
+    }
+}
@@ -0,0 +1,452 @@
+//! Typed mutations of a seed JSON body.
+//!
+//! Every operator has a stable ID (`family.name`) that is recorded on the
+//! case it produces, so a finding can be traced back to the operator.
+
+use serde_json::{Map, Number, Value};
+
+use crate::rng::Rng;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// What an operator does to the node it targets.
+#[derive(Debug, Clone)]
+pub enum Patch {
+    Set(Value),
+    /// Raw JSON text, for values `serde_json::Value` cannot hold (`-0`, lone surrogates, overflow)
+    Raw(String),
+    Remove,
+    /// Repeat the member's key with this value right after the original
+    Duplicate(Value),
+}
+
+pub struct Operator {
+    pub id: &'static str,
+    applies: fn(&Value, &[Step]) -> bool,
+    patch: fn(&Value) -> Patch,
+}
+
+fn is_member(_: &Value, at: &[Step]) -> bool {
+    matches!(at.last(), Some(Step::Key(_)))
+}
+
+fn is_string(v: &Value, _: &[Step]) -> bool {
+    v.is_string()
+}
+
+fn is_number(v: &Value, _: &[Step]) -> bool {
+    v.is_number()
+}
+
+fn is_scalar(v: &Value, _: &[Step]) -> bool {
+    !v.is_object() && !v.is_array()
+}
+
+pub static OPERATORS: &[Operator] = &[
+    Operator {
+        id: "type.str-to-num",
+        applies: is_string,
+        patch: |_| Patch::Set(Value::from(1337)),
+    },
+    Operator {
+        id: "type.num-to-str",
+        applies: is_number,
+        patch: |v| Patch::Set(Value::String(v.to_string())),
+    },
+    Operator {
+        id: "type.obj-to-arr",
+        applies: |v, _| v.is_object(),
+        patch: |v| Patch::Set(Value::Array(v.as_object().map(|m| m.values().cloned().collect()).unwrap_or_default())),
+    },
+    Operator {
+        id: "type.arr-to-obj",
+        applies: |v, _| v.is_array(),
+        patch: |v| {
+            let items = v.as_array().cloned().unwrap_or_default();
+            Patch::Set(Value::Object(items.into_iter().enumerate().map(|(i, x)| (i.to_string(), x)).collect::<Map<_, _>>()))
+        },
+    },
+    Operator {
+        id: "type.scalar-to-obj",
+        applies: is_scalar,
+        patch: |v| Patch::Set(serde_json::json!({ "value": v })),
+    },
+    Operator {
+        id: "key.drop",
+        applies: is_member,
+        patch: |_| Patch::Remove,
+    },
+    Operator {
+        id: "key.dup",
+        applies: is_member,
+        patch: |v| Patch::Duplicate(if v.is_string() { Value::from(0) } else { Value::String("fuzzkit".into()) }),
+    },
+    Operator {
+        id: "int.max",
+        applies: is_number,
+        patch: |_| Patch::Set(Value::from(i64::MAX)),
+    },
+    Operator {
+        id: "int.min",
+        applies: is_number,
+        patch: |_| Patch::Set(Value::from(i64::MIN)),
+    },
+    Operator {
+        id: "int.overflow",
+        applies: is_number,
+        patch: |_| Patch::Raw("9223372036854775808".into()),
+    },
+    Operator {
+        id: "int.neg-zero",
+        applies: is_number,
+        patch: |_| Patch::Raw("-0".into()),
+    },
+    Operator {
+        id: "num.nan-str",
+        applies: is_number,
+        patch: |_| Patch::Set(Value::String("NaN".into())),
+    },
+    Operator {
+        id: "num.inf-str",
+        applies: is_number,
+        patch: |_| Patch::Set(Value::String("Infinity".into())),
+    },
+    Operator {
+        id: "num.tiny-float",
+        applies: is_number,
+        patch: |_| Patch::Set(Value::Number(Number::from_f64(f64::MIN_POSITIVE).expect("finite"))),
+    },
+    Operator {
+        id: "unicode.rtl",
+        applies: is_string,
+        patch: |_| Patch::Set(Value::String("\u{202E}fuzzkit\u{202C}".into())),
+    },
+    Operator {
+        id: "unicode.zero-width",
+        applies: is_string,
+        patch: |_| Patch::Set(Value::String("fuzz\u{200B}\u{200D}\u{FEFF}kit".into())),
+    },
+    Operator {
+        id: "unicode.combining",
+        applies: is_string,
+        patch: |_| Patch::Set(Value::String(format!("e{}", "\u{0301}".repeat(64)))),
+    },
+    Operator {
+        id: "unicode.astral",
+        applies: is_string,
+        patch: |_| Patch::Set(Value::String("\u{1F4A9}\u{10FFFF}".into())),
+    },
+    Operator {
+        id: "unicode.nul",
+        applies: is_string,
+        patch: |_| Patch::Set(Value::String("fuzz\u{0}kit".into())),
+    },
+    Operator {
+        id: "unicode.lone-surrogate",
+        applies: is_string,
+        patch: |_| Patch::Raw(r#""\ud800""#.into()),
+    },
+    Operator {
+        id: "null.set",
+        applies: |v, at| !v.is_null() && !at.is_empty(),
+        patch: |_| Patch::Set(Value::Null),
+    },
+    Operator {
+        id: "null.fill",
+        applies: |v, _| v.is_null(),
+        patch: |_| Patch::Set(Value::String("null".into())),
+    },
+];
+
+/// One mutated body, attributed to the operator and JSON pointer that produced it.
+#[derive(Debug, Clone)]
+pub struct Mutation {
+    pub operator: String,
+    pub pointer: String,
+    pub body: Vec<u8>,
+}
+
+/// Apply every operator to every node it applies to, in document order.
+pub fn mutations(seed: &Value) -> Vec<Mutation> {
+    let mut out = Vec::new();
+    for (at, node) in &nodes(seed) {
+        for op in OPERATORS.iter().filter(|op| (op.applies)(node, at)) {
+            out.push(Mutation {
+                operator: op.id.to_string(),
+                pointer: pointer(at),
+                body: render(seed, at, &(op.patch)(node)).into_bytes(),
+            });
+        }
+    }
+    out
+}
+
+/// Stack one to three randomly chosen mutations. The operator ID is the
+/// stacked IDs joined with `+`, and the target lists each pointer in order.
+/// Each step targets a node neither inside nor above an earlier one, so no
+/// step undoes another. Stops after a step whose text would not come back
+/// byte-for-byte from `Value` (`-0`, overflow, a duplicated key, a lone
+/// surrogate), so every operator in the ID shows in the body.
+pub fn havoc(seed: &Value, rng: &mut Rng) -> Option<Mutation> {
+    let mut doc = seed.clone();
+    let (mut ops, mut paths, mut body) = (Vec::new(), Vec::<Vec<Step>>::new(), String::new());
+    for _ in 0..1 + rng.below(3) {
+        let (id, at, text) = {
+            let mut candidates: Vec<(&Operator, Vec<Step>, &Value)> = Vec::new();
+            for (at, v) in nodes(&doc) {
+                if paths.iter().any(|p| at.starts_with(p) || p.starts_with(&at)) {
+                    continue;
+                }
+                for op in OPERATORS.iter().filter(|op| (op.applies)(v, &at)) {
+                    candidates.push((op, at.clone(), v));
+                }
+            }
+            if candidates.is_empty() {
+                break;
+            }
+            let (op, at, node) = rng.pick(&candidates);
+            (op.id, at.clone(), render(&doc, at, &(op.patch)(node)))
+        };
+        ops.push(id);
+        paths.push(at);
+        body = text;
+        match serde_json::from_str::<Value>(&body) {
+            Ok(next) if serde_json::to_vec(&next).ok().as_deref() == Some(body.as_bytes()) => doc = next,
+            _ => break,
+        }
+    }
+    let pointers: Vec<String> = paths.iter().map(|at| pointer(at)).collect();
+    (!ops.is_empty()).then(|| Mutation { operator: ops.join("+"), pointer: pointers.join(","), body: body.into_bytes() })
+}
+
+/// Every node of `v` with its path, in document order (root first).
+pub fn nodes(v: &Value) -> Vec<(Vec<Step>, &Value)> {
+    let mut out = Vec::new();
+    collect(v, &mut Vec::new(), &mut out);
+    out
+}
+
+fn collect<'a>(v: &'a Value, at: &mut Vec<Step>, out: &mut Vec<(Vec<Step>, &'a Value)>) {
+    out.push((at.clone(), v));
+    match v {
+        Value::Object(m) => {
+            for (k, child) in m {
+                at.push(Step::Key(k.clone()));
+                collect(child, at, out);
+                at.pop();
+            }
+        }
+        Value::Array(items) => {
+            for (i, child) in items.iter().enumerate() {
+                at.push(Step::Index(i));
+                collect(child, at, out);
+                at.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// RFC 6901 JSON pointer for a path.
+pub fn pointer(at: &[Step]) -> String {
+    at.iter()
+        .map(|s| match s {
+            Step::Key(k) => format!("/{}", k.replace('~', "~0").replace('/', "~1")),
+            Step::Index(i) => format!("/{i}"),
+        })
+        .collect()
+}
+
+/// Serialise `v` with `patch` applied at `at`. Written by hand because the
+/// output may contain duplicate keys or raw text that `Value` cannot model.
+pub fn render(v: &Value, at: &[Step], patch: &Patch) -> String {
+    let mut out = String::new();
+    write(v, at, patch, &mut out);
+    out
+}
+
+fn write(v: &Value, at: &[Step], patch: &Patch, out: &mut String) {
+    if at.is_empty() {
+        match patch {
+            Patch::Set(x) => out.push_str(&x.to_string()),
+            Patch::Raw(raw) => out.push_str(raw),
+            // Removing or duplicating the root leaves nothing sensible; send it unchanged.
+            Patch::Remove | Patch::Duplicate(_) => out.push_str(&v.to_string()),
+        }
+        return;
+    }
+    match (v, &at[0]) {
+        (Value::Object(m), Step::Key(target)) => {
+            out.push('{');
+            let mut first = true;
+            let mut member = |out: &mut String, k: &str, write_value: &dyn Fn(&mut String)| {
+                if !std::mem::take(&mut first) {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(k.to_string()).to_string());
+                out.push(':');
+                write_value(out);
+            };
+            for (k, child) in m {
+                if k != target {
+                    member(out, k, &|o| o.push_str(&child.to_string()));
+                    continue;
+                }
+                match (at.len(), patch) {
+                    (1, Patch::Remove) => {}
+                    (1, Patch::Duplicate(dup)) => {
+                        member(out, k, &|o| o.push_str(&child.to_string()));
+                        member(out, k, &|o| o.push_str(&dup.to_string()));
+                    }
+                    _ => member(out, k, &|o| write(child, &at[1..], patch, o)),
+                }
+            }
+            out.push('}');
+        }
+        (Value::Array(items), Step::Index(target)) => {
+            out.push('[');
+            let mut first = true;
+            for (i, child) in items.iter().enumerate() {
+                if i == *target && at.len() == 1 && matches!(patch, Patch::Remove) {
+                    continue;
+                }
+                if !std::mem::take(&mut first) {
+                    out.push(',');
+                }
+                if i == *target {
+                    write(child, &at[1..], patch, out);
+                } else {
+                    out.push_str(&child.to_string());
+                }
+            }
+            out.push(']');
+        }
+        // Path does not match the document shape; leave the node untouched.
+        _ => out.push_str(&v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The body `operator` produced at `pointer`, as text.
+    fn body(seed: &Value, operator: &str, pointer: &str) -> String {
+        let m = mutations(seed)
+            .into_iter()
+            .find(|m| m.operator == operator && m.pointer == pointer)
+            .unwrap_or_else(|| panic!("no {operator} at {pointer:?}"));
+        String::from_utf8(m.body).unwrap()
+    }
+
+    #[test]
+    fn type_operators_swap_the_node_type() {
+        let seed = json!({"amount": 150, "pin": "A1", "lines": [1, 2], "meta": {"a": 1}});
+
+        assert_eq!(body(&seed, "type.str-to-num", "/pin"), r#"{"amount":150,"lines":[1,2],"meta":{"a":1},"pin":1337}"#);
+        assert_eq!(body(&seed, "type.num-to-str", "/amount"), r#"{"amount":"150","lines":[1,2],"meta":{"a":1},"pin":"A1"}"#);
+        assert_eq!(body(&seed, "type.obj-to-arr", "/meta"), r#"{"amount":150,"lines":[1,2],"meta":[1],"pin":"A1"}"#);
+        assert_eq!(body(&seed, "type.arr-to-obj", "/lines"), r#"{"amount":150,"lines":{"0":1,"1":2},"meta":{"a":1},"pin":"A1"}"#);
+        assert_eq!(body(&seed, "type.scalar-to-obj", "/pin"), r#"{"amount":150,"lines":[1,2],"meta":{"a":1},"pin":{"value":"A1"}}"#);
+    }
+
+    #[test]
+    fn number_operators_emit_boundary_values_verbatim() {
+        let seed = json!({"amount": 150});
+
+        assert_eq!(body(&seed, "int.max", "/amount"), r#"{"amount":9223372036854775807}"#);
+        assert_eq!(body(&seed, "int.min", "/amount"), r#"{"amount":-9223372036854775808}"#);
+        assert_eq!(body(&seed, "int.overflow", "/amount"), r#"{"amount":9223372036854775808}"#);
+        assert_eq!(body(&seed, "int.neg-zero", "/amount"), r#"{"amount":-0}"#);
+        assert_eq!(body(&seed, "num.nan-str", "/amount"), r#"{"amount":"NaN"}"#);
+        assert_eq!(body(&seed, "num.inf-str", "/amount"), r#"{"amount":"Infinity"}"#);
+        assert_eq!(body(&seed, "num.tiny-float", "/amount"), r#"{"amount":2.2250738585072014e-308}"#);
+    }
+
+    #[test]
+    fn unicode_operators_inject_edge_case_strings() {
+        let seed = json!({"name": "x"});
+        let decoded = |op: &str| serde_json::from_str::<Value>(&body(&seed, op, "/name")).unwrap()["name"].as_str().unwrap().to_string();
+
+        assert_eq!(decoded("unicode.rtl"), "\u{202E}fuzzkit\u{202C}");
+        assert_eq!(decoded("unicode.zero-width"), "fuzz\u{200B}\u{200D}\u{FEFF}kit");
+        assert_eq!(decoded("unicode.combining").chars().count(), 65);
+        assert_eq!(decoded("unicode.astral"), "\u{1F4A9}\u{10FFFF}");
+        assert_eq!(body(&seed, "unicode.nul", "/name"), r#"{"name":"fuzz\u0000kit"}"#);
+        // Not valid UTF-16 once decoded, so only the raw text can carry it.
+        assert_eq!(body(&seed, "unicode.lone-surrogate", "/name"), r#"{"name":"\ud800"}"#);
+    }
+
+    #[test]
+    fn key_operators_drop_and_duplicate_members() {
+        let seed = json!({"amount": 150, "pin": "A1"});
+
+        assert_eq!(body(&seed, "key.drop", "/pin"), r#"{"amount":150}"#);
+        assert_eq!(body(&seed, "key.dup", "/pin"), r#"{"amount":150,"pin":"A1","pin":0}"#);
+        assert_eq!(body(&seed, "key.dup", "/amount"), r#"{"amount":150,"amount":"fuzzkit","pin":"A1"}"#);
+        // Array items and the root are not members.
+        assert!(!mutations(&json!([1])).iter().any(|m| m.operator.starts_with("key.")));
+    }
+
+    #[test]
+    fn null_operators_set_and_fill_nulls() {
+        let seed = json!({"note": null, "pin": "A1"});
+
+        assert_eq!(body(&seed, "null.set", "/pin"), r#"{"note":null,"pin":null}"#);
+        assert_eq!(body(&seed, "null.fill", "/note"), r#"{"note":"null","pin":"A1"}"#);
+        assert!(!mutations(&seed).iter().any(|m| m.operator == "null.set" && m.pointer.is_empty()));
+    }
+
+    #[test]
+    fn nested_nodes_are_mutated_in_place_and_pointed_at() {
+        let seed = json!({"lines": [{"a/b": 1, "code": "X"}], "pin": "A1"});
+
+        assert_eq!(body(&seed, "int.neg-zero", "/lines/0/a~1b"), r#"{"lines":[{"a/b":-0,"code":"X"}],"pin":"A1"}"#);
+        assert_eq!(body(&seed, "key.drop", "/lines/0/code"), r#"{"lines":[{"a/b":1}],"pin":"A1"}"#);
+        assert_eq!(body(&seed, "key.dup", "/lines/0/code"), r#"{"lines":[{"a/b":1,"code":"X","code":0}],"pin":"A1"}"#);
+        assert_eq!(body(&seed, "type.obj-to-arr", "/lines/0"), r#"{"lines":[[1,"X"]],"pin":"A1"}"#);
+        // Every operator that applies somewhere is reached, at every depth.
+        let pointers: Vec<String> = mutations(&seed).into_iter().map(|m| m.pointer).collect();
+        for expected in ["", "/lines", "/lines/0", "/lines/0/a~1b", "/lines/0/code", "/pin"] {
+            assert!(pointers.iter().any(|p| p == expected), "nothing mutated at {expected:?}");
+        }
+    }
+
+    #[test]
+    fn every_operator_in_a_havoc_id_shows_in_its_body() {
+        let seed = json!({"amount": 150, "pin": "A1", "lines": [1, {"sku": "x-1", "qty": 2}], "meta": {"note": null}});
+        let mut stacked = 0;
+        for n in 0..500 {
+            let m = havoc(&seed, &mut Rng::for_case(7, n)).unwrap();
+            let ids: Vec<&str> = m.operator.split('+').collect();
+            let pointers: Vec<&str> = m.pointer.split(',').collect();
+            assert_eq!(ids.len(), pointers.len(), "{}", m.operator);
+            stacked += usize::from(ids.len() > 1);
+
+            // Replay the steps: no target sits inside or above another, and
+            // every step but the last leaves text `Value` gives back verbatim.
+            let mut doc = seed.clone();
+            let mut text = String::new();
+            for (i, (id, at)) in ids.iter().zip(&pointers).enumerate() {
+                for earlier in &pointers[..i] {
+                    let nested = |a: &str, b: &str| a == b || b.starts_with(&format!("{a}/"));
+                    assert!(!nested(earlier, at) && !nested(at, earlier), "{} at {}", m.operator, m.pointer);
+                }
+                if i > 0 {
+                    doc = serde_json::from_str(&text).unwrap();
+                    assert_eq!(doc.to_string(), text, "{} at {}", m.operator, m.pointer);
+                }
+                let op = OPERATORS.iter().find(|op| op.id == *id).unwrap();
+                let (path, node) = nodes(&doc).into_iter().find(|(p, _)| pointer(p) == *at).unwrap();
+                text = render(&doc, &path, &(op.patch)(node));
+            }
+            assert_eq!(text.as_bytes(), m.body, "{} at {}", m.operator, m.pointer);
+        }
+        assert!(stacked > 100, "only {stacked} stacked mutations");
+    }
+}
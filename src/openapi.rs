@@ -0,0 +1,385 @@
+//! `import openapi`: turn an OpenAPI 3 or Swagger 2 spec (JSON or YAML) into
+//! a draft profile with one endpoint per operation, seeded from the spec's
+//! examples and schemas.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::endpoint::{Endpoint, Param, ParamLocation};
+use crate::schema::{Generation, Kind, Schema};
+
+/// Schemas nested deeper than this, and `$ref` chains longer, are cut off.
+const MAX_DEPTH: usize = 16;
+const METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+pub struct Options {
+    pub name: Option<String>,
+    pub base_url: Option<String>,
+    /// Operations with other methods are left out of the profile
+    pub methods: Vec<String>,
+    pub generation: Generation,
+}
+
+/// The profile sections the importer writes; everything safety-related is
+/// set to its most conservative value for a human to review.
+#[derive(Serialize)]
+struct Draft {
+    name: String,
+    base_url: String,
+    endpoints: Vec<Endpoint>,
+    limits: DraftLimits,
+    timeouts: DraftTimeouts,
+    safety: DraftSafety,
+}
+
+#[derive(Serialize)]
+struct DraftLimits {
+    concurrency: usize,
+    rate_per_sec: u32,
+    request_budget: u32,
+    max_rate_per_sec: u32,
+    allowed_methods: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DraftTimeouts {
+    connect_ms: u64,
+    read_ms: u64,
+}
+
+#[derive(Serialize)]
+struct DraftSafety {
+    require_sandbox_flag: bool,
+    allowlist_hosts: Vec<String>,
+    force_headers: HashMap<String, String>,
+}
+
+/// Read a spec file; YAML unless it parses as JSON.
+fn load(path: &Path) -> Result<Value> {
+    let raw = fs::read_to_string(path).with_context(|| format!("failed to read spec {}", path.display()))?;
+    if let Ok(v) = serde_json::from_str(&raw) {
+        return Ok(v);
+    }
+    let yaml: serde_yaml::Value = serde_yaml::from_str(&raw).context("spec is neither JSON nor YAML")?;
+    Ok(yaml_to_json(yaml))
+}
+
+/// YAML maps may have non-string keys (`200:` under `responses`).
+fn yaml_to_json(v: serde_yaml::Value) -> Value {
+    use serde_yaml::Value as Y;
+    match v {
+        Y::Null => Value::Null,
+        Y::Bool(b) => Value::Bool(b),
+        Y::Number(n) => n
+            .as_i64()
+            .map(Value::from)
+            .or_else(|| n.as_u64().map(Value::from))
+            .unwrap_or_else(|| Value::from(n.as_f64().unwrap_or_default())),
+        Y::String(s) => Value::String(s),
+        Y::Sequence(items) => Value::Array(items.into_iter().map(yaml_to_json).collect()),
+        Y::Mapping(m) => Value::Object(
+            m.into_iter()
+                .map(|(k, v)| {
+                    let key = match yaml_to_json(k) {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    (key, yaml_to_json(v))
+                })
+                .collect(),
+        ),
+        Y::Tagged(t) => yaml_to_json(t.value),
+    }
+}
+
+/// TOML has no null, so nulls are dropped from examples.
+fn without_nulls(v: &Value) -> Option<Value> {
+    match v {
+        Value::Null => None,
+        Value::Array(items) => Some(Value::Array(items.iter().filter_map(without_nulls).collect())),
+        Value::Object(m) => Some(Value::Object(
+            m.iter().filter_map(|(k, v)| without_nulls(v).map(|v| (k.clone(), v))).collect(),
+        )),
+        other => Some(other.clone()),
+    }
+}
+
+struct Spec {
+    doc: Value,
+}
+
+impl Spec {
+    fn is_swagger2(&self) -> bool {
+        self.doc.get("swagger").is_some()
+    }
+
+    /// Follow a local `$ref` (`#/components/schemas/X`, `#/definitions/X`, ...).
+    fn deref<'a>(&'a self, v: &'a Value) -> Result<&'a Value> {
+        let mut v = v;
+        for _ in 0..MAX_DEPTH {
+            let Some(r) = v.get("$ref").and_then(Value::as_str) else { return Ok(v) };
+            let Some(pointer) = r.strip_prefix('#') else { bail!("external $ref not supported: {r}") };
+            v = self.doc.pointer(pointer).with_context(|| format!("dangling $ref {r}"))?;
+        }
+        bail!("$ref chain too deep")
+    }
+
+    fn base_url(&self) -> Option<String> {
+        if self.is_swagger2() {
+            let host = self.doc.get("host")?.as_str()?;
+            let scheme = self.doc["schemes"].as_array().and_then(|s| s.first()).and_then(Value::as_str).unwrap_or("https");
+            let base = self.doc.get("basePath").and_then(Value::as_str).unwrap_or("");
+            return Some(format!("{scheme}://{host}{base}"));
+        }
+        let server = self.doc["servers"].as_array()?.first()?;
+        let mut url = server.get("url")?.as_str()?.to_string();
+        // Fill server variables with their defaults.
+        if let Some(vars) = server.get("variables").and_then(Value::as_object) {
+            for (name, var) in vars {
+                let default = var.get("default").and_then(Value::as_str).unwrap_or_default();
+                url = url.replace(&format!("{{{name}}}"), default);
+            }
+        }
+        url.starts_with("http").then_some(url)
+    }
+
+    /// Convert a spec schema to the subset endpoints keep.
+    fn schema(&self, v: &Value) -> Result<Schema> {
+        self.schema_in(v, 0, &mut Vec::new())
+    }
+
+    /// `refs` is the chain of `$ref`s being expanded; a schema that refers
+    /// back into it (a tree node's children) becomes an open object.
+    fn schema_in(&self, v: &Value, depth: usize, refs: &mut Vec<String>) -> Result<Schema> {
+        let open = Schema { kind: Some(Kind::Object), ..Schema::default() };
+        if depth > MAX_DEPTH {
+            return Ok(open);
+        }
+        let r = v.get("$ref").and_then(Value::as_str).map(String::from);
+        if let Some(r) = &r {
+            if refs.contains(r) {
+                return Ok(open);
+            }
+            refs.push(r.clone());
+        }
+        let out = self.expand(self.deref(v)?, depth, refs);
+        if r.is_some() {
+            refs.pop();
+        }
+        out
+    }
+
+    fn expand(&self, v: &Value, depth: usize, refs: &mut Vec<String>) -> Result<Schema> {
+        let mut out = Schema::default();
+        if let Some(parts) = v.get("allOf").and_then(Value::as_array) {
+            for part in parts {
+                let part = self.schema_in(part, depth + 1, refs)?;
+                out.kind = out.kind.or(part.kind);
+                out.properties.extend(part.properties);
+                out.required.extend(part.required);
+                out.additional_properties = out.additional_properties.or(part.additional_properties);
+            }
+        }
+        for key in ["oneOf", "anyOf"] {
+            if let Some(first) = v.get(key).and_then(Value::as_array).and_then(|a| a.first()) {
+                let first = self.schema_in(first, depth + 1, refs)?;
+                out = Schema { properties: out.properties.into_iter().chain(first.properties.clone()).collect(), ..first };
+            }
+        }
+        let kind = match v.get("type") {
+            Some(Value::String(t)) => Some(t.as_str()),
+            // OpenAPI 3.1: `type: [string, "null"]`
+            Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).find(|t| *t != "null"),
+            _ => None,
+        };
+        out.kind = match kind {
+            Some("string") => Some(Kind::String),
+            Some("integer") => Some(Kind::Integer),
+            Some("number") => Some(Kind::Number),
+            Some("boolean") => Some(Kind::Boolean),
+            Some("array") => Some(Kind::Array),
+            Some("object") => Some(Kind::Object),
+            _ => out.kind,
+        };
+        let str_of = |k: &str| v.get(k).and_then(Value::as_str).map(String::from);
+        let u64_of = |k: &str| v.get(k).and_then(Value::as_u64);
+        out.format = str_of("format").or(out.format);
+        out.pattern = str_of("pattern").or(out.pattern);
+        out.min_length = u64_of("minLength").or(out.min_length);
+        out.max_length = u64_of("maxLength").or(out.max_length);
+        out.min_items = u64_of("minItems").or(out.min_items);
+        out.max_items = u64_of("maxItems").or(out.max_items);
+        out.minimum = v.get("minimum").and_then(Value::as_f64).or(out.minimum);
+        out.maximum = v.get("maximum").and_then(Value::as_f64).or(out.maximum);
+        // 3.0 and Swagger use boolean flags; 3.1 puts the bound itself there.
+        match v.get("exclusiveMinimum") {
+            Some(Value::Bool(b)) => out.exclusive_minimum = *b,
+            Some(Value::Number(n)) => (out.minimum, out.exclusive_minimum) = (n.as_f64(), true),
+            _ => {}
+        }
+        match v.get("exclusiveMaximum") {
+            Some(Value::Bool(b)) => out.exclusive_maximum = *b,
+            Some(Value::Number(n)) => (out.maximum, out.exclusive_maximum) = (n.as_f64(), true),
+            _ => {}
+        }
+        if let Some(values) = v.get("enum").and_then(Value::as_array) {
+            out.enum_values = values.iter().filter(|x| !x.is_null()).cloned().collect();
+        }
+        if let Some(items) = v.get("items") {
+            out.items = Some(Box::new(self.schema_in(items, depth + 1, refs)?));
+        }
+        if let Some(props) = v.get("properties").and_then(Value::as_object) {
+            for (k, p) in props {
+                out.properties.insert(k.clone(), self.schema_in(p, depth + 1, refs)?);
+            }
+        }
+        if let Some(req) = v.get("required").and_then(Value::as_array) {
+            out.required.extend(req.iter().filter_map(Value::as_str).map(String::from));
+        }
+        if let Some(Value::Bool(b)) = v.get("additionalProperties") {
+            out.additional_properties = Some(*b);
+        }
+        let example = v
+            .get("example")
+            .or_else(|| v.get("examples").and_then(Value::as_array).and_then(|e| e.first()))
+            .or_else(|| v.get("default"));
+        out.example = example.and_then(without_nulls).or(out.example);
+        Ok(out)
+    }
+
+    /// Path- and operation-level parameters, the operation's overriding.
+    fn params(&self, path_item: &Value, op: &Value) -> Result<Vec<Param>> {
+        let mut merged: Vec<&Value> = Vec::new();
+        for list in [path_item.get("parameters"), op.get("parameters")].into_iter().flatten() {
+            for p in list.as_array().into_iter().flatten() {
+                let p = self.deref(p)?;
+                merged.retain(|q| q.get("name") != p.get("name") || q.get("in") != p.get("in"));
+                merged.push(p);
+            }
+        }
+        let mut out = Vec::new();
+        for p in merged {
+            let location = match p.get("in").and_then(Value::as_str) {
+                Some("path") => ParamLocation::Path,
+                Some("query") => ParamLocation::Query,
+                _ => continue,
+            };
+            let name = p.get("name").and_then(Value::as_str).context("parameter without a name")?.to_string();
+            // Swagger 2 puts the schema keywords on the parameter itself.
+            let schema = self.schema(p.get("schema").unwrap_or(p))?;
+            let value = p.get("example").and_then(without_nulls).unwrap_or_else(|| schema.example());
+            out.push(Param { name, location, value, schema: Some(schema) });
+        }
+        Ok(out)
+    }
+
+    /// The JSON request body schema, if the operation takes one.
+    fn body(&self, op: &Value) -> Result<Option<(Schema, Option<Value>)>> {
+        if self.is_swagger2() {
+            let body = op["parameters"].as_array().into_iter().flatten().find(|p| p.get("in") == Some(&"body".into()));
+            return match body.and_then(|b| b.get("schema")) {
+                Some(s) => Ok(Some((self.schema(s)?, None))),
+                None => Ok(None),
+            };
+        }
+        let Some(body) = op.get("requestBody") else { return Ok(None) };
+        let body = self.deref(body)?;
+        let Some(content) = body.get("content").and_then(Value::as_object) else { return Ok(None) };
+        let Some((_, media)) = content.iter().find(|(ct, _)| ct.starts_with("application/json") || ct.ends_with("+json"))
+        else {
+            return Ok(None);
+        };
+        let example = media
+            .get("example")
+            .or_else(|| media.get("examples").and_then(Value::as_object).and_then(|e| e.values().next()?.get("value")))
+            .and_then(without_nulls);
+        Ok(Some((self.schema(media.get("schema").unwrap_or(&Value::Null))?, example)))
+    }
+}
+
+/// Build the draft profile and render it as TOML.
+pub fn import(spec_path: &Path, opts: &Options) -> Result<String> {
+    let spec = Spec { doc: load(spec_path)? };
+    if spec.doc.get("openapi").is_none() && !spec.is_swagger2() {
+        bail!("{} is not an OpenAPI 3 or Swagger 2 document", spec_path.display());
+    }
+    let base_url = opts
+        .base_url
+        .clone()
+        .or_else(|| spec.base_url())
+        .context("spec has no absolute server URL; pass --base-url")?;
+    let host = url::Url::parse(&base_url)
+        .ok()
+        .and_then(|u| u.host_str().map(String::from))
+        .with_context(|| format!("invalid base URL: {base_url}"))?;
+
+    let mut endpoints = Vec::new();
+    let empty = Map::new();
+    let paths = spec.doc.get("paths").and_then(Value::as_object).unwrap_or(&empty);
+    for (path, item) in paths {
+        let item = spec.deref(item)?;
+        for method in METHODS {
+            let Some(op) = item.get(*method) else { continue };
+            let method = method.to_uppercase();
+            if !opts.methods.iter().any(|m| m.eq_ignore_ascii_case(&method)) {
+                tracing::info!(target: "openapi", "skipping {method} {path}: method not in --methods");
+                continue;
+            }
+            let params = spec.params(item, op).with_context(|| format!("{method} {path}"))?;
+            let body = spec.body(op).with_context(|| format!("{method} {path}"))?;
+            let (schema, seed_body) = match body {
+                Some((schema, example)) => {
+                    let seed = example.unwrap_or_else(|| schema.example());
+                    (Some(schema), without_nulls(&seed))
+                }
+                None => (None, None),
+            };
+            endpoints.push(Endpoint {
+                path: path.clone(),
+                method,
+                seed_body,
+                params,
+                budget_share: 1.0,
+                schema,
+                generation: opts.generation,
+                captures: Vec::new(),
+            });
+        }
+    }
+    if endpoints.is_empty() {
+        bail!("no operations to import");
+    }
+
+    let title = spec.doc["info"]["title"].as_str().unwrap_or("imported");
+    let name = opts.name.clone().unwrap_or_else(|| {
+        title.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect()
+    });
+    let draft = Draft {
+        name,
+        base_url,
+        endpoints,
+        limits: DraftLimits {
+            concurrency: 1,
+            rate_per_sec: 1,
+            request_budget: 200,
+            max_rate_per_sec: 5,
+            allowed_methods: opts.methods.iter().map(|m| m.to_uppercase()).collect(),
+        },
+        timeouts: DraftTimeouts { connect_ms: 3000, read_ms: 5000 },
+        safety: DraftSafety {
+            require_sandbox_flag: true,
+            allowlist_hosts: vec![host],
+            force_headers: HashMap::from([("X-Env".to_string(), "sandbox".to_string())]),
+        },
+    };
+    let counts: BTreeMap<&str, usize> = draft.endpoints.iter().fold(BTreeMap::new(), |mut m, e| {
+        *m.entry(e.method.as_str()).or_default() += 1;
+        m
+    });
+    tracing::info!(target: "openapi", "imported {} operations {counts:?}; review limits and safety before running", draft.endpoints.len());
+    toml::to_string_pretty(&draft).context("failed to render profile")
+}
@@ -0,0 +1,509 @@
+//! Response oracles. Each oracle looks at one case and its response and may
+//! raise a signal; the case's verdict is the most severe signal raised.
+
+use anyhow::{Context, Result};
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+
+use crate::bypass::Tamper;
+use crate::plan::Case;
+use crate::runner::Outcome;
+use crate::transport::{Body, Response};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Anomaly,
+    Finding,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Verdict::Pass => "pass",
+            Verdict::Anomaly => "anomaly",
+            Verdict::Finding => "finding",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signal {
+    pub oracle: String,
+    pub verdict: Verdict,
+    pub detail: String,
+}
+
+pub trait Oracle: Send {
+    fn id(&self) -> &'static str;
+    /// Default severity; profiles may override it per oracle.
+    fn verdict(&self) -> Verdict;
+    /// What the oracle raises on `resp`, without changing its state.
+    fn check(&self, case: &Case, resp: &Response) -> Option<String>;
+    /// Learn from a judged response; only oracles that track a session keep anything.
+    fn observe(&mut self, _: &Response) {}
+}
+
+/// `[oracles]`
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct OracleConfig {
+    /// Oracle IDs to run; all of them when empty
+    pub enabled: Vec<String>,
+    /// Per-oracle severity override, e.g. `{ "accept.malformed" = "finding" }`
+    pub severity: HashMap<String, Verdict>,
+    /// Extra regexes matched against response bodies, raised as `leak.custom`
+    pub leak_patterns: Vec<String>,
+    /// Flag a response slower than this multiple of the running median
+    pub latency_factor: f64,
+    /// ...and slower than this many milliseconds, so fast APIs don't flap
+    pub latency_floor_ms: u64,
+}
+
+impl Default for OracleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: Vec::new(),
+            severity: HashMap::new(),
+            leak_patterns: Vec::new(),
+            latency_factor: 5.0,
+            latency_floor_ms: 1000,
+        }
+    }
+}
+
+struct ServerError;
+
+impl Oracle for ServerError {
+    fn id(&self) -> &'static str {
+        "status.5xx"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Finding
+    }
+    fn check(&self, _: &Case, resp: &Response) -> Option<String> {
+        (resp.status >= 500).then(|| format!("server error {}", resp.status))
+    }
+}
+
+/// Regex-driven body leak detector, used for stack traces, SQL errors and
+/// the profile's own `leak_patterns`.
+struct Leak {
+    id: &'static str,
+    patterns: RegexSet,
+}
+
+impl Oracle for Leak {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Finding
+    }
+    fn check(&self, _: &Case, resp: &Response) -> Option<String> {
+        let body = String::from_utf8_lossy(&resp.body);
+        let hit = self.patterns.matches(&body).into_iter().next()?;
+        Some(format!("body matches /{}/", self.patterns.patterns()[hit]))
+    }
+}
+
+const STACK_TRACE: &[&str] = &[
+    r"Traceback \(most recent call last\)",
+    r"\bat [\w$.]+\([\w$]+\.(java|kt|scala):\d+\)",
+    r"Exception in thread ",
+    r"\bat .+ in .+:line \d+",
+    r"(?m)^\s+at .+ \(.+\.[cm]?js:\d+:\d+\)",
+    r"\.go:\d+ \+0x[0-9a-f]+",
+    r"panicked at ",
+    r"System\.\w+Exception",
+    r"(?i)stack ?trace:",
+];
+
+const SQL_ERROR: &[&str] = &[
+    r"(?i)you have an error in your sql syntax",
+    r"(?i)syntax error at or near",
+    r"(?i)unclosed quotation mark",
+    r"\bORA-\d{5}\b",
+    r"\bSQLSTATE\[?\w*\]?",
+    r"PG::\w+Error",
+    r"(?i)sqlite3?\.\w*error",
+    r"(?i)\bjava\.sql\.\w+Exception",
+    r"(?i)quoted string not properly terminated",
+];
+
+/// A 2xx for a case that was deliberately malformed.
+struct AcceptedMalformed;
+
+impl Oracle for AcceptedMalformed {
+    fn id(&self) -> &'static str {
+        "accept.malformed"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Anomaly
+    }
+    fn check(&self, case: &Case, resp: &Response) -> Option<String> {
+        // `auth.bypass` and `signature.bypass` judge their own cases.
+        let judged = case.request.auth != Tamper::None || case.request.origin.is_some();
+        (case.is_mutated() && !judged && (200..300).contains(&resp.status))
+            .then(|| format!("{} accepted input from {}", resp.status, case.operator))
+    }
+}
+
+/// A 2xx for a case whose credential was dropped, forged, swapped or misplaced.
+struct AuthBypass;
+
+impl Oracle for AuthBypass {
+    fn id(&self) -> &'static str {
+        "auth.bypass"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Finding
+    }
+    fn check(&self, case: &Case, resp: &Response) -> Option<String> {
+        let tamper = &case.request.auth;
+        // A re-cased header name still carries the valid credential.
+        let forged = !matches!(tamper, Tamper::None | Tamper::Case(_));
+        (forged && (200..300).contains(&resp.status)).then(|| {
+            let target = tamper.target();
+            let target = if target.is_empty() { String::new() } else { format!(" ({target})") };
+            format!("{} despite {}{target}", resp.status, tamper.operator())
+        })
+    }
+}
+
+/// A 401 or 403 for the valid credential under a re-cased header name:
+/// something on the way looks the header up by its exact spelling.
+struct AuthCase;
+
+impl Oracle for AuthCase {
+    fn id(&self) -> &'static str {
+        "auth.case"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Anomaly
+    }
+    fn check(&self, case: &Case, resp: &Response) -> Option<String> {
+        let Tamper::Case(_) = case.request.auth else { return None };
+        matches!(resp.status, 401 | 403).then(|| format!("{} for the credential under a {} header name", resp.status, case.request.auth.target()))
+    }
+}
+
+/// A 2xx for a case mutated after signing, so the signature does not match
+/// what was sent.
+struct SignatureBypass;
+
+impl Oracle for SignatureBypass {
+    fn id(&self) -> &'static str {
+        "signature.bypass"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Finding
+    }
+    fn check(&self, case: &Case, resp: &Response) -> Option<String> {
+        let changed = case.request.origin.as_deref().is_some_and(|o| !o.same_wire(&case.request));
+        (changed && (200..300).contains(&resp.status))
+            .then(|| format!("{} although {} changed the request after signing", resp.status, case.operator))
+    }
+}
+
+/// Latency well above the running median of everything seen so far.
+struct LatencyOutlier {
+    factor: f64,
+    floor: Duration,
+    /// Sorted, so the median is one lookup
+    seen: Vec<Duration>,
+}
+
+impl Oracle for LatencyOutlier {
+    fn id(&self) -> &'static str {
+        "latency.outlier"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Anomaly
+    }
+    fn check(&self, _: &Case, resp: &Response) -> Option<String> {
+        if self.seen.len() < 5 {
+            return None;
+        }
+        let median = self.seen[self.seen.len() / 2];
+        let limit = median.mul_f64(self.factor).max(self.floor);
+        (resp.elapsed > limit).then(|| {
+            format!("{} ms vs running median {} ms", resp.elapsed.as_millis(), median.as_millis())
+        })
+    }
+    fn observe(&mut self, resp: &Response) {
+        let at = self.seen.partition_point(|&d| d <= resp.elapsed);
+        self.seen.insert(at, resp.elapsed);
+    }
+}
+
+/// A string the case introduced (absent from the baseline) shows up verbatim
+/// in the response.
+struct Reflected {
+    baseline: HashSet<String>,
+}
+
+impl Reflected {
+    const MIN_LEN: usize = 6;
+    const PREFIX: usize = 64;
+
+    fn strings(v: &Value, out: &mut Vec<String>) {
+        match v {
+            Value::String(s) => out.push(s.clone()),
+            Value::Array(items) => items.iter().for_each(|x| Self::strings(x, out)),
+            Value::Object(m) => m.iter().for_each(|(k, x)| {
+                out.push(k.clone());
+                Self::strings(x, out);
+            }),
+            _ => {}
+        }
+    }
+
+    fn injected(case: &Case) -> Vec<String> {
+        let mut out = Vec::new();
+        if let Some(Body::Bytes(raw)) = &case.request.body {
+            if let Ok(doc) = serde_json::from_slice::<Value>(raw) {
+                Self::strings(&doc, &mut out);
+            }
+        }
+        out.extend(case.request.query.iter().map(|(_, v)| v.clone()));
+        out.extend(case.request.headers.iter().map(|(_, v)| v.clone()));
+        out
+    }
+}
+
+impl Oracle for Reflected {
+    fn id(&self) -> &'static str {
+        "reflect.payload"
+    }
+    fn verdict(&self) -> Verdict {
+        Verdict::Anomaly
+    }
+    fn check(&self, case: &Case, resp: &Response) -> Option<String> {
+        if !case.is_mutated() {
+            return None;
+        }
+        let body = String::from_utf8_lossy(&resp.body);
+        Self::injected(case)
+            .into_iter()
+            .filter(|s| s.len() >= Self::MIN_LEN && !self.baseline.contains(s))
+            .filter(|s| !s.bytes().all(|b| b.is_ascii_digit()))
+            .find_map(|s| {
+                let probe: String = s.chars().take(Self::PREFIX).collect();
+                body.contains(&probe).then(|| format!("response echoes injected value {probe:?}"))
+            })
+    }
+}
+
+/// The configured oracles, run in order over each outcome.
+pub struct Oracles {
+    oracles: Vec<Box<dyn Oracle>>,
+    enabled: Vec<String>,
+    severity: HashMap<String, Verdict>,
+}
+
+impl Oracles {
+    pub const IDS: &'static [&'static str] = &[
+        "status.5xx",
+        "leak.stacktrace",
+        "leak.sql",
+        "leak.custom",
+        "accept.malformed",
+        "auth.bypass",
+        "auth.case",
+        "latency.outlier",
+        "reflect.payload",
+        "signature.bypass",
+        "race.toctou",
+        "replay.accepted",
+        "unique.duplicate",
+        "unique.key-reuse",
+        "unique.nonce-reuse",
+    ];
+
+    /// One line on what oracle `id` (or the transport pseudo-oracle) flags.
+    pub fn describe(id: &str) -> &'static str {
+        match id {
+            "status.5xx" => "The server answered with a 5xx status",
+            "leak.stacktrace" => "The response body leaks a stack trace",
+            "leak.sql" => "The response body leaks a database error",
+            "leak.custom" => "The response body matches one of the profile's leak_patterns",
+            "accept.malformed" => "A deliberately malformed request was accepted with a 2xx",
+            "auth.bypass" => "A request with a dropped, forged, foreign or misplaced credential was accepted with a 2xx",
+            "auth.case" => "The valid credential was refused with 401 or 403 under a re-cased header name",
+            "latency.outlier" => "The response was far slower than the running median",
+            "race.toctou" => "More requests of a synchronized burst were accepted than the endpoint allows",
+            "reflect.payload" => "The response echoes an injected value",
+            "replay.accepted" => "A captured request re-sent unchanged after a delay was accepted with a 2xx",
+            "signature.bypass" => "A request changed after signing was accepted with a 2xx",
+            "transport.error" => "The request failed at the transport level",
+            "unique.duplicate" => "A request the API should have deduplicated created a second resource",
+            "unique.key-reuse" => "A used idempotency key was accepted with a different body",
+            "unique.nonce-reuse" => "A nonce was accepted a second time within its validity window",
+            _ => "Oracle signal",
+        }
+    }
+
+    pub fn new(cfg: &OracleConfig, baselines: &[Case]) -> Result<Self> {
+        for id in cfg.enabled.iter().chain(cfg.severity.keys()) {
+            if !Self::IDS.contains(&id.as_str()) {
+                anyhow::bail!("unknown oracle '{id}' (known: {})", Self::IDS.join(", "));
+            }
+        }
+        let leak = |id, patterns: &[&str]| -> Box<dyn Oracle> {
+            Box::new(Leak { id, patterns: RegexSet::new(patterns).expect("built-in leak patterns compile") })
+        };
+        let custom = RegexSet::new(&cfg.leak_patterns).context("invalid oracle leak_patterns")?;
+        let all: Vec<Box<dyn Oracle>> = vec![
+            Box::new(ServerError),
+            leak("leak.stacktrace", STACK_TRACE),
+            leak("leak.sql", SQL_ERROR),
+            Box::new(Leak { id: "leak.custom", patterns: custom }),
+            Box::new(AcceptedMalformed),
+            Box::new(AuthBypass),
+            Box::new(AuthCase),
+            Box::new(LatencyOutlier {
+                factor: cfg.latency_factor,
+                floor: Duration::from_millis(cfg.latency_floor_ms),
+                seen: Vec::new(),
+            }),
+            Box::new(Reflected { baseline: baselines.iter().flat_map(Reflected::injected).collect() }),
+            Box::new(SignatureBypass),
+        ];
+        let oracles = all
+            .into_iter()
+            .filter(|o| cfg.enabled.is_empty() || cfg.enabled.iter().any(|id| id == o.id()))
+            .collect();
+        Ok(Self { oracles, enabled: cfg.enabled.clone(), severity: cfg.severity.clone() })
+    }
+
+    /// Whether oracle `id` fires on `resp`; used to re-check shrunk cases.
+    /// Probes are not observed, so they leave later verdicts as they were.
+    pub fn fires(&self, id: &str, case: &Case, resp: &Response) -> bool {
+        self.oracles.iter().find(|o| o.id() == id).is_some_and(|o| o.check(case, resp).is_some())
+    }
+
+    /// Run every oracle over the outcome and record what fired on it. Signals
+    /// the race, replay and uniqueness modes raised themselves are kept only
+    /// if enabled, at the profile's severity.
+    pub fn judge(&mut self, o: &mut Outcome) {
+        o.signals.retain(|s| self.enabled.is_empty() || self.enabled.contains(&s.oracle));
+        for s in &mut o.signals {
+            s.verdict = self.severity.get(&s.oracle).copied().unwrap_or(s.verdict);
+        }
+        let resp = match &o.result {
+            Ok(resp) => resp,
+            Err(e) => {
+                o.signals.push(Signal {
+                    oracle: "transport.error".into(),
+                    verdict: Verdict::Anomaly,
+                    detail: format!("{e:#}"),
+                });
+                return;
+            }
+        };
+        for oracle in &mut self.oracles {
+            if let Some(detail) = oracle.check(&o.case, resp) {
+                let verdict = self.severity.get(oracle.id()).copied().unwrap_or(oracle.verdict());
+                o.signals.push(Signal { oracle: oracle.id().into(), verdict, detail });
+            }
+            oracle.observe(resp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan;
+    use crate::testutil;
+
+    fn outcome(case: &Case, status: u16, body: &str, ms: u64) -> Outcome {
+        let resp = Response { status, headers: Vec::new(), body: body.into(), elapsed: Duration::from_millis(ms), sent: case.request.clone() };
+        Outcome { index: 0, case: case.clone(), result: Ok(resp), signals: Vec::new() }
+    }
+
+    #[test]
+    fn custom_leak_pattern_raises_one_signal_of_its_own() {
+        let cfg = OracleConfig { leak_patterns: vec!["INTERNAL-\\d+".into()], ..OracleConfig::default() };
+        let case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        let mut oracles = Oracles::new(&cfg, std::slice::from_ref(&case)).unwrap();
+
+        let mut o = outcome(&case, 200, "error INTERNAL-42", 10);
+        oracles.judge(&mut o);
+
+        let fired: Vec<&str> = o.signals.iter().map(|s| s.oracle.as_str()).collect();
+        assert_eq!(fired, ["leak.custom"]);
+    }
+
+    #[test]
+    fn minimizer_probes_do_not_move_the_latency_median() {
+        let cfg = OracleConfig { latency_floor_ms: 0, ..OracleConfig::default() };
+        let case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        let mut oracles = Oracles::new(&cfg, std::slice::from_ref(&case)).unwrap();
+        for _ in 0..5 {
+            oracles.judge(&mut outcome(&case, 200, "", 10));
+        }
+        let slow = outcome(&case, 200, "", 100);
+        let Ok(resp) = &slow.result else { unreachable!() };
+
+        for _ in 0..20 {
+            assert!(oracles.fires("latency.outlier", &case, resp));
+        }
+        let mut later = outcome(&case, 200, "", 100);
+        oracles.judge(&mut later);
+
+        assert!(later.signals.iter().any(|s| s.oracle == "latency.outlier"));
+    }
+
+    #[test]
+    fn severity_overrides_apply_to_built_in_and_mode_raised_signals() {
+        let cfg: OracleConfig = toml::from_str(r#"
+            enabled = ["status.5xx", "race.toctou", "unique.duplicate"]
+            severity = { "status.5xx" = "anomaly", "race.toctou" = "anomaly" }
+        "#).unwrap();
+        let case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        let mut oracles = Oracles::new(&cfg, std::slice::from_ref(&case)).unwrap();
+        let mut o = outcome(&case, 503, "", 10);
+        for oracle in ["race.toctou", "unique.duplicate", "unique.key-reuse"] {
+            o.signals.push(Signal { oracle: oracle.into(), verdict: Verdict::Finding, detail: String::new() });
+        }
+
+        oracles.judge(&mut o);
+
+        let fired: Vec<(&str, Verdict)> = o.signals.iter().map(|s| (s.oracle.as_str(), s.verdict)).collect();
+        assert_eq!(fired, [("race.toctou", Verdict::Anomaly), ("unique.duplicate", Verdict::Finding), ("status.5xx", Verdict::Anomaly)]);
+    }
+
+    #[test]
+    fn every_oracle_id_is_known_and_described() {
+        for id in Oracles::IDS {
+            let cfg = OracleConfig { severity: HashMap::from([(id.to_string(), Verdict::Pass)]), ..OracleConfig::default() };
+            assert!(Oracles::new(&cfg, &[]).is_ok(), "{id}");
+            assert_ne!(Oracles::describe(id), "Oracle signal", "{id}");
+        }
+        let cfg = OracleConfig { enabled: vec!["race.tocttou".into()], ..OracleConfig::default() };
+        assert!(Oracles::new(&cfg, &[]).is_err());
+    }
+
+    #[test]
+    fn latency_median_tracks_responses_in_any_order() {
+        let cfg = OracleConfig { latency_factor: 2.0, latency_floor_ms: 0, ..OracleConfig::default() };
+        let case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        let mut oracles = Oracles::new(&cfg, std::slice::from_ref(&case)).unwrap();
+        // Median of these is 40 ms, whatever order they arrive in.
+        for ms in [90, 10, 40, 70, 20, 40, 50] {
+            oracles.judge(&mut outcome(&case, 200, "", ms));
+        }
+        let Ok(fast) = outcome(&case, 200, "", 80).result else { unreachable!() };
+        let Ok(slow) = outcome(&case, 200, "", 81).result else { unreachable!() };
+
+        assert!(!oracles.fires("latency.outlier", &case, &fast));
+        assert!(oracles.fires("latency.outlier", &case, &slow));
+    }
+}
@@ -0,0 +1,232 @@
+//! Oversized payloads: bodies, headers, query strings and single JSON fields
+//! grown to each rung of `Limits::payload_ladder`.
+
+use serde::{de, Deserialize, Deserializer};
+use std::fmt;
+
+use crate::auth::AuthConfig;
+use crate::endpoint::Endpoint;
+use crate::mutate::{self, Patch};
+use crate::plan::Case;
+use crate::signing::Signing;
+use crate::transport::{pairs_len, Body, Request};
+use crate::Profile;
+
+/// A byte count that deserialises from either an integer or a string such
+/// as `"64KiB"` or `"16MiB"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+const UNITS: &[(&str, u64)] = &[("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10), ("B", 1)];
+
+impl ByteSize {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        for (suffix, scale) in UNITS {
+            if let Some(n) = s.strip_suffix(suffix) {
+                return n.trim().parse::<u64>().ok()?.checked_mul(*scale).map(ByteSize);
+            }
+        }
+        s.parse().ok().map(ByteSize)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (suffix, scale) = UNITS
+            .iter()
+            .find(|(_, scale)| self.0 >= *scale && self.0.is_multiple_of(*scale))
+            .unwrap_or(&("B", 1));
+        write!(f, "{}{suffix}", self.0 / scale)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Int(u64),
+            Str(String),
+        }
+        match Raw::deserialize(d)? {
+            Raw::Int(n) => Ok(ByteSize(n)),
+            Raw::Str(s) => ByteSize::parse(&s)
+                .ok_or_else(|| de::Error::custom(format!("invalid size '{s}' (expected e.g. 64KiB, 1MiB)"))),
+        }
+    }
+}
+
+pub fn default_ladder() -> Vec<ByteSize> {
+    vec![ByteSize(1 << 10), ByteSize(64 << 10), ByteSize(1 << 20)]
+}
+
+pub fn default_max_payload() -> ByteSize {
+    ByteSize(1 << 20)
+}
+
+const FILL: u8 = b'A';
+const PAD_HEADER: &str = "X-Fuzzkit-Pad";
+const PAD_QUERY: &str = "fuzzkit_pad";
+/// The HTTP stack refuses URIs longer than 64 KiB, so larger query rungs are skipped.
+const MAX_QUERY: u64 = 60 << 10;
+
+/// Bytes the transport adds to every case: the forced headers, the `[auth]`
+/// credential and the `[signing]` stamps.
+pub fn headroom(p: &Profile) -> u64 {
+    let forced: u64 = p.safety.force_headers.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum();
+    forced + p.auth.as_ref().map_or(0, AuthConfig::allowance) + p.signing.as_ref().map_or(0, Signing::allowance)
+}
+
+/// One case per target per ladder rung. Each case's body, headers and query
+/// add up to exactly the rung size less `headroom`, so once the transport has
+/// added its headers the request is at most the rung, and the ladder and
+/// `max_payload_bytes` mean the same thing.
+pub fn cases(p: &Profile, ep: &Endpoint, base: &Case) -> Vec<Case> {
+    let mut out = Vec::new();
+    let headroom = headroom(p);
+    for &rung in &p.limits.payload_ladder {
+        let size = ByteSize(rung.0.saturating_sub(headroom));
+        out.push(body_case(ep, base, rung, size));
+        out.push(Case {
+            operator: "oversize.header".into(),
+            target: rung.to_string(),
+            request: Request { headers: padded(&base.request.headers, PAD_HEADER, size, &base.request), ..base.request.clone() },
+        });
+        if size.0 <= MAX_QUERY {
+            out.push(Case {
+                operator: "oversize.query".into(),
+                target: rung.to_string(),
+                request: Request { query: padded(&base.request.query, PAD_QUERY, size, &base.request), ..base.request.clone() },
+            });
+        }
+        out.extend(field_cases(ep, base, rung, size));
+    }
+    out
+}
+
+fn fill_string(len: u64) -> String {
+    char::from(FILL).to_string().repeat(len as usize)
+}
+
+/// Pad `pairs` (the headers or query of `req`) with one extra pair so the
+/// whole request adds up to `size`.
+fn padded(pairs: &[(String, String)], name: &str, size: ByteSize, req: &Request) -> Vec<(String, String)> {
+    let used = req.size();
+    let mut pairs = pairs.to_vec();
+    pairs.push((name.into(), fill_string(size.0.saturating_sub(used + name.len() as u64))));
+    pairs
+}
+
+/// Bytes of a request besides its body.
+fn outside_body(req: &Request) -> u64 {
+    pairs_len(&req.headers) + pairs_len(&req.query)
+}
+
+/// The seed body padded with an extra string member until the request is
+/// `size` bytes, or a bare run of filler when the profile has no seed.
+fn body_case(ep: &Endpoint, base: &Case, rung: ByteSize, size: ByteSize) -> Case {
+    let (prefix, suffix) = match ep.seed_body.as_ref().and_then(|s| s.as_object()) {
+        Some(seed) if !seed.is_empty() => {
+            let rest = serde_json::Value::Object(seed.clone()).to_string();
+            (br#"{"fuzzkit_pad":""#.to_vec(), format!("\",{}", &rest[1..]).into_bytes())
+        }
+        Some(_) => (br#"{"fuzzkit_pad":""#.to_vec(), br#""}"#.to_vec()),
+        None => (Vec::new(), Vec::new()),
+    };
+    let len = size.0.saturating_sub(outside_body(&base.request)).max((prefix.len() + suffix.len()) as u64);
+    Case {
+        operator: "oversize.body".into(),
+        target: rung.to_string(),
+        request: Request {
+            body: Some(Body::Filled { prefix, fill: FILL, len, suffix }),
+            ..base.request.clone()
+        },
+    }
+}
+
+/// One case per string field of the seed, with only that field grown.
+fn field_cases(ep: &Endpoint, base: &Case, rung: ByteSize, size: ByteSize) -> Vec<Case> {
+    const MARK: &str = "\u{0}FUZZKIT_FILL\u{0}";
+    let Some(seed) = &ep.seed_body else { return Vec::new() };
+    let len = size.0.saturating_sub(outside_body(&base.request));
+    mutate::nodes(seed)
+        .into_iter()
+        .filter(|(_, v)| v.is_string())
+        .filter_map(|(at, _)| {
+            let rendered = mutate::render(seed, &at, &Patch::Raw(format!("\"{MARK}\"")));
+            let (prefix, suffix) = rendered.split_once(MARK)?;
+            if (prefix.len() + suffix.len()) as u64 > len {
+                return None;
+            }
+            Some(Case {
+                operator: "oversize.field".into(),
+                target: format!("{} {rung}", mutate::pointer(&at)),
+                request: Request {
+                    body: Some(Body::Filled {
+                        prefix: prefix.as_bytes().to_vec(),
+                        fill: FILL,
+                        len,
+                        suffix: suffix.as_bytes().to_vec(),
+                    }),
+                    ..base.request.clone()
+                },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan;
+    use crate::testutil;
+
+    #[test]
+    fn every_case_fills_its_rung_exactly() {
+        let mut p = testutil::profile("http://127.0.0.1:1");
+        let base = plan::baseline(&p.endpoints[0]);
+        for rung in [ByteSize(1 << 10), ByteSize(64 << 10)] {
+            p.limits.payload_ladder = vec![rung];
+
+            let out = cases(&p, &p.endpoints[0], &base);
+
+            assert!(out.iter().any(|c| c.operator == "oversize.field"));
+            for case in &out {
+                assert_eq!(case.request.size(), rung.0 - headroom(&p), "{case}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn top_rung_cases_fit_the_ceiling_once_signed() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        p.signing = Some(testutil::signing(""));
+        p.safety.max_payload_bytes = ByteSize(64 << 10);
+        p.limits.payload_ladder = vec![p.safety.max_payload_bytes];
+        let transport = testutil::transport(&p);
+
+        let out = cases(&p, &p.endpoints[0], &plan::baseline(&p.endpoints[0]));
+
+        for case in &out {
+            transport.send(&case.request).await.unwrap_or_else(|e| panic!("{case}: {e:#}"));
+        }
+        let received = server.received_requests().await.unwrap();
+        assert_eq!(received.len(), out.len());
+        for r in &received {
+            assert!(r.headers.contains_key("x-signature") && r.headers.contains_key("x-env"));
+        }
+    }
+
+    #[test]
+    fn byte_sizes_parse_with_units() {
+        assert_eq!(ByteSize::parse("64KiB"), Some(ByteSize(64 << 10)));
+        assert_eq!(ByteSize::parse("1 MiB"), Some(ByteSize(1 << 20)));
+        assert_eq!(ByteSize::parse("512"), Some(ByteSize(512)));
+        assert_eq!(ByteSize::parse("1TB"), None);
+        assert_eq!(ByteSize(64 << 10).to_string(), "64KiB");
+    }
+}
@@ -0,0 +1,289 @@
+use serde_json::Value;
+use std::fmt;
+use url::Url;
+
+use crate::bypass::{self, Tamper};
+use crate::corpus::Corpus;
+use crate::mutate::{self, Mutation};
+use crate::oversize;
+use crate::rng::Rng;
+use crate::schema::{Generation, Schema};
+use crate::signing::Stage;
+use crate::endpoint::Endpoint;
+use crate::transport::{Body, Request};
+use crate::Profile;
+
+/// A request plus the operator that produced it, so findings can be attributed.
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub operator: String,
+    /// Where the operator was applied (a JSON pointer for body mutations)
+    pub target: String,
+    pub request: Request,
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} [{}", self.request.method, self.request.path, self.operator)?;
+        if !self.target.is_empty() {
+            write!(f, " {}", self.target)?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// The unmutated request for one endpoint.
+pub fn baseline(ep: &Endpoint) -> Case {
+    let mut headers = Vec::new();
+    let body = ep.seed_body.as_ref().map(|seed| {
+        headers.push(("Content-Type".to_string(), "application/json".to_string()));
+        Body::Bytes(seed.to_string().into_bytes())
+    });
+    Case {
+        operator: "baseline".into(),
+        target: String::new(),
+        request: Request {
+            method: ep.method.clone(),
+            path: ep.path(),
+            query: ep.query(),
+            headers,
+            body,
+            auth: Tamper::None,
+            origin: None,
+            signed: false,
+        },
+    }
+}
+
+/// The baseline of every endpoint in the profile.
+pub fn baselines(p: &Profile) -> Vec<Case> {
+    p.endpoints.iter().map(baseline).collect()
+}
+
+/// The cases for one endpoint: a fixed prefix, then havoc if it has a seed
+/// body or corpus entries.
+struct Lane<'a> {
+    endpoint: &'a Endpoint,
+    base: Case,
+    fixed: Vec<Case>,
+    /// `seed_body`, then the endpoint's corpus entries
+    seeds: Vec<&'a Value>,
+    /// Mutated cases are signed as the request they came from
+    signed_after: bool,
+}
+
+impl Lane<'_> {
+    /// How many cases the lane can produce; unbounded when havoc or the
+    /// schema generator can run.
+    fn capacity(&self) -> Option<usize> {
+        (self.seeds.is_empty() && self.endpoint.schema.is_none()).then_some(self.fixed.len())
+    }
+
+    fn case(&self, local: usize, rng: &mut Rng) -> Option<Case> {
+        let mut case = self.unsigned(local, rng)?;
+        // Body mutations come from their seed or capture; the rest from the baseline.
+        if self.signed_after && case.is_mutated() && case.request.auth == Tamper::None {
+            case.request.origin.get_or_insert_with(|| Box::new(self.base.request.clone()));
+        } else {
+            case.request.origin = None;
+        }
+        Some(case)
+    }
+
+    fn unsigned(&self, local: usize, rng: &mut Rng) -> Option<Case> {
+        if let Some(c) = self.fixed.get(local) {
+            return Some(c.clone());
+        }
+        // With both a schema and a seed, alternate schema-driven cases with havoc.
+        let seed_body = match (&self.endpoint.schema, self.seeds.as_slice()) {
+            (Some(schema), []) => return self.generated(schema, rng),
+            (Some(schema), _) if rng.below(2) == 0 => return self.generated(schema, rng),
+            (_, []) => return None,
+            (_, [only]) => *only,
+            (_, seeds) => *rng.pick(seeds),
+        };
+        mutate::havoc(seed_body, rng).map(|m| self.base.with_body(m))
+    }
+
+    fn generated(&self, schema: &Schema, rng: &mut Rng) -> Option<Case> {
+        let (operator, target, body) = match self.endpoint.generation {
+            Generation::Valid => ("schema.valid".to_string(), String::new(), schema.generate(rng)),
+            Generation::Violate => {
+                let v = schema.violate(rng)?;
+                (format!("schema.{}", v.rule), v.pointer, v.body)
+            }
+        };
+        Some(self.base.with_json(operator, target, &body))
+    }
+}
+
+/// Each capture as sent, then every single mutation of its JSON body.
+fn captures(ep: &Endpoint, base_url: &Url) -> Vec<Case> {
+    let mut out = Vec::new();
+    for (i, capture) in ep.captures.iter().enumerate() {
+        // Guardrails have already refused captures outside base_url.
+        let Ok(request) = capture.request(&ep.method, base_url) else { continue };
+        let case = Case { operator: "capture".into(), target: format!("#{i}"), request };
+        let mutations = capture.json().map(|body| mutate::mutations(&body)).unwrap_or_default();
+        out.push(case.clone());
+        out.extend(mutations.into_iter().map(|m| case.with_body(m)));
+    }
+    out
+}
+
+/// One case per constraint of the endpoint's schema, each breaking that rule
+/// on a valid body (the seed if it conforms, else the schema's example).
+fn violations(ep: &Endpoint, base: &Case) -> Vec<Case> {
+    let Some(schema) = &ep.schema else { return Vec::new() };
+    let doc = ep.seed_body.clone().filter(|seed| schema.accepts(seed)).unwrap_or_else(|| schema.example());
+    schema
+        .violations(&doc)
+        .into_iter()
+        .map(|v| base.with_json(format!("schema.{}", v.rule), v.pointer, &v.body))
+        .collect()
+}
+
+/// Deterministic case generator: case `i` depends only on the profile, the
+/// session seed and `i`, so any case can be regenerated for replay.
+pub struct Planner<'a> {
+    seed: u64,
+    lanes: Vec<Lane<'a>>,
+}
+
+impl<'a> Planner<'a> {
+    /// Each endpoint's fixed prefix is its baseline, the auth-bypass cases,
+    /// every single body mutation of its seed, its captures and their mutations, one violation
+    /// per schema constraint, then the oversized payloads. Past it, cases are
+    /// random stacked mutations of the seed body and the corpus entries.
+    pub fn new(profile: &'a Profile, corpus: &'a Corpus, seed: u64) -> Self {
+        let base_url = Url::parse(&profile.base_url).expect("guardrails check base_url");
+        let lanes = profile
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let base = baseline(endpoint);
+                let mut fixed = vec![base.clone()];
+                if let Some(auth) = &profile.auth {
+                    fixed.extend(bypass::cases(auth, profile.http_version, &base));
+                }
+                if let Some(seed_body) = &endpoint.seed_body {
+                    fixed.extend(mutate::mutations(seed_body).into_iter().map(|m| base.with_body(m)));
+                }
+                fixed.extend(captures(endpoint, &base_url));
+                fixed.extend(violations(endpoint, &base));
+                fixed.extend(oversize::cases(profile, endpoint, &base));
+                let seeds = endpoint.seed_body.iter().chain(corpus.bodies(endpoint)).collect();
+                let signed_after = profile.signing.as_ref().is_some_and(|s| s.mutate == Stage::After);
+                Lane { endpoint, base, fixed, seeds, signed_after }
+            })
+            .collect();
+        Self { seed, lanes }
+    }
+
+    /// Which lane, and which of its cases, each global index goes to. Lanes
+    /// are interleaved by smooth weighted round-robin on `budget_share`, so
+    /// any prefix of the run (and so any budget) is split by share; a lane
+    /// drops out once it runs dry.
+    fn schedule(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut current = vec![0.0f64; self.lanes.len()];
+        let mut taken = vec![0usize; self.lanes.len()];
+        std::iter::from_fn(move || {
+            let open: Vec<usize> = (0..self.lanes.len())
+                .filter(|&i| self.lanes[i].capacity().is_none_or(|cap| taken[i] < cap))
+                .collect();
+            let total: f64 = open.iter().map(|&i| self.lanes[i].endpoint.budget_share).sum();
+            for &i in &open {
+                current[i] += self.lanes[i].endpoint.budget_share;
+            }
+            let pick = open.iter().copied().reduce(|a, b| if current[b] > current[a] { b } else { a })?;
+            current[pick] -= total;
+            taken[pick] += 1;
+            Some((pick, taken[pick] - 1))
+        })
+    }
+
+    pub fn case(&self, index: usize) -> Option<Case> {
+        let (lane, local) = self.schedule().nth(index)?;
+        self.lanes[lane].case(local, &mut Rng::for_case(self.seed, index as u64))
+    }
+
+    /// The cases at the first `count` indices, each with its index. An index
+    /// whose lane has no case there (havoc or the schema generator came up
+    /// empty) is skipped, so the rest keep the index `case` regenerates them by.
+    pub fn cases(&self, count: usize) -> Vec<(usize, Case)> {
+        self.schedule()
+            .take(count)
+            .enumerate()
+            .filter_map(|(i, (lane, local))| Some((i, self.lanes[lane].case(local, &mut Rng::for_case(self.seed, i as u64))?)))
+            .collect()
+    }
+}
+
+impl Case {
+    /// Whether the case deliberately departs from a valid request. Timestamp
+    /// replay, uniqueness and race cases are valid requests; those modes judge
+    /// them themselves.
+    pub fn is_mutated(&self) -> bool {
+        !matches!(self.operator.as_str(), "baseline" | "capture" | "schema.valid")
+            && !self.operator.starts_with("replay.")
+            && !self.operator.starts_with("unique.")
+            && !self.operator.starts_with("race.")
+    }
+
+    /// Whether the target may rightly drop the connection instead of
+    /// answering: oversized payloads. A transport error there is no failure.
+    pub fn may_drop(&self) -> bool {
+        self.operator.starts_with("oversize.")
+    }
+
+    /// This case with `body` as its JSON body.
+    fn with_json(&self, operator: String, target: String, body: &Value) -> Case {
+        let mut request = self.request.clone();
+        if request.body.is_none() {
+            request.headers.push(("Content-Type".into(), "application/json".into()));
+        }
+        request.body = Some(Body::Bytes(body.to_string().into_bytes()));
+        Case { operator, target, request }
+    }
+
+    fn with_body(&self, m: Mutation) -> Case {
+        Case {
+            operator: m.operator,
+            target: m.pointer,
+            request: Request {
+                body: Some(Body::Bytes(m.body)),
+                origin: Some(Box::new(self.request.clone())),
+                ..self.request.clone()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn a_lane_with_nothing_to_generate_does_not_end_the_plan() {
+        let mut p = testutil::profile("http://127.0.0.1:1");
+        // An empty schema has no rule to break, so every generated case is `None`.
+        p.endpoints.push(toml::from_str(r#"
+            path = "/v1/periods"
+            method = "POST"
+            generation = "violate"
+            schema = {}
+        "#).unwrap());
+        let corpus = Corpus::for_profile(None).unwrap();
+        let planner = Planner::new(&p, &corpus, 7);
+
+        let cases = planner.cases(200);
+
+        assert!(cases.len() < 200, "the empty lane should skip indices");
+        assert!(cases.iter().filter(|(_, c)| c.request.path == "/v1/returns").count() >= 90);
+        for (i, case) in &cases {
+            let again = planner.case(*i).unwrap();
+            assert!(again.request.same_wire(&case.request), "#{i} {case}");
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Race-condition mode: synchronized bursts for TOCTOU bugs.
+//!
+//! Each round prepares `requests` copies of one valid request, sends all of
+//! them but the last body byte, and releases them together: last-byte sync
+//! over HTTP/1.1, the single-packet attack over HTTP/2 (see
+//! `Transport::send_burst`). When more of a burst is accepted than the
+//! endpoint allows, e.g. two returns filed against one period, the check and
+//! the write behind it are not atomic.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use crate::oracle::{Signal, Verdict};
+use crate::plan::{self, Case};
+use crate::runner::Outcome;
+use crate::scheduler::Scheduler;
+use crate::timestamp;
+use crate::transport::{Gate, Transport};
+use crate::Profile;
+
+/// `[race]`
+#[derive(Debug, Deserialize)]
+pub struct Race {
+    /// Path template of the endpoint to race; the first endpoint if omitted
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Requests released at once; must fit `concurrency` and
+    /// `max_rate_per_sec` unless `limits.burst_ceiling` allows more
+    #[serde(default = "default_requests")]
+    pub requests: usize,
+    #[serde(default = "default_rounds")]
+    pub rounds: usize,
+    /// How many requests of a burst may succeed; more is a finding
+    #[serde(default = "default_max_accepted")]
+    pub max_accepted: usize,
+    /// Pause once every request is held, so their partial writes are on the wire
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+}
+
+fn default_requests() -> usize {
+    5
+}
+
+fn default_rounds() -> usize {
+    1
+}
+
+fn default_max_accepted() -> usize {
+    1
+}
+
+fn default_settle_ms() -> u64 {
+    100
+}
+
+fn accepted(o: &Outcome) -> bool {
+    matches!(&o.result, Ok(r) if (200..300).contains(&r.status))
+}
+
+/// `base` with a fresh `[clock]` timestamp and nonce, so only the race is under test.
+fn stamped(p: &Profile, base: &Case, target: String) -> Result<Case> {
+    let mut case = Case { operator: "race.burst".into(), target, request: base.request.clone() };
+    if let Some(clock) = &p.clock {
+        timestamp::set_field(&mut case.request, &clock.timestamp, &clock.timestamp.format.render(OffsetDateTime::now_utc()))?;
+        if let Some(nonce) = &clock.nonce {
+            timestamp::set_field(&mut case.request, nonce, &Value::String(timestamp::fresh_nonce()))?;
+        }
+    }
+    Ok(case)
+}
+
+/// Send one burst. Requests are not retried: a retry would miss the release.
+async fn burst(p: &Profile, cfg: &Race, transport: &Arc<Transport>, sched: &Arc<Scheduler>, base: &Case, round: usize) -> Result<Option<Vec<Outcome>>> {
+    let n = cfg.requests;
+    let Some(slots) = sched.burst(n).await else {
+        tracing::warn!(target: "scheduler", round, "request budget cannot cover another burst; stopping");
+        return Ok(None);
+    };
+    let cases = (0..n).map(|i| stamped(p, base, format!("burst {round} {}/{n}", i + 1))).collect::<Result<Vec<_>>>()?;
+    let gate = Gate::new();
+    let sending = {
+        let (transport, gate) = (Arc::clone(transport), Arc::clone(&gate));
+        let requests = cases.iter().map(|c| c.request.clone()).collect();
+        tokio::spawn(async move { transport.send_burst(requests, &gate).await })
+    };
+
+    let held = gate.held(n, Duration::from_millis(p.timeouts.connect_ms + p.timeouts.read_ms)).await;
+    tokio::time::sleep(Duration::from_millis(cfg.settle_ms)).await;
+    gate.release();
+    if held < n {
+        tracing::warn!(target: "race", round, held, n, "not every request reached the gate; released the rest unsynchronized");
+    }
+
+    let results = sending.await.context("burst task panicked")?;
+    let mut outcomes: Vec<Outcome> = cases
+        .into_iter()
+        .zip(results)
+        .enumerate()
+        .map(|(index, (case, result))| {
+            slots.finish(result.is_ok());
+            Outcome { index, case, result, signals: Vec::new() }
+        })
+        .collect();
+
+    let winners: Vec<usize> = (0..outcomes.len()).filter(|&i| accepted(&outcomes[i])).collect();
+    if winners.len() > cfg.max_accepted {
+        let detail = format!("{} of {n} synchronized requests accepted; at most {} should be", winners.len(), cfg.max_accepted);
+        tracing::warn!(target: "race", round, "FINDING: {detail}");
+        for &at in winners.iter().skip(cfg.max_accepted) {
+            outcomes[at].signals.push(Signal { oracle: "race.toctou".into(), verdict: Verdict::Finding, detail: detail.clone() });
+        }
+    } else {
+        tracing::info!(target: "race", round, "{held}/{n} held and released together, {} accepted", winners.len());
+    }
+    Ok(Some(outcomes))
+}
+
+pub async fn run(p: &Profile, transport: Arc<Transport>, sched: Arc<Scheduler>) -> Result<Vec<Outcome>> {
+    let Some(cfg) = &p.race else {
+        bail!("race mode needs a [race] section in the profile");
+    };
+    let endpoint = match &cfg.endpoint {
+        Some(path) => p.endpoints.iter().find(|e| &e.path == path),
+        None => p.endpoints.first(),
+    };
+    let Some(endpoint) = endpoint else {
+        bail!("[race] endpoint {:?} is not one of the profile's endpoints", cfg.endpoint.as_deref().unwrap_or_default());
+    };
+    let base = plan::baseline(endpoint);
+    let mut outcomes = Vec::new();
+    for round in 1..=cfg.rounds {
+        match burst(p, cfg, &transport, &sched, &base, round).await? {
+            Some(sent) => outcomes.extend(sent),
+            None => break,
+        }
+    }
+    if outcomes.is_empty() {
+        bail!("request budget exhausted before the first burst");
+    }
+
+    for (i, o) in outcomes.iter_mut().enumerate() {
+        o.index = i;
+    }
+    Ok(outcomes)
+}
@@ -0,0 +1,924 @@
+//! Reports on a session artifact directory: the console summary, a Markdown
+//! summary or self-contained HTML page to hand to API owners, and SARIF 2.1.0
+//! or JUnit XML for CI.
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::artifacts::{self, Exchange, MinimizedRecord, RequestRecord, SessionRecord};
+use crate::bucket::Bucket;
+use crate::endpoint::{self, Endpoint};
+use crate::oracle::{Oracles, Verdict};
+use crate::{Mode, Profile};
+
+/// Upper edges of the latency histogram bins in milliseconds; the last bin is open.
+const LATENCY_EDGES_MS: &[u64] = &[10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+/// Width of the text bars in Markdown histograms.
+const BAR_WIDTH: usize = 30;
+
+fn status_label(x: &Exchange) -> String {
+    x.response.as_ref().map_or_else(|| "error".to_string(), |r| r.status.to_string())
+}
+
+pub fn text(dir: &Path) -> Result<String> {
+    let (s, exchanges) = artifacts::load(dir)?;
+    let mut out = String::new();
+
+    writeln!(out, "session  {} ({} mode, seed {})", s.profile, s.mode, s.seed)?;
+    writeln!(out, "window   {} .. {}", s.started, s.finished)?;
+    writeln!(
+        out,
+        "requests {} issued of {} budget, {} retries, {} transport failures ({} unexpected)",
+        s.issued, s.budget, s.retries, s.failed, s.errors
+    )?;
+    writeln!(
+        out,
+        "pacing   {:.2} req/s observed, peak {} in flight of {} allowed",
+        s.observed_rate, s.peak_in_flight, s.concurrency
+    )?;
+
+    let mut verdicts: BTreeMap<Verdict, usize> = BTreeMap::new();
+    let mut statuses: BTreeMap<String, usize> = BTreeMap::new();
+    let mut oracles: BTreeMap<&str, usize> = BTreeMap::new();
+    for x in &exchanges {
+        *verdicts.entry(x.verdict).or_default() += 1;
+        *statuses.entry(status_label(x)).or_default() += 1;
+        for sig in &x.signals {
+            *oracles.entry(sig.oracle.as_str()).or_default() += 1;
+        }
+    }
+    let join = |m: Vec<String>| if m.is_empty() { "-".to_string() } else { m.join(", ") };
+    writeln!(out, "verdicts {}", join(verdicts.iter().map(|(v, n)| format!("{v} {n}")).collect()))?;
+    writeln!(out, "statuses {}", join(statuses.iter().map(|(st, n)| format!("{st} x{n}")).collect()))?;
+    writeln!(out, "oracles  {}", join(oracles.iter().map(|(o, n)| format!("{o} x{n}")).collect()))?;
+
+    let buckets = artifacts::load_buckets(dir)?;
+    if buckets.is_empty() {
+        // Sessions from before bucketing: list every finding.
+        let findings: Vec<&Exchange> = exchanges.iter().filter(|x| x.verdict == Verdict::Finding).collect();
+        if !findings.is_empty() {
+            writeln!(out, "\nfindings")?;
+        }
+        for x in findings {
+            exchange(&mut out, "  ", x)?;
+        }
+        return Ok(out);
+    }
+
+    writeln!(out, "\nbuckets  {} ({} new)", buckets.len(), buckets.iter().filter(|b| b.new).count())?;
+    for b in &buckets {
+        let sig = &b.signature;
+        let status = sig.status.map_or_else(|| "error".to_string(), |s| s.to_string());
+        let new = if b.new { "new" } else { "known" };
+        writeln!(out, "  {} {} {new} x{}  {} -> {} [{}]", b.id, b.verdict, b.count, sig.endpoint, status, sig.operator)?;
+        if !sig.message.is_empty() {
+            writeln!(out, "    message {}", sig.message)?;
+        }
+        if !sig.frames.is_empty() {
+            writeln!(out, "    frames  {}", sig.frames.join(" < "))?;
+        }
+        if let Some(x) = exchanges.iter().find(|x| x.index == b.representative) {
+            exchange(&mut out, "    ", x)?;
+        }
+    }
+    Ok(out)
+}
+
+/// One exchange and the signals raised on it.
+fn exchange(out: &mut String, indent: &str, x: &Exchange) -> Result<()> {
+    writeln!(
+        out,
+        "{indent}#{} {} {} [{} {}] -> {}",
+        x.index, x.request.method, x.request.path, x.operator, x.target, status_label(x)
+    )?;
+    for sig in &x.signals {
+        writeln!(out, "{indent}    {} [{}] {}", sig.verdict, sig.oracle, sig.detail)?;
+    }
+    Ok(())
+}
+
+/// What the Markdown and HTML reports show, read from one session directory
+/// and the profile copied into it.
+pub struct Summary<'a> {
+    dir: &'a Path,
+    profile: &'a Profile,
+    session: SessionRecord,
+    exchanges: Vec<Exchange>,
+    buckets: Vec<Bucket>,
+    minimized: Vec<MinimizedRecord>,
+}
+
+/// A latency histogram of one endpoint.
+struct Latency {
+    endpoint: String,
+    /// Sorted
+    samples: Vec<u64>,
+}
+
+impl Latency {
+    fn percentile(&self, p: f64) -> u64 {
+        self.samples[((self.samples.len() - 1) as f64 * p).round() as usize]
+    }
+
+    /// (label, count) per bin, up to the slowest non-empty one.
+    fn bins(&self) -> Vec<(String, usize)> {
+        let mut bins: Vec<(String, usize)> = LATENCY_EDGES_MS.iter().map(|e| (format!("<{}", ms(*e)), 0)).collect();
+        bins.push((format!(">={}", ms(LATENCY_EDGES_MS[LATENCY_EDGES_MS.len() - 1])), 0));
+        for v in &self.samples {
+            let i = LATENCY_EDGES_MS.iter().position(|e| v < e).unwrap_or(LATENCY_EDGES_MS.len());
+            bins[i].1 += 1;
+        }
+        let last = bins.iter().rposition(|(_, n)| *n > 0).unwrap_or(0);
+        bins.truncate(last + 1);
+        bins
+    }
+
+    fn headline(&self) -> String {
+        format!(
+            "{} responses, p50 {}, p95 {}, max {}",
+            self.samples.len(),
+            ms(self.percentile(0.5)),
+            ms(self.percentile(0.95)),
+            ms(self.samples[self.samples.len() - 1])
+        )
+    }
+}
+
+fn ms(v: u64) -> String {
+    match v {
+        v if v < 1000 => format!("{v}ms"),
+        v if v % 1000 == 0 => format!("{}s", v / 1000),
+        v => format!("{:.1}s", v as f64 / 1000.0),
+    }
+}
+
+/// Single-quote `s` for a POSIX shell unless it is plainly safe.
+fn shell_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+impl<'a> Summary<'a> {
+    pub fn load(dir: &'a Path, profile: &'a Profile) -> Result<Self> {
+        let (session, exchanges) = artifacts::load(dir)?;
+        Ok(Self {
+            dir,
+            profile,
+            session,
+            exchanges,
+            buckets: artifacts::load_buckets(dir)?,
+            minimized: artifacts::load_minimized(dir)?,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.dir.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    fn count(&self, verdict: Verdict) -> usize {
+        self.exchanges.iter().filter(|x| x.verdict == verdict).count()
+    }
+
+    /// `count (share%)` of all exchanges.
+    fn share(&self, count: usize) -> String {
+        let total = self.exchanges.len().max(1);
+        format!("{count} ({:.1}%)", 100.0 * count as f64 / total as f64)
+    }
+
+    fn endpoints(&self) -> Vec<(String, String)> {
+        self.profile
+            .endpoints
+            .iter()
+            .map(|ep| {
+                let mut notes = vec![format!("budget share {}", ep.budget_share)];
+                if ep.seed_body.is_some() {
+                    notes.push("seed body".into());
+                }
+                if ep.schema.is_some() {
+                    notes.push(format!("schema ({})", format!("{:?}", ep.generation).to_lowercase()));
+                }
+                if !ep.captures.is_empty() {
+                    notes.push(format!("{} captures", ep.captures.len()));
+                }
+                (ep.key(), notes.join(", "))
+            })
+            .collect()
+    }
+
+    /// (guardrail, setting) as enforced before the session sent anything.
+    fn guardrails(&self) -> Vec<(&'static str, String)> {
+        let s = &self.profile.safety;
+        let l = &self.profile.limits;
+        let list = |mut v: Vec<String>| {
+            v.sort();
+            if v.is_empty() { "none".to_string() } else { v.join(", ") }
+        };
+        let probe = s.sandbox_probe.as_ref().map_or_else(
+            || "none".to_string(),
+            |p| {
+                let body = p.expect_body.as_ref().map(|b| format!(", body containing {b:?}")).unwrap_or_default();
+                format!("GET {} expects {}{body}", p.path, p.expect_status)
+            },
+        );
+        let pinned = s.resolve.iter().map(|(host, ips)| {
+            format!("{host} -> {}", ips.iter().map(ToString::to_string).collect::<Vec<_>>().join(" "))
+        });
+        vec![
+            ("sandbox attestation", if s.require_sandbox_flag { "required (--sandbox yes)" } else { "optional" }.into()),
+            ("sandbox header", format!("{}: {}", s.sandbox_header.name, s.sandbox_header.value)),
+            ("sandbox probe", probe),
+            ("allowlisted hosts", list(s.allowlist_hosts.clone())),
+            ("private targets", if s.allow_private_targets { "allowed" } else { "refused" }.into()),
+            ("pinned DNS", list(pinned.collect())),
+            ("allowed methods", list(l.allowed_methods.clone())),
+            ("forced headers", list(s.force_headers.iter().map(|(k, v)| format!("{k}: {v}")).collect())),
+            ("max payload", s.max_payload_bytes.to_string()),
+            ("payload ladder", l.payload_ladder.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")),
+            ("rate ceiling", format!("{} req/s", l.max_rate_per_sec)),
+        ]
+    }
+
+    /// (what, consumed, limit) for the budget and pacing `Limits` set.
+    fn consumption(&self) -> Vec<(&'static str, String, String)> {
+        let s = &self.session;
+        let l = &self.profile.limits;
+        let pct = |used: f64, of: f64| if of > 0.0 { format!(" ({:.0}%)", 100.0 * used / of) } else { String::new() };
+        let mut rows = vec![
+            ("requests issued", format!("{}{}", s.issued, pct(s.issued.into(), s.budget.into())), format!("request_budget {}", s.budget)),
+            ("cases sent", s.cases.to_string(), format!("{} planned after minimize_budget", l.case_budget())),
+        ];
+        if s.mode == Mode::Fuzz {
+            // Everything issued that was neither a case, a retry, a token fetch nor the sandbox probe.
+            let probe = u32::from(self.profile.safety.sandbox_probe.is_some());
+            let shrink = s.issued.saturating_sub(s.cases as u32 + s.retries + s.token_fetches + probe);
+            rows.push(("minimiser requests", shrink.to_string(), format!("minimize_budget {}", l.minimize_budget())));
+        }
+        rows.extend([
+            ("retries", s.retries.to_string(), format!("up to {} per case", l.retries)),
+            ("token fetches", s.token_fetches.to_string(), "OAuth2, refreshed before expiry".into()),
+            ("transport failures", s.failed.to_string(), format!("{} unexpected (oversized cases may be dropped)", s.errors)),
+            (
+                "request rate",
+                format!("{:.2} req/s{}", s.observed_rate, pct(s.observed_rate, l.rate_per_sec.into())),
+                format!("rate_per_sec {} (ceiling {})", l.rate_per_sec, l.max_rate_per_sec),
+            ),
+            ("in flight", format!("peak {}", s.peak_in_flight), format!("concurrency {}", s.concurrency)),
+        ]);
+        rows
+    }
+
+    /// `METHOD /path/{template}` of the exchange, as buckets name endpoints.
+    fn endpoint(&self, x: &Exchange) -> String {
+        let (method, path) = (&x.request.method, &x.request.path);
+        endpoint::of(&self.profile.endpoints, method, path).map_or_else(|| format!("{method} {path}"), Endpoint::key)
+    }
+
+    fn statuses(&self) -> BTreeMap<String, usize> {
+        let mut out = BTreeMap::new();
+        for x in &self.exchanges {
+            *out.entry(status_label(x)).or_default() += 1;
+        }
+        out
+    }
+
+    fn latencies(&self) -> Vec<Latency> {
+        let mut by: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+        for x in &self.exchanges {
+            let Some(resp) = &x.response else { continue };
+            by.entry(self.endpoint(x)).or_default().push(resp.latency_ms);
+        }
+        by.into_iter()
+            .map(|(endpoint, mut samples)| {
+                samples.sort_unstable();
+                Latency { endpoint, samples }
+            })
+            .collect()
+    }
+
+    /// Buckets, findings first, each in order of first appearance.
+    fn buckets(&self) -> Vec<&Bucket> {
+        let mut out: Vec<&Bucket> = self.buckets.iter().collect();
+        out.sort_by_key(|b| Reverse(b.verdict));
+        out
+    }
+
+    fn example(&self, b: &Bucket) -> Option<&Exchange> {
+        self.exchanges.iter().find(|x| x.index == b.representative)
+    }
+
+    fn shrunk(&self, b: &Bucket) -> Option<&MinimizedRecord> {
+        self.minimized.iter().find(|m| m.index == b.representative)
+    }
+
+    /// `curl` command for a logged request, with the forced headers the
+    /// transport added. `None` when the log only holds part of it.
+    fn curl(&self, req: &RequestRecord) -> Option<String> {
+        if !req.complete() {
+            return None;
+        }
+        let forced = &self.profile.safety.force_headers;
+        let auth = self.profile.auth.as_ref();
+        // The logged credential is redacted; `curl_args` supplies it.
+        let query: Vec<_> = req.query.iter().filter(|(k, _)| auth.and_then(|a| a.query()) != Some(k.as_str())).collect();
+        let mut url = format!("{}{}", self.profile.base_url.trim_end_matches('/'), req.path);
+        if !query.is_empty() {
+            let mut q = url::form_urlencoded::Serializer::new(String::new());
+            q.extend_pairs(query);
+            url = format!("{url}?{}", q.finish());
+        }
+        let body = req.body.as_ref().map(|b| b.preview.as_str());
+        if body.is_some_and(|b| b.contains('\0')) {
+            return None;
+        }
+        let mut parts = vec!["curl".to_string()];
+        if url.contains(['[', ']', '{', '}']) {
+            parts.push("--globoff".into());
+        }
+        match (req.method.as_str(), body) {
+            ("GET", None) | ("POST", Some(_)) => {}
+            (method, _) => parts.push(format!("-X {}", shell_quote(method))),
+        }
+        parts.push(shell_quote(&url));
+        let mut forced: Vec<(&String, &String)> = forced.iter().collect();
+        forced.sort();
+        let replaced = |k: &str| {
+            k.eq_ignore_ascii_case("content-length")
+                || forced.iter().any(|(f, _)| f.eq_ignore_ascii_case(k))
+                || auth.and_then(|a| a.header()).is_some_and(|h| h.eq_ignore_ascii_case(k))
+        };
+        let sent = req.headers.iter().filter(|(k, _)| !replaced(k)).map(|(k, v)| (k, v));
+        for (k, v) in sent.chain(forced.iter().copied()) {
+            // `-H 'Name:'` would remove the header; `Name;` sends it empty.
+            let header = if v.is_empty() { format!("{k};") } else { format!("{k}: {v}") };
+            parts.push(format!("-H {}", shell_quote(&header)));
+        }
+        parts.extend(auth.map(|a| a.curl_args()).unwrap_or_default());
+        if let Some(b) = body {
+            parts.push(format!("--data-binary {}", shell_quote(b)));
+        }
+        Some(parts.join(" \\\n  "))
+    }
+
+    fn replay(&self, index: usize) -> String {
+        format!("api-fuzzkit --sandbox yes replay --from {} --case {index}", shell_quote(&self.dir.to_string_lossy()))
+    }
+
+    /// The reproducer for a bucket: the minimised request when there is
+    /// one, else the example, as `curl` or failing that as a replay.
+    fn reproducer(&self, b: &Bucket) -> (String, String) {
+        let req = self.shrunk(b).map(|m| &m.request).or_else(|| self.example(b).map(|x| &x.request));
+        // The transport signs requests and tampers with auth-bypass
+        // credentials at send time, which a fixed curl command cannot redo.
+        let live = if matches!(self.session.mode, Mode::Uniqueness | Mode::Race) {
+            Some("the finding spans several requests")
+        } else if self.profile.signing.is_some() {
+            Some("requests are signed at send time")
+        } else if b.signature.operator.starts_with("auth.") {
+            Some("the credential is tampered with at send time")
+        } else {
+            None
+        };
+        let why = live.unwrap_or("request too large or not text for curl");
+        match req.filter(|_| live.is_none()).and_then(|r| self.curl(r)) {
+            Some(curl) => ("curl".into(), curl),
+            None if matches!(self.session.mode, Mode::Timestamp | Mode::Uniqueness | Mode::Race) => {
+                let mode = self.session.mode;
+                (format!("the {mode} mode ({why})"), format!("api-fuzzkit --sandbox yes run --mode {mode}"))
+            }
+            None => (format!("replay ({why})"), self.replay(b.representative)),
+        }
+    }
+
+    fn headline(&self) -> String {
+        let findings = self.buckets.iter().filter(|b| b.verdict == Verdict::Finding).count();
+        let new = self.buckets.iter().filter(|b| b.verdict == Verdict::Finding && b.new).count();
+        format!(
+            "{} findings in {findings} buckets ({new} new), {} anomalies, {} passed, of {} cases",
+            self.count(Verdict::Finding),
+            self.count(Verdict::Anomaly),
+            self.count(Verdict::Pass),
+            self.exchanges.len()
+        )
+    }
+
+    /// Timestamp mode: which clock skews the API accepted.
+    fn skew_window(&self) -> Option<String> {
+        self.session.accepted_skews.as_ref().map(|skews| {
+            if skews.is_empty() { "no skewed timestamp accepted".to_string() } else { format!("accepted {}", skews.join(", ")) }
+        })
+    }
+
+    fn bucket_title(b: &Bucket) -> String {
+        let status = b.signature.status.map_or_else(|| "error".to_string(), |s| s.to_string());
+        let new = if b.new { "new" } else { "known" };
+        format!("{} {} -> {status} [{}], {new}, x{}", b.verdict, b.signature.endpoint, b.signature.operator, b.count)
+    }
+
+    /// The oracle behind the bucket's verdict, from its example's signals.
+    fn rule(&self, b: &Bucket) -> String {
+        let signals = self.example(b).map(|x| x.signals.as_slice()).unwrap_or_default();
+        signals.iter().find(|s| s.verdict == b.verdict).map_or_else(|| "unknown".to_string(), |s| s.oracle.clone())
+    }
+
+    fn minimized_note(&self, b: &Bucket) -> Option<String> {
+        self.shrunk(b).map(|m| {
+            format!(
+                "minimised for {} from {} to {} bytes in {} requests (raw HTTP in minimized/{}-minimized.http)",
+                m.oracle, m.original_bytes, m.minimized_bytes, m.attempts, m.index
+            )
+        })
+    }
+}
+
+/// Escape `|` so text stays in its Markdown table cell.
+fn cell(s: &str) -> String {
+    s.replace('|', r"\|")
+}
+
+/// `s` as a Markdown code span, fenced by more backticks than any run in it.
+fn code(s: &str) -> String {
+    let longest = s.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest + 1);
+    if longest == 0 { format!("{fence}{s}{fence}") } else { format!("{fence} {s} {fence}") }
+}
+
+pub fn markdown(r: &Summary) -> Result<String> {
+    let s = &r.session;
+    let mut out = String::new();
+    writeln!(out, "# Fuzz report: {}\n", r.profile.name)?;
+    writeln!(out, "{}.\n", r.headline())?;
+    writeln!(out, "- Session: {} ({} mode, seed {})", code(&r.name()), s.mode, s.seed)?;
+    writeln!(out, "- Window: {} .. {}", s.started, s.finished)?;
+    writeln!(out, "- Target: {}", code(&r.profile.base_url))?;
+    if let Some(window) = r.skew_window() {
+        writeln!(out, "- Clock skew: {window}")?;
+    }
+
+    writeln!(out, "\n## Endpoints\n\n| Endpoint | Setup |\n|---|---|")?;
+    for (ep, notes) in r.endpoints() {
+        writeln!(out, "| {} | {} |", cell(&code(&ep)), cell(&notes))?;
+    }
+
+    writeln!(out, "\n## Guardrails\n\n| Guardrail | Setting |\n|---|---|")?;
+    for (what, setting) in r.guardrails() {
+        writeln!(out, "| {what} | {} |", cell(&setting))?;
+    }
+
+    writeln!(out, "\n## Budget and rate\n\n| | Consumed | Limit |\n|---|---|---|")?;
+    for (what, used, limit) in r.consumption() {
+        writeln!(out, "| {what} | {used} | {limit} |")?;
+    }
+
+    writeln!(out, "\n## Responses by status\n\n| Status | Responses |\n|---|---|")?;
+    for (status, n) in r.statuses() {
+        writeln!(out, "| {status} | {} |", r.share(n))?;
+    }
+
+    writeln!(out, "\n## Latency")?;
+    for lat in r.latencies() {
+        writeln!(out, "\n{}: {}\n\n```text", code(&lat.endpoint), lat.headline())?;
+        let bins = lat.bins();
+        let most = bins.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1);
+        for (label, n) in bins {
+            let bar = "#".repeat((n * BAR_WIDTH).div_ceil(most));
+            writeln!(out, "{label:>7} {bar:<BAR_WIDTH$} {n}")?;
+        }
+        writeln!(out, "```")?;
+    }
+
+    let buckets = r.buckets();
+    writeln!(out, "\n## Buckets\n")?;
+    if buckets.is_empty() {
+        writeln!(out, "No oracle fired.")?;
+    }
+    for b in buckets {
+        writeln!(out, "### {} {}\n", code(&b.id), Summary::bucket_title(b))?;
+        if !b.signature.message.is_empty() {
+            writeln!(out, "- Message: {}", code(&b.signature.message))?;
+        }
+        if !b.signature.frames.is_empty() {
+            writeln!(out, "- Frames: {}", code(&b.signature.frames.join(" < ")))?;
+        }
+        if let Some(x) = r.example(b) {
+            writeln!(out, "- Example: #{} ({} {})", x.index, x.operator, code(&x.target))?;
+            for sig in &x.signals {
+                writeln!(out, "- {} [{}] {}", sig.verdict, sig.oracle, sig.detail)?;
+            }
+        }
+        if let Some(note) = r.minimized_note(b) {
+            writeln!(out, "- {note}")?;
+        }
+        let (label, command) = r.reproducer(b);
+        writeln!(out, "\nReproduce with {label}:\n\n```sh\n{command}\n```\n")?;
+    }
+    Ok(out)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+const STYLE: &str = "
+body { font: 14px/1.45 system-ui, sans-serif; margin: 2rem auto; max-width: 72rem; padding: 0 1rem; color: #1d2127; }
+h1 { margin-bottom: .25rem; } h2 { margin-top: 2rem; border-bottom: 1px solid #d8dde3; }
+table { border-collapse: collapse; margin: .5rem 0; } th, td { text-align: left; padding: .2rem .75rem .2rem 0; vertical-align: top; }
+code, pre { font: 12px/1.4 ui-monospace, monospace; } pre { background: #f4f6f8; padding: .75rem; overflow-x: auto; white-space: pre-wrap; word-break: break-all; }
+.bar { display: inline-block; height: .8rem; background: #5b8def; } .hist td:nth-child(2) { width: 20rem; }
+details { border: 1px solid #d8dde3; border-radius: 4px; padding: .4rem .75rem; margin: .5rem 0; } summary { cursor: pointer; }
+.finding { border-left: 4px solid #c62828; } .anomaly { border-left: 4px solid #ef8f00; } .pass { border-left: 4px solid #2e7d32; }
+.new { background: #c62828; color: #fff; border-radius: 3px; padding: 0 .3rem; font-size: 11px; }
+.muted { color: #5f6b7a; }
+";
+
+pub fn html(r: &Summary) -> Result<String> {
+    let s = &r.session;
+    let e = |v: &str| escape(v);
+    let mut out = String::new();
+    writeln!(out, "<!doctype html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>Fuzz report: {}</title>\n<style>{STYLE}</style>\n</head>\n<body>", e(&r.profile.name))?;
+    writeln!(out, "<h1>Fuzz report: {}</h1>", e(&r.profile.name))?;
+    writeln!(out, "<p><strong>{}</strong></p>", e(&r.headline()))?;
+    writeln!(
+        out,
+        "<p class=\"muted\">Session <code>{}</code>, {} mode, seed {}, {} .. {}<br>Target <code>{}</code></p>",
+        e(&r.name()),
+        e(s.mode.as_str()),
+        s.seed,
+        e(&s.started),
+        e(&s.finished),
+        e(&r.profile.base_url)
+    )?;
+    if let Some(window) = r.skew_window() {
+        writeln!(out, "<p>Clock skew: {}</p>", e(&window))?;
+    }
+
+    writeln!(out, "<h2>Endpoints</h2>\n<table>")?;
+    for (ep, notes) in r.endpoints() {
+        writeln!(out, "<tr><td><code>{}</code></td><td>{}</td></tr>", e(&ep), e(&notes))?;
+    }
+    writeln!(out, "</table>")?;
+
+    writeln!(out, "<h2>Guardrails</h2>\n<table>")?;
+    for (what, setting) in r.guardrails() {
+        writeln!(out, "<tr><th>{what}</th><td>{}</td></tr>", e(&setting))?;
+    }
+    writeln!(out, "</table>")?;
+
+    writeln!(out, "<h2>Budget and rate</h2>\n<table>\n<tr><th></th><th>Consumed</th><th>Limit</th></tr>")?;
+    for (what, used, limit) in r.consumption() {
+        writeln!(out, "<tr><th>{what}</th><td>{}</td><td>{}</td></tr>", e(&used), e(&limit))?;
+    }
+    writeln!(out, "</table>")?;
+
+    writeln!(out, "<h2>Responses by status</h2>\n<table>")?;
+    for (status, n) in r.statuses() {
+        writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", e(&status), r.share(n))?;
+    }
+    writeln!(out, "</table>")?;
+
+    writeln!(out, "<h2>Latency</h2>")?;
+    for lat in r.latencies() {
+        writeln!(out, "<h3><code>{}</code></h3>\n<p class=\"muted\">{}</p>\n<table class=\"hist\">", e(&lat.endpoint), lat.headline())?;
+        let bins = lat.bins();
+        let most = bins.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1);
+        for (label, n) in bins {
+            let width = 100.0 * n as f64 / most as f64;
+            writeln!(out, "<tr><td>{}</td><td><span class=\"bar\" style=\"width:{width:.1}%\"></span></td><td>{n}</td></tr>", e(&label))?;
+        }
+        writeln!(out, "</table>")?;
+    }
+
+    let buckets = r.buckets();
+    writeln!(out, "<h2>Buckets</h2>")?;
+    if buckets.is_empty() {
+        writeln!(out, "<p>No oracle fired.</p>")?;
+    }
+    for b in buckets {
+        let open = if b.verdict == Verdict::Finding { " open" } else { "" };
+        let new = if b.new { " <span class=\"new\">new</span>" } else { "" };
+        writeln!(out, "<details class=\"{}\"{open}>", b.verdict)?;
+        writeln!(out, "<summary><code>{}</code> {}{new}</summary>\n<table>", e(&b.id), e(&Summary::bucket_title(b)))?;
+        if !b.signature.message.is_empty() {
+            writeln!(out, "<tr><th>message</th><td><code>{}</code></td></tr>", e(&b.signature.message))?;
+        }
+        if !b.signature.frames.is_empty() {
+            writeln!(out, "<tr><th>frames</th><td><code>{}</code></td></tr>", e(&b.signature.frames.join(" < ")))?;
+        }
+        if let Some(x) = r.example(b) {
+            writeln!(out, "<tr><th>example</th><td>#{} {} <code>{}</code></td></tr>", x.index, e(&x.operator), e(&x.target))?;
+            for sig in &x.signals {
+                writeln!(out, "<tr><th>{}</th><td>[{}] {}</td></tr>", sig.verdict, e(&sig.oracle), e(&sig.detail))?;
+            }
+        }
+        if let Some(note) = r.minimized_note(b) {
+            writeln!(out, "<tr><th>minimised</th><td>{}</td></tr>", e(&note))?;
+        }
+        let (label, command) = r.reproducer(b);
+        writeln!(out, "</table>\n<p class=\"muted\">Reproduce with {}:</p>\n<pre>{}</pre>\n</details>", e(&label), e(&command))?;
+    }
+    writeln!(out, "</body>\n</html>")?;
+    Ok(out)
+}
+
+/// 1-based line of the `[[endpoints]]` header declaring `key` in the raw profile.
+fn endpoint_line(r: &Summary, raw: &str, key: &str) -> usize {
+    let Some(i) = r.profile.endpoints.iter().position(|ep| ep.key() == key) else { return 1 };
+    raw.lines()
+        .enumerate()
+        .filter(|(_, l)| l.trim() == "[[endpoints]]")
+        .nth(i)
+        .map_or(1, |(n, _)| n + 1)
+}
+
+pub fn sarif(r: &Summary) -> Result<String> {
+    let profile_path = r.dir.join("profile.toml");
+    let raw = fs::read_to_string(&profile_path).with_context(|| format!("failed to read {}", profile_path.display()))?;
+    let uri = profile_path.to_string_lossy().replace('\\', "/");
+    let level = |v: Verdict| match v {
+        Verdict::Finding => "error",
+        Verdict::Anomaly => "warning",
+        Verdict::Pass => "note",
+    };
+
+    let buckets = r.buckets();
+    let rules: BTreeSet<String> = buckets.iter().map(|b| r.rule(b)).collect();
+    let rules: Vec<Value> = rules
+        .iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "shortDescription": { "text": Oracles::describe(id) },
+            })
+        })
+        .collect();
+    let results: Vec<Value> = buckets
+        .iter()
+        .map(|b| {
+            let sig = &b.signature;
+            let status = sig.status.map_or_else(|| "error".to_string(), |s| s.to_string());
+            let mut text = format!("{} -> {status} after {} ({} cases)", sig.endpoint, sig.operator, b.count);
+            if !sig.message.is_empty() {
+                write!(text, ": {}", sig.message).ok();
+            }
+            let (_, reproducer) = r.reproducer(b);
+            json!({
+                "ruleId": r.rule(b),
+                "level": level(b.verdict),
+                "message": { "text": text },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri },
+                        "region": { "startLine": endpoint_line(r, &raw, &sig.endpoint) },
+                    },
+                    "logicalLocations": [{ "name": sig.endpoint, "kind": "function" }],
+                }],
+                "partialFingerprints": { "apiFuzzkitBucket/v1": b.id },
+                "baselineState": if b.new { "new" } else { "unchanged" },
+                "properties": {
+                    "bucket": b.id,
+                    "count": b.count,
+                    "status": sig.status,
+                    "operator": sig.operator,
+                    "frames": sig.frames,
+                    "example": b.representative,
+                    "reproducer": reproducer,
+                },
+            })
+        })
+        .collect();
+    let s = &r.session;
+    let doc = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": { "driver": {
+                "name": "api-fuzzkit",
+                "version": env!("CARGO_PKG_VERSION"),
+                "rules": rules,
+            }},
+            "automationDetails": { "id": format!("api-fuzzkit/{}/{}", r.profile.name, r.name()) },
+            "invocations": [{
+                "executionSuccessful": s.errors == 0,
+                "startTimeUtc": s.started,
+                "endTimeUtc": s.finished,
+            }],
+            "properties": { "mode": s.mode, "seed": s.seed, "target": r.profile.base_url },
+            "results": results,
+        }],
+    });
+    Ok(serde_json::to_string_pretty(&doc)? + "\n")
+}
+
+/// `s` escaped for XML text and attributes, without the control characters
+/// XML 1.0 cannot carry.
+fn xml(s: &str) -> String {
+    let kept: String = s.chars().filter(|&c| c >= ' ' || matches!(c, '\t' | '\n' | '\r')).collect();
+    escape(&kept)
+}
+
+pub fn junit(r: &Summary) -> Result<String> {
+    // One suite per profile endpoint, then any a bucket names that none matched.
+    let mut suites: Vec<String> = r.profile.endpoints.iter().map(Endpoint::key).collect();
+    for b in &r.buckets {
+        if !suites.contains(&b.signature.endpoint) {
+            suites.push(b.signature.endpoint.clone());
+        }
+    }
+    let secs = |xs: &[&Exchange]| xs.iter().filter_map(|x| x.response.as_ref()).map(|resp| resp.latency_ms).sum::<u64>() as f64 / 1000.0;
+
+    let mut body = String::new();
+    let (mut tests, mut failures) = (0, 0);
+    for suite in &suites {
+        let exchanges: Vec<&Exchange> = r.exchanges.iter().filter(|x| &r.endpoint(x) == suite).collect();
+        let buckets: Vec<&Bucket> = r.buckets().into_iter().filter(|b| &b.signature.endpoint == suite).collect();
+        let clean = exchanges.iter().filter(|x| x.signals.is_empty()).count();
+        let classname = xml(&format!("{}.{suite}", r.profile.name));
+        writeln!(
+            body,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\" timestamp=\"{}\">",
+            xml(suite),
+            buckets.len() + 1,
+            buckets.len(),
+            secs(&exchanges),
+            xml(&r.session.started)
+        )?;
+        writeln!(body, "    <testcase classname=\"{classname}\" name=\"{clean} cases without signals\"/>")?;
+        for b in &buckets {
+            let example: Vec<&Exchange> = r.example(b).into_iter().collect();
+            writeln!(
+                body,
+                "    <testcase classname=\"{classname}\" name=\"{}\" time=\"{:.3}\">",
+                xml(&format!("{} {}", b.id, Summary::bucket_title(b))),
+                secs(&example)
+            )?;
+            let mut detail = String::new();
+            if !b.signature.message.is_empty() {
+                writeln!(detail, "message: {}", b.signature.message)?;
+            }
+            if !b.signature.frames.is_empty() {
+                writeln!(detail, "frames: {}", b.signature.frames.join(" < "))?;
+            }
+            for x in &example {
+                writeln!(detail, "example: #{} {} {}", x.index, x.operator, x.target)?;
+                for sig in &x.signals {
+                    writeln!(detail, "{} [{}] {}", sig.verdict, sig.oracle, sig.detail)?;
+                }
+            }
+            let (label, command) = r.reproducer(b);
+            writeln!(detail, "reproduce with {label}:\n{command}")?;
+            writeln!(
+                body,
+                "      <failure type=\"{}\" message=\"{}\">{}</failure>\n    </testcase>",
+                b.verdict,
+                xml(&format!("{}: {}", r.rule(b), Oracles::describe(&r.rule(b)))),
+                xml(&detail)
+            )?;
+        }
+        writeln!(body, "  </testsuite>")?;
+        tests += buckets.len() + 1;
+        failures += buckets.len();
+    }
+
+    let all: Vec<&Exchange> = r.exchanges.iter().collect();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        out,
+        "<testsuites name=\"{}\" tests=\"{tests}\" failures=\"{failures}\" time=\"{:.3}\">",
+        xml(&format!("api-fuzzkit {}", r.profile.name)),
+        secs(&all)
+    )?;
+    out.push_str(&body);
+    out.push_str("</testsuites>\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifacts::Session;
+    use crate::bucket;
+    use crate::oracle::Signal;
+    use crate::plan;
+    use crate::runner::Outcome;
+    use crate::scheduler::Scheduler;
+    use crate::testutil;
+    use crate::transport::Response;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// A case made by `operator` that drew `status` and `body`, flagged as a finding.
+    fn outcome(p: &Profile, index: usize, operator: &str, status: u16, body: &str) -> Outcome {
+        let mut case = plan::baseline(&p.endpoints[0]);
+        case.operator = operator.into();
+        let mut sent = case.request.clone();
+        sent.headers.push(("X-Env".into(), "sandbox".into()));
+        let resp = Response { status, headers: Vec::new(), body: body.into(), elapsed: Duration::from_millis(20), sent };
+        let signal = Signal { oracle: "status.5xx".into(), verdict: Verdict::Finding, detail: format!("server error {status}") };
+        Outcome { index, case, result: Ok(resp), signals: vec![signal] }
+    }
+
+    /// Write a `mode` session of `outcomes` under a fresh directory named
+    /// for `name`; the buckets at `known` were seen by an earlier session.
+    fn session(name: &str, p: &Profile, mode: Mode, outcomes: &[Outcome], known: &[usize]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("fuzzkit-report-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let profile_path = root.join("profile.toml");
+        fs::write(&profile_path, "name = \"test\"\n\n[[endpoints]]\npath = \"/v1/returns\"\nmethod = \"POST\"\n").unwrap();
+        let mut s = Session::create(&root, &p.name, profile_path.to_str().unwrap()).unwrap();
+        for o in outcomes {
+            s.record(o).unwrap();
+        }
+        let mut buckets = bucket::group(&p.endpoints, outcomes);
+        for &i in known {
+            buckets[i].new = false;
+        }
+        s.buckets(&buckets).unwrap();
+        s.finish(1, mode, outcomes.len(), 0, &Scheduler::new(&p.limits).snapshot(), Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn html_escapes_response_text_and_markdown_keeps_it_in_code() {
+        let p = testutil::profile("http://127.0.0.1:1");
+        let outcomes = [
+            outcome(&p, 0, "null.set", 500, r#"{"error":"<script>alert(1)</script> & <b>"}"#),
+            outcome(&p, 1, "key.drop", 502, r#"{"error":"use ``raw`` quotes"}"#),
+        ];
+        let dir = session("escape", &p, Mode::Fuzz, &outcomes, &[]);
+        let r = Summary::load(&dir, &p).unwrap();
+
+        let page = html(&r).unwrap();
+        let md = markdown(&r).unwrap();
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+        assert!(!page.contains("<script>") && !page.contains("<b>"), "{page}");
+        assert!(page.contains("<code>&lt;script&gt;alert(#)&lt;/script&gt; &amp; &lt;b&gt;</code>"), "{page}");
+        assert!(md.contains("- Message: `<script>alert(#)</script> & <b>`\n"), "{md}");
+        assert!(md.contains("- Message: ``` use ``raw`` quotes ```\n"), "{md}");
+    }
+
+    #[test]
+    fn sarif_baseline_state_follows_the_bucket_db() {
+        let p = testutil::profile("http://127.0.0.1:1");
+        let outcomes = [outcome(&p, 0, "null.set", 500, "{}"), outcome(&p, 1, "key.drop", 502, "{}")];
+        let dir = session("sarif", &p, Mode::Fuzz, &outcomes, &[1]);
+        let r = Summary::load(&dir, &p).unwrap();
+        let ids: Vec<String> = r.buckets.iter().map(|b| b.id.clone()).collect();
+
+        let doc: Value = serde_json::from_str(&sarif(&r).unwrap()).unwrap();
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+        let results = doc["runs"][0]["results"].as_array().unwrap();
+        let states: Vec<(&str, &str)> = results
+            .iter()
+            .map(|x| (x["partialFingerprints"]["apiFuzzkitBucket/v1"].as_str().unwrap(), x["baselineState"].as_str().unwrap()))
+            .collect();
+        assert_eq!(states, [(ids[0].as_str(), "new"), (ids[1].as_str(), "unchanged")]);
+        for x in results {
+            assert_eq!(x["ruleId"], "status.5xx");
+            assert_eq!(x["level"], "error");
+            assert_eq!(x["locations"][0]["physicalLocation"]["region"]["startLine"], 3);
+        }
+        assert_eq!(doc["runs"][0]["tool"]["driver"]["rules"][0]["id"], "status.5xx");
+    }
+
+    #[test]
+    fn reproducers_are_curl_unless_the_request_is_rebuilt_at_send_time() {
+        let mut p = testutil::profile("http://127.0.0.1:1");
+        let outcomes = [outcome(&p, 0, "null.set", 500, "{}"), outcome(&p, 1, "auth.none", 500, "{}")];
+        let reproducers = |p: &Profile, mode: Mode| {
+            let dir = session(&format!("curl-{mode}"), p, mode, &outcomes, &[]);
+            let r = Summary::load(&dir, p).unwrap();
+            let out: Vec<(String, String)> = r.buckets.iter().map(|b| r.reproducer(b)).collect();
+            let junit = junit(&r).unwrap();
+            fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+            (out, dir, junit)
+        };
+
+        let (fuzz, dir, junit) = reproducers(&p, Mode::Fuzz);
+        assert_eq!(fuzz[0].0, "curl");
+        assert!(fuzz[0].1.starts_with("curl \\\n  http://127.0.0.1:1/v1/returns"), "{}", fuzz[0].1);
+        assert!(fuzz[0].1.contains("-H 'X-Env: sandbox'"), "{}", fuzz[0].1);
+        assert_eq!(fuzz[1].0, "replay (the credential is tampered with at send time)");
+        assert_eq!(fuzz[1].1, format!("api-fuzzkit --sandbox yes replay --from {} --case 1", shell_quote(&dir.to_string_lossy())));
+        assert!(junit.contains(r#"tests="3" failures="2""#), "{junit}");
+        assert!(junit.contains("reproduce with curl:\ncurl \\\n  http://127.0.0.1:1/v1/returns"), "{junit}");
+
+        let (race, _, _) = reproducers(&p, Mode::Race);
+        assert!(race.iter().all(|(label, command)| label == "the race mode (the finding spans several requests)"
+            && command == "api-fuzzkit --sandbox yes run --mode race"));
+
+        p.signing = Some(testutil::signing(""));
+        let (signed, _, _) = reproducers(&p, Mode::Fuzz);
+        assert!(signed.iter().all(|(label, _)| label == "replay (requests are signed at send time)"), "{signed:?}");
+    }
+}
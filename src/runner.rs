@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+use crate::oracle::{Signal, Verdict};
+use crate::plan::Case;
+use crate::scheduler::Scheduler;
+use crate::transport::{self, Response, Transport};
+
+pub struct Outcome {
+    pub index: usize,
+    pub case: Case,
+    pub result: Result<Response>,
+    /// What the oracles raised on this outcome, filled in by `Oracles::judge`
+    pub signals: Vec<Signal>,
+}
+
+impl Outcome {
+    pub fn verdict(&self) -> Verdict {
+        self.signals.iter().map(|s| s.verdict).max().unwrap_or(Verdict::Pass)
+    }
+
+    /// One line for the console: case, status, size, content type, latency.
+    pub fn summary(&self) -> String {
+        match &self.result {
+            Ok(resp) => {
+                let content_type = resp
+                    .headers
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+                    .map_or("-", |(_, v)| v.as_str());
+                format!(
+                    "#{} {} -> {} ({} bytes {content_type}, {} ms)",
+                    self.index,
+                    self.case,
+                    resp.status,
+                    resp.body.len(),
+                    resp.elapsed.as_millis()
+                )
+            }
+            Err(e) => format!("#{} {} -> error: {e:#}", self.index, self.case),
+        }
+    }
+
+    /// The summary line plus one indented line per raised signal.
+    pub fn report(&self) -> String {
+        let mut out = self.summary();
+        for s in &self.signals {
+            out.push_str(&format!("\n    {} [{}] {}", s.verdict, s.oracle, s.detail));
+        }
+        out
+    }
+}
+
+/// Send every `(index, case)` through the scheduler. Transport errors are retried up to
+/// `retries` times; each retry is charged against the budget. A case refused
+/// before anything was sent (e.g. over `max_payload_bytes`) is neither
+/// retried nor charged. Cases that did not fit in the budget are not returned.
+pub async fn run(
+    transport: Arc<Transport>,
+    sched: Arc<Scheduler>,
+    retries: u32,
+    cases: Vec<(usize, Case)>,
+) -> Vec<Outcome> {
+    let mut tasks = JoinSet::new();
+    for (index, case) in cases {
+        let Some(slot) = sched.acquire().await else {
+            tracing::warn!(target: "scheduler", index, "request budget exhausted; stopping");
+            break;
+        };
+        let transport = Arc::clone(&transport);
+        tasks.spawn(async move {
+            let mut result = transport.send(&case.request).await;
+            let mut attempt = 0;
+            while let Err(e) = &result {
+                if !transport::is_transport(e) || attempt >= retries || !slot.retry().await {
+                    break;
+                }
+                attempt += 1;
+                tracing::debug!(target: "scheduler", index, attempt, error = %e, "retrying");
+                result = transport.send(&case.request).await;
+            }
+            match &result {
+                Err(e) if !transport::is_transport(e) => slot.refund(),
+                _ => slot.finish(result.is_ok()),
+            }
+            Outcome { index, case, result, signals: Vec::new() }
+        });
+    }
+
+    let mut outcomes = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => tracing::error!(error = %e, "request task panicked"),
+        }
+    }
+    outcomes.sort_by_key(|o| o.index);
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan;
+    use crate::scheduler::Scheduler;
+    use crate::testutil;
+    use crate::transport::Body;
+    use crate::ByteSize;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn refused_cases_are_not_retried_or_charged() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        p.safety.max_payload_bytes = ByteSize(256);
+        let sched = Scheduler::new(&p.limits);
+        let transport = Arc::new(testutil::transport(&p));
+        let fits = plan::baseline(&p.endpoints[0]);
+        let mut over = fits.clone();
+        over.request.body = Some(Body::Bytes(vec![b'A'; 512]));
+
+        let outcomes = run(transport, Arc::clone(&sched), 3, vec![(0, over), (1, fits)]).await;
+
+        assert!(outcomes[0].result.as_ref().unwrap_err().to_string().contains("exceeds max_payload_bytes"));
+        assert!(outcomes[1].result.is_ok());
+        let s = sched.snapshot();
+        assert_eq!((s.issued, s.retries, s.completed, s.failed), (1, 0, 1, 0));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
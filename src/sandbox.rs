@@ -0,0 +1,96 @@
+//! Sandbox confirmation: the forced header that echoes `--sandbox yes`, and
+//! the optional health probe that must confirm sandbox mode before a run.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::bypass::Tamper;
+use crate::scheduler::Scheduler;
+use crate::transport::{Request, Response, Transport};
+use crate::Refused;
+
+/// `[safety.sandbox_header]`: a forced header every request must carry.
+#[derive(Debug, Deserialize)]
+pub struct EchoHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl Default for EchoHeader {
+    fn default() -> Self {
+        Self { name: "X-Env".into(), value: "sandbox".into() }
+    }
+}
+
+impl EchoHeader {
+    pub fn check(&self, forced: &HashMap<String, String>) -> Result<()> {
+        let found = forced.iter().find(|(k, _)| k.eq_ignore_ascii_case(&self.name));
+        match found {
+            Some((_, v)) if v.eq_ignore_ascii_case(&self.value) => Ok(()),
+            Some((k, v)) => bail!("forced header {k}: {v} does not echo the sandbox attestation (expected {})", self.value),
+            None => bail!("force_headers must include {}: {} to echo the sandbox attestation", self.name, self.value),
+        }
+    }
+}
+
+/// `[safety.sandbox_probe]`: a GET that must answer in a way only the sandbox does.
+#[derive(Debug, Deserialize)]
+pub struct Probe {
+    pub path: String,
+    #[serde(default = "default_status")]
+    pub expect_status: u16,
+    /// Substring the response body must contain
+    #[serde(default)]
+    pub expect_body: Option<String>,
+    /// Response header that must be present with this value
+    #[serde(default)]
+    pub expect_header: Option<EchoHeader>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+impl Probe {
+    /// Send the probe (charged to the budget like any request) and refuse the
+    /// session unless every expectation holds.
+    pub async fn confirm(&self, transport: &Transport, sched: &Arc<Scheduler>) -> Result<()> {
+        let Some(slot) = sched.acquire().await else { bail!("request budget exhausted before sandbox probe") };
+        let req = Request {
+            method: "GET".into(),
+            path: self.path.clone(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: None,
+            auth: Tamper::None,
+            origin: None,
+            signed: false,
+        };
+        let resp = transport.send(&req).await;
+        slot.finish(resp.is_ok());
+        let resp = resp.map_err(|e| e.context("sandbox probe failed; refusing to run"))?;
+        self.expect(&resp).context(Refused)?;
+        tracing::info!(target: "session", path = %self.path, "sandbox probe confirmed");
+        Ok(())
+    }
+
+    fn expect(&self, resp: &Response) -> Result<()> {
+        if resp.status != self.expect_status {
+            bail!("sandbox probe {} returned {} (expected {}); refusing to run", self.path, resp.status, self.expect_status);
+        }
+        if let Some(needle) = &self.expect_body {
+            if !String::from_utf8_lossy(&resp.body).contains(needle.as_str()) {
+                bail!("sandbox probe {} body does not contain {needle:?}; refusing to run", self.path);
+            }
+        }
+        if let Some(h) = &self.expect_header {
+            let ok = resp.headers.iter().any(|(k, v)| k.eq_ignore_ascii_case(&h.name) && v.eq_ignore_ascii_case(&h.value));
+            if !ok {
+                bail!("sandbox probe {} lacks header {}: {}; refusing to run", self.path, h.name, h.value);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::Limits;
+
+/// Token bucket with a capacity of one token, so requests are evenly spaced
+/// at `rate_per_sec` and never burst above it.
+struct TokenBucket {
+    interval: Duration,
+    next_free: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_sec: u32) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / f64::from(rate_per_sec.max(1))),
+            next_free: Instant::now(),
+        }
+    }
+
+    /// Reserve the next `n` tokens; returns how long the caller must wait for
+    /// the first. Later requests wait out the rest, so a burst keeps the average rate.
+    fn reserve(&mut self, n: u32) -> Duration {
+        let now = Instant::now();
+        let slot = self.next_free.max(now);
+        self.next_free = slot + self.interval * n;
+        slot - now
+    }
+}
+
+/// Live counters, readable while the session runs.
+#[derive(Debug, Default)]
+struct Counters {
+    issued: AtomicU32,
+    retries: AtomicU32,
+    completed: AtomicU32,
+    failed: AtomicU32,
+    token_fetches: AtomicU32,
+    in_flight: AtomicUsize,
+    peak_in_flight: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub issued: u32,
+    pub retries: u32,
+    pub completed: u32,
+    pub failed: u32,
+    pub token_fetches: u32,
+    pub in_flight: usize,
+    pub peak_in_flight: usize,
+    pub budget: u32,
+    pub concurrency: usize,
+    pub elapsed: Duration,
+}
+
+impl Snapshot {
+    /// Observed requests per second since the scheduler was created,
+    /// measured over at least one second so a short session is not inflated.
+    pub fn rate(&self) -> f64 {
+        f64::from(self.issued) / self.elapsed.as_secs_f64().max(1.0)
+    }
+
+    pub fn log(&self, what: &str) {
+        tracing::info!(target: "scheduler",
+            issued = self.issued,
+            budget = self.budget,
+            retries = self.retries,
+            completed = self.completed,
+            failed = self.failed,
+            token_fetches = self.token_fetches,
+            in_flight = self.in_flight,
+            peak_in_flight = self.peak_in_flight,
+            concurrency = self.concurrency,
+            rate = format!("{:.2}", self.rate()),
+            "{what}"
+        );
+    }
+}
+
+/// Enforces `Limits` at runtime: at most `concurrency` requests in flight,
+/// paced at `rate_per_sec`, hard stop at `request_budget` (retries included).
+pub struct Scheduler {
+    slots: Arc<Semaphore>,
+    bucket: Mutex<TokenBucket>,
+    budget: u32,
+    concurrency: usize,
+    counters: Counters,
+    started: Instant,
+}
+
+impl Scheduler {
+    pub fn new(limits: &Limits) -> Arc<Self> {
+        Arc::new(Self {
+            slots: Arc::new(Semaphore::new(limits.concurrency.max(1))),
+            bucket: Mutex::new(TokenBucket::new(limits.rate_per_sec)),
+            budget: limits.request_budget,
+            concurrency: limits.concurrency.max(1),
+            counters: Counters::default(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Wait for a concurrency slot and a rate token. Returns `None` once the
+    /// budget is spent; callers must stop issuing work at that point.
+    pub async fn acquire(self: &Arc<Self>) -> Option<Slot> {
+        let permit = self.slots.clone().acquire_owned().await.ok()?;
+        if !self.admit(1).await {
+            return None;
+        }
+        self.enter(1);
+        Some(Slot { _permit: permit, sched: Arc::clone(self) })
+    }
+
+    /// Admit `n` requests to be released at one instant. The burst holds `n`
+    /// slots, or every slot when `n` is above `concurrency` (only a profile's
+    /// `burst_ceiling` allows that), and is charged `n` units of budget and
+    /// `n` rate tokens. Returns `None` when the budget cannot cover it.
+    pub async fn burst(self: &Arc<Self>, n: usize) -> Option<Burst> {
+        let permits = self.slots.clone().acquire_many_owned(n.min(self.concurrency) as u32).await.ok()?;
+        if !self.admit(n as u32).await {
+            return None;
+        }
+        self.enter(n);
+        Some(Burst { _permits: permits, sched: Arc::clone(self), n })
+    }
+
+    /// Charge an OAuth2 token fetch to the budget and the rate limit. It
+    /// takes no slot: the request it authenticates already holds one, and
+    /// waiting for a second could deadlock at `concurrency = 1`.
+    pub async fn charge_token_fetch(&self) -> bool {
+        if !self.admit(1).await {
+            return false;
+        }
+        self.counters.token_fetches.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    fn enter(&self, n: usize) {
+        let now = self.counters.in_flight.fetch_add(n, Ordering::SeqCst) + n;
+        self.counters.peak_in_flight.fetch_max(now, Ordering::SeqCst);
+    }
+
+    /// Take `n` units of budget, then wait for the token bucket.
+    async fn admit(&self, n: u32) -> bool {
+        let taken = self
+            .counters
+            .issued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |i| (i + n <= self.budget).then_some(i + n))
+            .is_ok();
+        if !taken {
+            return false;
+        }
+        let wait = self.bucket.lock().expect("token bucket poisoned").reserve(n);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        true
+    }
+
+    fn finish(&self, ok: bool) {
+        let c = &self.counters;
+        if ok {
+            c.completed.fetch_add(1, Ordering::SeqCst);
+        } else {
+            c.failed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let c = &self.counters;
+        Snapshot {
+            issued: c.issued.load(Ordering::SeqCst),
+            retries: c.retries.load(Ordering::SeqCst),
+            completed: c.completed.load(Ordering::SeqCst),
+            failed: c.failed.load(Ordering::SeqCst),
+            token_fetches: c.token_fetches.load(Ordering::SeqCst),
+            in_flight: c.in_flight.load(Ordering::SeqCst),
+            peak_in_flight: c.peak_in_flight.load(Ordering::SeqCst),
+            budget: self.budget,
+            concurrency: self.concurrency,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// Log counters every `every` until the returned handle is aborted.
+    pub fn spawn_reporter(self: &Arc<Self>, every: Duration) -> tokio::task::JoinHandle<()> {
+        let sched = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(every);
+            tick.tick().await;
+            loop {
+                tick.tick().await;
+                sched.snapshot().log("progress");
+            }
+        })
+    }
+}
+
+/// A held concurrency slot. Dropping it frees the slot.
+pub struct Slot {
+    _permit: OwnedSemaphorePermit,
+    sched: Arc<Scheduler>,
+}
+
+impl Slot {
+    /// Charge a retry against the budget and rate limit, keeping this slot.
+    pub async fn retry(&self) -> bool {
+        if !self.sched.admit(1).await {
+            return false;
+        }
+        self.sched.counters.retries.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    pub fn finish(&self, ok: bool) {
+        self.sched.finish(ok);
+    }
+
+    /// Give back the unit of budget the request took: it was refused before
+    /// anything was sent, so it is neither completed nor failed.
+    pub fn refund(&self) {
+        self.sched.counters.issued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.sched.counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Slots held by a synchronized burst. Dropping it frees them all.
+pub struct Burst {
+    _permits: OwnedSemaphorePermit,
+    sched: Arc<Scheduler>,
+    n: usize,
+}
+
+impl Burst {
+    pub fn finish(&self, ok: bool) {
+        self.sched.finish(ok);
+    }
+}
+
+impl Drop for Burst {
+    fn drop(&mut self) {
+        self.sched.counters.in_flight.fetch_sub(self.n, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn scheduler(concurrency: usize, rate_per_sec: u32, request_budget: u32) -> Arc<Scheduler> {
+        let mut limits = testutil::profile("http://127.0.0.1:1").limits;
+        limits.concurrency = concurrency;
+        limits.rate_per_sec = rate_per_sec;
+        limits.request_budget = request_budget;
+        Scheduler::new(&limits)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_hard_at_the_request_budget() {
+        let sched = scheduler(4, 1000, 3);
+
+        for _ in 0..3 {
+            sched.acquire().await.expect("within budget").finish(true);
+        }
+
+        assert!(sched.acquire().await.is_none());
+        assert!(sched.burst(1).await.is_none());
+        let snap = sched.snapshot();
+        assert_eq!((snap.issued, snap.completed, snap.in_flight), (3, 3, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_are_charged_to_the_budget() {
+        let sched = scheduler(4, 1000, 3);
+        let slot = sched.acquire().await.unwrap();
+
+        assert!(slot.retry().await);
+        assert!(slot.retry().await);
+        assert!(!slot.retry().await);
+        slot.finish(false);
+        drop(slot);
+
+        assert!(sched.acquire().await.is_none());
+        let snap = sched.snapshot();
+        assert_eq!((snap.issued, snap.retries, snap.failed), (3, 2, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn holds_at_most_concurrency_slots() {
+        let sched = scheduler(2, 1000, 10);
+        let first = sched.acquire().await.unwrap();
+        let _second = sched.acquire().await.unwrap();
+
+        let waiting = tokio::spawn({
+            let sched = Arc::clone(&sched);
+            async move { sched.acquire().await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!waiting.is_finished(), "a third request got a slot");
+        assert_eq!(sched.snapshot().in_flight, 2);
+
+        drop(first);
+        assert!(waiting.await.unwrap());
+        assert_eq!(sched.snapshot().peak_in_flight, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_requests_at_rate_per_sec() {
+        let sched = scheduler(8, 10, 100);
+        let started = Instant::now();
+
+        let mut at = Vec::new();
+        for _ in 0..5 {
+            drop(sched.acquire().await.unwrap());
+            at.push(started.elapsed());
+        }
+
+        let expected: Vec<Duration> = (0..5).map(|i| Duration::from_millis(100 * i)).collect();
+        assert_eq!(at, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_burst_takes_its_budget_and_tokens_at_once() {
+        let sched = scheduler(2, 10, 10);
+        let started = Instant::now();
+
+        // Above concurrency (as a burst_ceiling allows): every slot, n units of budget.
+        let burst = sched.burst(3).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        let snap = sched.snapshot();
+        assert_eq!((snap.issued, snap.in_flight, snap.peak_in_flight), (3, 3, 3));
+        let waiting = tokio::spawn({
+            let sched = Arc::clone(&sched);
+            async move { sched.acquire().await.map(|_| Instant::now()) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished(), "the burst left a slot free");
+
+        drop(burst);
+        // The next request waits out the three tokens the burst took.
+        let admitted = waiting.await.unwrap().unwrap();
+        assert_eq!(admitted - started, Duration::from_millis(300));
+        assert_eq!(sched.snapshot().in_flight, 0);
+        assert!(sched.burst(7).await.is_none(), "6 units of budget are left");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_fetches_take_budget_and_tokens_but_no_slot() {
+        let sched = scheduler(1, 10, 3);
+        let started = Instant::now();
+        let _slot = sched.acquire().await.unwrap();
+
+        assert!(sched.charge_token_fetch().await);
+        assert!(sched.charge_token_fetch().await);
+        assert!(!sched.charge_token_fetch().await);
+
+        assert_eq!(started.elapsed(), Duration::from_millis(200));
+        let snap = sched.snapshot();
+        assert_eq!((snap.issued, snap.token_fetches, snap.in_flight), (3, 2, 1));
+    }
+}
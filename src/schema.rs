@@ -0,0 +1,763 @@
+//! The JSON Schema subset kept on endpoints (by hand or by `import openapi`):
+//! types, enums, numeric bounds, lengths, patterns, formats, item counts,
+//! required keys and closed objects. From it we build valid instances and
+//! instances that break exactly one rule.
+
+use regex::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::mutate::{self, Step};
+use crate::rng::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+/// Keys follow JSON Schema spelling (`maxLength`, `additionalProperties`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Schema {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Kind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(rename = "enum", skip_serializing_if = "Vec::is_empty")]
+    pub enum_values: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maximum: Option<f64>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub exclusive_minimum: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub exclusive_maximum: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Box<Schema>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, Schema>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+    /// Only `false` is meaningful: the object is closed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub additional_properties: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub example: Option<Value>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// `generation` on an endpoint: what its schema-driven cases look like.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Generation {
+    /// Random instances that satisfy every constraint
+    #[default]
+    Valid,
+    /// Random instances with exactly one constraint broken
+    Violate,
+}
+
+/// One constraint of one node, named with its JSON Schema keyword.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    Type,
+    MaxLength,
+    MinLength,
+    Maximum,
+    Minimum,
+    Enum,
+    Pattern,
+    Format,
+    MaxItems,
+    MinItems,
+    Required(String),
+    AdditionalProperties,
+}
+
+impl Rule {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::Type => "type",
+            Rule::MaxLength => "maxLength",
+            Rule::MinLength => "minLength",
+            Rule::Maximum => "maximum",
+            Rule::Minimum => "minimum",
+            Rule::Enum => "enum",
+            Rule::Pattern => "pattern",
+            Rule::Format => "format",
+            Rule::MaxItems => "maxItems",
+            Rule::MinItems => "minItems",
+            Rule::Required(_) => "required",
+            Rule::AdditionalProperties => "additionalProperties",
+        }
+    }
+}
+
+/// A body with one rule broken at `pointer`.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub pointer: String,
+    pub body: Value,
+}
+
+/// Member added to closed objects.
+const EXTRA_KEY: &str = "fuzzkit_extra";
+/// Longest random string when `maxLength` leaves it open.
+const RANDOM_SPAN: u64 = 16;
+
+fn format_example(format: &str) -> Option<&'static str> {
+    Some(match format {
+        "date" => "2024-01-31",
+        "date-time" => "2024-01-31T12:00:00Z",
+        "time" => "12:00:00",
+        "email" => "fuzzkit@example.com",
+        "uuid" => "3f2b8c4e-9a51-4d6b-8f0e-1c2d3e4f5a6b",
+        "uri" | "url" => "https://example.com/fuzzkit",
+        "hostname" => "example.com",
+        "ipv4" => "192.0.2.1",
+        "ipv6" => "2001:db8::1",
+        "byte" => "ZnV6emtpdA==",
+        _ => return None,
+    })
+}
+
+fn format_breaker(format: &str) -> Option<&'static str> {
+    Some(match format {
+        "date" => "2024-13-45",
+        "date-time" => "2024-01-31 25:61",
+        "time" => "25:61:61",
+        "email" => "fuzzkit.example.com",
+        "uuid" => "3f2b8c4e-zzzz-4d6b-8f0e",
+        "uri" | "url" => "::not a uri",
+        "hostname" => "-bad_host-.",
+        "ipv4" => "256.0.2.1",
+        "ipv6" => "2001:db8:::zz",
+        "byte" => "not*base64",
+        _ => return None,
+    })
+}
+
+/// Patterns compiled once per thread; validation runs them for every candidate.
+fn regex(pattern: &str) -> Option<Regex> {
+    thread_local! {
+        static CACHE: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+    }
+    CACHE.with(|c| c.borrow_mut().entry(pattern.to_string()).or_insert_with(|| Regex::new(pattern).ok()).clone())
+}
+
+/// Format checks for the formats `format_breaker` knows; others always pass.
+fn format_ok(format: &str, s: &str) -> bool {
+    let re = |p: &str| regex(p).is_some_and(|re| re.is_match(s));
+    match format {
+        "date" => re(r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])$"),
+        "date-time" => re(r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])[Tt]([01]\d|2[0-3]):[0-5]\d:[0-5]\d(\.\d+)?([Zz]|[+-]([01]\d|2[0-3]):[0-5]\d)$"),
+        "time" => re(r"^([01]\d|2[0-3]):[0-5]\d:[0-5]\d(\.\d+)?([Zz]|[+-]([01]\d|2[0-3]):[0-5]\d)?$"),
+        "email" => re(r"^[^@\s]+@[^@\s]+\.[^@\s]+$"),
+        "uuid" => re(r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$"),
+        "uri" | "url" => url::Url::parse(s).is_ok(),
+        "hostname" => re(r"^[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?(\.[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?)*$"),
+        "ipv4" => s.parse::<std::net::Ipv4Addr>().is_ok(),
+        "ipv6" => s.parse::<std::net::Ipv6Addr>().is_ok(),
+        "byte" => s.len().is_multiple_of(4) && re(r"^[A-Za-z0-9+/]*={0,2}$"),
+        _ => true,
+    }
+}
+
+/// Build a string matching `hir`: the smallest one when `rng` is `None`,
+/// otherwise random choices of alternatives, classes and repeat counts.
+fn synth(hir: &Hir, rng: &mut Option<&mut Rng>, out: &mut String) {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => {}
+        HirKind::Literal(lit) => out.push_str(&String::from_utf8_lossy(&lit.0)),
+        HirKind::Class(Class::Unicode(cls)) => {
+            let Some(first) = cls.ranges().first() else { return };
+            // Stay in ASCII when the class allows it: `\d` also matches every
+            // other script's digits, which no API means by it.
+            let ascii: Vec<(u32, u32)> = cls
+                .ranges()
+                .iter()
+                .filter(|r| r.start().is_ascii())
+                .map(|r| (r.start() as u32, (r.end() as u32).min(0x7e)))
+                .collect();
+            let c = match (rng, ascii.is_empty()) {
+                (Some(rng), false) => {
+                    let (lo, hi) = *rng.pick(&ascii);
+                    char::from_u32(lo + rng.below((hi - lo) as usize + 1) as u32).unwrap_or(first.start())
+                }
+                (Some(rng), true) => rng.pick(cls.ranges()).start(),
+                (None, _) => first.start(),
+            };
+            out.push(c);
+        }
+        HirKind::Class(Class::Bytes(cls)) => {
+            if let Some(r) = cls.ranges().first() {
+                out.push(r.start() as char);
+            }
+        }
+        HirKind::Repetition(rep) => {
+            let extra = match rng {
+                Some(rng) => {
+                    let room = rep.max.map_or(3, |max| (max - rep.min).min(3));
+                    rng.below(room as usize + 1) as u32
+                }
+                None => 0,
+            };
+            for _ in 0..rep.min + extra {
+                synth(&rep.sub, rng, out);
+            }
+        }
+        HirKind::Capture(cap) => synth(&cap.sub, rng, out),
+        HirKind::Concat(parts) => parts.iter().for_each(|h| synth(h, rng, out)),
+        HirKind::Alternation(alts) => {
+            let alt = match rng {
+                Some(rng) => rng.pick(alts),
+                None => &alts[0],
+            };
+            synth(alt, rng, out);
+        }
+    }
+}
+
+impl Schema {
+    /// A string matching `pattern`, if it parses.
+    fn pattern_string(&self, rng: Option<&mut Rng>) -> Option<String> {
+        let hir = regex_syntax::parse(self.pattern.as_deref()?).ok()?;
+        let mut out = String::new();
+        synth(&hir, &mut { rng }, &mut out);
+        let len = out.chars().count() as u64;
+        let fits = self.min_length.is_none_or(|m| len >= m) && self.max_length.is_none_or(|m| len <= m);
+        Some(out).filter(|s| fits && self.matches(s))
+    }
+
+    fn kind(&self) -> Option<Kind> {
+        self.kind.or_else(|| (!self.properties.is_empty()).then_some(Kind::Object))
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        match &self.pattern {
+            Some(p) => regex(p).is_none_or(|re| re.is_match(s)),
+            None => true,
+        }
+    }
+
+    /// Integer bounds after applying exclusivity.
+    fn int_bounds(&self) -> (i64, i64) {
+        let lo = self.minimum.map_or(i64::MIN, |m| m.ceil() as i64 + i64::from(self.exclusive_minimum && m.fract() == 0.0));
+        let hi = self.maximum.map_or(i64::MAX, |m| m.floor() as i64 - i64::from(self.exclusive_maximum && m.fract() == 0.0));
+        (lo, hi)
+    }
+
+    fn number_in(&self, t: f64) -> f64 {
+        match (self.minimum, self.maximum) {
+            (Some(lo), Some(hi)) => lo + (hi - lo) * t,
+            (Some(lo), None) => lo + 1.0 + t,
+            (None, Some(hi)) => hi - 1.0 - t,
+            (None, None) => 1.5 + t,
+        }
+    }
+
+    fn string_of(&self, base: &str) -> String {
+        let min = self.min_length.unwrap_or(0) as usize;
+        let max = self.max_length.map_or(usize::MAX, |m| m as usize);
+        let mut s: String = base.chars().take(max).collect();
+        while s.chars().count() < min {
+            s.push('a');
+        }
+        s
+    }
+
+    /// A deterministic valid instance: the declared example, else the first
+    /// enum member, else the plainest value that satisfies every constraint.
+    pub fn example(&self) -> Value {
+        if let Some(v) = &self.example {
+            return v.clone();
+        }
+        if let Some(v) = self.enum_values.first() {
+            return v.clone();
+        }
+        match self.kind() {
+            Some(Kind::String) => {
+                let candidates = [self.format.as_deref().and_then(format_example).unwrap_or("fuzzkit"), "A", "a1", "0"];
+                let s = candidates
+                    .iter()
+                    .map(|c| self.string_of(c))
+                    .find(|s| self.matches(s))
+                    .or_else(|| self.pattern_string(None))
+                    .unwrap_or_else(|| self.string_of("fuzzkit"));
+                Value::String(s)
+            }
+            Some(Kind::Integer) => {
+                let (lo, hi) = self.int_bounds();
+                Value::from(1i64.clamp(lo, hi.max(lo)))
+            }
+            Some(Kind::Number) => Value::from(self.number_in(0.5)),
+            Some(Kind::Boolean) => Value::Bool(true),
+            Some(Kind::Array) => {
+                let item = self.items.as_ref().map_or(Value::String("fuzzkit".into()), |s| s.example());
+                Value::Array(vec![item; self.min_items.unwrap_or(1).max(1) as usize])
+            }
+            Some(Kind::Object) => {
+                Value::Object(self.properties.iter().map(|(k, s)| (k.clone(), s.example())).collect())
+            }
+            None => Value::String("fuzzkit".into()),
+        }
+    }
+
+    /// A random valid instance; optional members are present half the time.
+    pub fn generate(&self, rng: &mut Rng) -> Value {
+        if !self.enum_values.is_empty() {
+            return rng.pick(&self.enum_values).clone();
+        }
+        match self.kind() {
+            Some(Kind::String) if self.pattern.is_none() && self.format.is_none() => {
+                const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+                let min = self.min_length.unwrap_or(0);
+                let max = self.max_length.unwrap_or(min + RANDOM_SPAN).max(min).min(min + RANDOM_SPAN);
+                let len = min + rng.below((max - min + 1) as usize) as u64;
+                Value::String((0..len).map(|_| *rng.pick(ALPHABET) as char).collect())
+            }
+            Some(Kind::Integer) => {
+                let (lo, hi) = self.int_bounds();
+                let (lo, hi) = (lo.max(-1_000_000), hi.min(1_000_000).max(lo.max(-1_000_000)));
+                Value::from(lo + rng.below((hi - lo + 1) as usize) as i64)
+            }
+            Some(Kind::Number) => Value::from(self.number_in((rng.below(1000) as f64 + 1.0) / 1002.0)),
+            Some(Kind::Boolean) => Value::Bool(rng.below(2) == 1),
+            Some(Kind::Array) => {
+                let min = self.min_items.unwrap_or(0);
+                let max = self.max_items.unwrap_or(min + 3).max(min).min(min + 3);
+                let len = min + rng.below((max - min + 1) as usize) as u64;
+                let items = self.items.as_deref().cloned().unwrap_or_default();
+                Value::Array((0..len).map(|_| items.generate(rng)).collect())
+            }
+            Some(Kind::Object) => {
+                let mut members = Map::new();
+                for (k, s) in &self.properties {
+                    if self.required.contains(k) || rng.below(2) == 1 {
+                        members.insert(k.clone(), s.generate(rng));
+                    }
+                }
+                Value::Object(members)
+            }
+            Some(Kind::String) if self.format.is_none() => {
+                Value::String(self.pattern_string(Some(rng)).unwrap_or_else(|| self.example().as_str().unwrap_or_default().into()))
+            }
+            _ => self.example(),
+        }
+    }
+
+    /// Values that break `rule` on this node, given its current value, best
+    /// first. The caller keeps the first that breaks nothing else.
+    fn breakers(&self, rule: &Rule, current: &Value) -> Vec<Value> {
+        let text = current.as_str().map(str::to_string).unwrap_or_else(|| self.example().as_str().unwrap_or("fuzzkit").to_string());
+        let chars: Vec<char> = text.chars().collect();
+        let strings = |v: Vec<String>| v.into_iter().map(Value::String).collect();
+        match rule {
+            Rule::Type => self
+                .kind()
+                .map(|k| match k {
+                    Kind::String => Value::from(12345),
+                    Kind::Integer => Value::String("1".into()),
+                    Kind::Number => Value::String("1.5".into()),
+                    Kind::Boolean => Value::String("true".into()),
+                    Kind::Array => Value::Object(Map::new()),
+                    Kind::Object => Value::Array(Vec::new()),
+                })
+                .into_iter()
+                .collect(),
+            Rule::MaxLength => {
+                let Some(max) = self.max_length else { return Vec::new() };
+                let len = max as usize + 1;
+                // Grow by repeating the last or first character, so a pattern
+                // like `^A\d+$` still matches.
+                let grow = |c: char| chars.iter().copied().chain(std::iter::repeat(c)).take(len).collect::<String>();
+                let mut out = Vec::new();
+                out.extend(chars.last().map(|&c| grow(c)));
+                out.extend(chars.first().map(|&c| grow(c)));
+                out.push(grow('a'));
+                strings(out)
+            }
+            Rule::MinLength => {
+                let Some(len) = self.min_length.and_then(|m| m.checked_sub(1)) else { return Vec::new() };
+                let len = len as usize;
+                strings(vec![chars[..len.min(chars.len())].iter().collect(), chars[chars.len().saturating_sub(len)..].iter().collect()])
+            }
+            Rule::Maximum => {
+                let Some(m) = self.maximum else { return Vec::new() };
+                match self.kind() {
+                    Some(Kind::Integer) => self.int_bounds().1.checked_add(1).map(Value::from).into_iter().collect(),
+                    _ => vec![Value::from(if self.exclusive_maximum { m } else { m + m.abs().max(1.0) * 1e-6 })],
+                }
+            }
+            Rule::Minimum => {
+                let Some(m) = self.minimum else { return Vec::new() };
+                match self.kind() {
+                    Some(Kind::Integer) => self.int_bounds().0.checked_sub(1).map(Value::from).into_iter().collect(),
+                    _ => vec![Value::from(if self.exclusive_minimum { m } else { m - m.abs().max(1.0) * 1e-6 })],
+                }
+            }
+            Rule::Enum => match self.enum_values.first() {
+                Some(Value::String(first)) => {
+                    let mut swapped: Vec<char> = first.chars().collect();
+                    if let Some(last) = swapped.last_mut() {
+                        *last = if *last == 'z' { 'y' } else { 'z' };
+                    }
+                    strings(vec![swapped.into_iter().collect(), format!("{first}_fuzzkit"), first.to_lowercase(), String::new()])
+                }
+                Some(Value::Number(_)) => {
+                    let top = self.enum_values.iter().filter_map(Value::as_i64).max().unwrap_or(0);
+                    top.checked_add(1).map(Value::from).into_iter().collect()
+                }
+                _ => Vec::new(),
+            },
+            Rule::Pattern => {
+                let replace = |i: usize| chars.iter().enumerate().map(|(j, &c)| if i == j { '!' } else { c }).collect();
+                let mut out: Vec<String> = Vec::new();
+                if !chars.is_empty() {
+                    out.push(replace(chars.len() - 1));
+                    out.push(replace(0));
+                }
+                out.extend(["", "!", "~ ~", "fuzzkit", "0", "A"].iter().map(|c| self.string_of(c)));
+                strings(out)
+            }
+            Rule::Format => self.format.as_deref().and_then(format_breaker).map(|s| Value::String(s.into())).into_iter().collect(),
+            Rule::MaxItems => self.max_items.map(|n| self.array_like(current, n + 1)).into_iter().collect(),
+            Rule::MinItems => self.min_items.and_then(|n| n.checked_sub(1)).map(|n| self.array_like(current, n)).into_iter().collect(),
+            Rule::Required(_) | Rule::AdditionalProperties => Vec::new(),
+        }
+    }
+
+    /// An array of `len` items, reusing the current (valid) items.
+    fn array_like(&self, current: &Value, len: u64) -> Value {
+        let items: Vec<Value> = current.as_array().cloned().unwrap_or_default();
+        let filler = items.first().cloned().unwrap_or_else(|| self.items.as_ref().map_or(Value::String("fuzzkit".into()), |s| s.example()));
+        Value::Array(items.into_iter().chain(std::iter::repeat(filler)).take(len as usize).collect())
+    }
+
+    /// The rules this node declares, in a stable order.
+    pub fn rules(&self) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        if self.kind.is_some() {
+            rules.push(Rule::Type);
+        }
+        let kind = self.kind();
+        if kind == Some(Kind::String) {
+            if self.max_length.is_some() {
+                rules.push(Rule::MaxLength);
+            }
+            if self.min_length.is_some_and(|m| m > 0) {
+                rules.push(Rule::MinLength);
+            }
+            if self.pattern.is_some() {
+                rules.push(Rule::Pattern);
+            }
+            if self.format.as_deref().and_then(format_breaker).is_some() {
+                rules.push(Rule::Format);
+            }
+        }
+        if matches!(kind, Some(Kind::Integer | Kind::Number)) {
+            if self.maximum.is_some() {
+                rules.push(Rule::Maximum);
+            }
+            if self.minimum.is_some() {
+                rules.push(Rule::Minimum);
+            }
+        }
+        if !self.enum_values.is_empty() {
+            rules.push(Rule::Enum);
+        }
+        if kind == Some(Kind::Array) {
+            if self.max_items.is_some() {
+                rules.push(Rule::MaxItems);
+            }
+            if self.min_items.is_some_and(|m| m > 0) {
+                rules.push(Rule::MinItems);
+            }
+        }
+        if kind == Some(Kind::Object) {
+            rules.extend(self.required.iter().filter(|k| self.properties.contains_key(*k)).map(|k| Rule::Required(k.clone())));
+            if self.additional_properties == Some(false) {
+                rules.push(Rule::AdditionalProperties);
+            }
+        }
+        rules
+    }
+
+    /// Every node of `value` that this schema describes, with its path.
+    pub fn walk<'s>(&'s self, value: &Value, at: Vec<Step>, out: &mut Vec<(Vec<Step>, &'s Schema)>) {
+        out.push((at.clone(), self));
+        match value {
+            Value::Object(members) => {
+                for (k, s) in &self.properties {
+                    if let Some(v) = members.get(k) {
+                        let mut next = at.clone();
+                        next.push(Step::Key(k.clone()));
+                        s.walk(v, next, out);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(s) = &self.items {
+                    for (i, v) in items.iter().enumerate() {
+                        let mut next = at.clone();
+                        next.push(Step::Index(i));
+                        s.walk(v, next, out);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Every rule `value` breaks, as (JSON pointer, rule ID). As in JSON
+    /// Schema, a value of the wrong type skips the keywords of its declared
+    /// type, but `enum` holds for values of any type.
+    pub fn errors(&self, value: &Value, at: Vec<Step>, out: &mut Vec<(String, &'static str)>) {
+        let here = || mutate::pointer(&at);
+        let type_ok = match self.kind() {
+            None => true,
+            Some(Kind::String) => value.is_string(),
+            Some(Kind::Integer) => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+            Some(Kind::Number) => value.is_number(),
+            Some(Kind::Boolean) => value.is_boolean(),
+            Some(Kind::Array) => value.is_array(),
+            Some(Kind::Object) => value.is_object(),
+        };
+        if !type_ok {
+            out.push((here(), "type"));
+        }
+        if !self.enum_values.is_empty() && !self.enum_values.contains(value) {
+            out.push((here(), "enum"));
+        }
+        if !type_ok {
+            return;
+        }
+        match value {
+            Value::String(s) => {
+                let len = s.chars().count() as u64;
+                if self.max_length.is_some_and(|m| len > m) {
+                    out.push((here(), "maxLength"));
+                }
+                if self.min_length.is_some_and(|m| len < m) {
+                    out.push((here(), "minLength"));
+                }
+                if !self.matches(s) {
+                    out.push((here(), "pattern"));
+                }
+                if self.format.as_deref().is_some_and(|f| !format_ok(f, s)) {
+                    out.push((here(), "format"));
+                }
+            }
+            Value::Number(n) => {
+                let f = n.as_f64().unwrap_or_default();
+                if let Some(m) = self.maximum {
+                    if f > m || (self.exclusive_maximum && f == m) {
+                        out.push((here(), "maximum"));
+                    }
+                }
+                if let Some(m) = self.minimum {
+                    if f < m || (self.exclusive_minimum && f == m) {
+                        out.push((here(), "minimum"));
+                    }
+                }
+            }
+            Value::Array(items) => {
+                let len = items.len() as u64;
+                if self.max_items.is_some_and(|m| len > m) {
+                    out.push((here(), "maxItems"));
+                }
+                if self.min_items.is_some_and(|m| len < m) {
+                    out.push((here(), "minItems"));
+                }
+                if let Some(s) = &self.items {
+                    for (i, v) in items.iter().enumerate() {
+                        let mut next = at.clone();
+                        next.push(Step::Index(i));
+                        s.errors(v, next, out);
+                    }
+                }
+            }
+            Value::Object(members) => {
+                let child = |k: &str| {
+                    let mut next = at.clone();
+                    next.push(Step::Key(k.to_string()));
+                    next
+                };
+                for k in self.required.iter().filter(|k| !members.contains_key(*k)) {
+                    out.push((mutate::pointer(&child(k)), "required"));
+                }
+                for (k, v) in members {
+                    match self.properties.get(k) {
+                        Some(s) => s.errors(v, child(k), out),
+                        None if self.additional_properties == Some(false) => {
+                            out.push((mutate::pointer(&child(k)), "additionalProperties"))
+                        }
+                        None => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Break `rule` of the node at `at` inside the valid `doc`, and nothing
+    /// else: every candidate is re-validated and kept only if `rule` is the
+    /// one error it causes.
+    pub fn break_at(&self, root: &Schema, doc: &Value, at: &[Step], rule: &Rule) -> Option<Violation> {
+        let isolated = |body: Value, pointer: String| {
+            let mut errors = Vec::new();
+            root.errors(&body, Vec::new(), &mut errors);
+            (errors == [(pointer.clone(), rule.id())]).then_some(Violation { rule: rule.id(), pointer, body })
+        };
+        let child = |key: &str| {
+            let mut path = at.to_vec();
+            path.push(Step::Key(key.to_string()));
+            mutate::pointer(&path)
+        };
+        match rule {
+            Rule::Required(key) => {
+                let mut body = doc.clone();
+                body.pointer_mut(&mutate::pointer(at))?.as_object_mut()?.remove(key)?;
+                isolated(body, child(key))
+            }
+            Rule::AdditionalProperties => {
+                let mut body = doc.clone();
+                let obj = body.pointer_mut(&mutate::pointer(at))?.as_object_mut()?;
+                obj.insert(EXTRA_KEY.into(), Value::String("fuzzkit".into()));
+                isolated(body, child(EXTRA_KEY))
+            }
+            _ => {
+                let current = doc.pointer(&mutate::pointer(at))?;
+                self.breakers(rule, current).into_iter().find_map(|v| {
+                    let mut body = doc.clone();
+                    *body.pointer_mut(&mutate::pointer(at))? = v;
+                    isolated(body, mutate::pointer(at))
+                })
+            }
+        }
+    }
+
+    /// Every (node, rule) of `doc` this schema constrains.
+    fn targets(&self, doc: &Value) -> Vec<(Vec<Step>, &Schema, Rule)> {
+        let mut nodes = Vec::new();
+        self.walk(doc, Vec::new(), &mut nodes);
+        nodes.into_iter().flat_map(|(at, s)| s.rules().into_iter().map(move |r| (at.clone(), s, r))).collect()
+    }
+
+    /// One case per constraint on `doc` (which must itself be valid), each
+    /// breaking that constraint and leaving every other field valid. Rules
+    /// that can't be broken alone (`maxLength` under an anchored fixed-length
+    /// pattern, say) are left out.
+    pub fn violations(&self, doc: &Value) -> Vec<Violation> {
+        self.targets(doc).iter().filter_map(|(at, s, rule)| s.break_at(self, doc, at, rule)).collect()
+    }
+
+    /// A random valid instance with one randomly chosen rule broken.
+    pub fn violate(&self, rng: &mut Rng) -> Option<Violation> {
+        let doc = self.generate(rng);
+        let targets = self.targets(&doc);
+        // Start at a random rule and move on if it can't be broken alone.
+        let start = rng.below(targets.len().max(1));
+        (0..targets.len()).find_map(|i| {
+            let (at, s, rule) = &targets[(start + i) % targets.len()];
+            s.break_at(self, &doc, at, rule)
+        })
+    }
+
+    /// Whether `value` satisfies every rule.
+    pub fn accepts(&self, value: &Value) -> bool {
+        let mut errors = Vec::new();
+        self.errors(value, Vec::new(), &mut errors);
+        errors.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// One of each constraint the generator can break, with formats
+    /// `jsonschema` checks the same way we do.
+    const SCHEMA: &str = r#"
+        type = "object"
+        required = ["pin", "period", "amount", "lines"]
+        additionalProperties = false
+        properties.pin = { type = "string", pattern = "^[AP]\\d{9}[A-Z]$" }
+        properties.period = { type = "string", format = "date" }
+        properties.contact = { type = "string", format = "email" }
+        properties.name = { type = "string", minLength = 2, maxLength = 8 }
+        properties.amount = { type = "integer", minimum = 0, maximum = 100000000 }
+        properties.kind = { type = "string", enum = ["INC", "EXP", "VAT"] }
+        properties.nil_return = { type = "boolean" }
+        properties.lines = { type = "array", minItems = 1, maxItems = 3, items = { type = "object", required = ["code", "value"], properties = { code = { type = "string", enum = ["INC", "EXP"] }, value = { type = "integer", minimum = 0 } } } }
+    "#;
+
+    /// The JSON Schema keyword an independent validator reports for each
+    /// error `body` has, with the pointer it reports it at.
+    fn failures(validator: &jsonschema::Validator, body: &Value) -> Vec<(String, String)> {
+        validator
+            .iter_errors(body)
+            .map(|e| {
+                let keyword = e.schema_path.as_str().rsplit('/').next().unwrap_or_default().to_string();
+                (e.instance_path.as_str().to_string(), keyword)
+            })
+            .collect()
+    }
+
+    fn breaks_exactly(validator: &jsonschema::Validator, v: &Violation) {
+        let failed = failures(validator, &v.body);
+        assert_eq!(failed.len(), 1, "{} at {}: {failed:?} in {}", v.rule, v.pointer, v.body);
+        let (at, keyword) = &failed[0];
+        assert_eq!(keyword, v.rule, "{} at {}: {failed:?}", v.rule, v.pointer);
+        // `required` and `additionalProperties` are reported at the object.
+        assert!(v.pointer == *at || v.pointer.starts_with(&format!("{at}/")), "{} at {}: {failed:?}", v.rule, v.pointer);
+    }
+
+    #[test]
+    fn each_violation_breaks_exactly_one_constraint_of_the_schema() {
+        let schema: Schema = toml::from_str(SCHEMA).unwrap();
+        let validator = jsonschema::options().should_validate_formats(true).build(&serde_json::to_value(&schema).unwrap()).unwrap();
+        let mut doc = schema.example();
+        doc["contact"] = Value::from("fuzzkit@example.com");
+        doc["kind"] = Value::from("INC");
+        assert_eq!(failures(&validator, &doc), [], "{doc}");
+
+        let violations = schema.violations(&doc);
+        for v in &violations {
+            breaks_exactly(&validator, v);
+        }
+        let rules: BTreeSet<&str> = violations.iter().map(|v| v.rule).collect();
+        let all = ["type", "maxLength", "minLength", "maximum", "minimum", "enum", "pattern", "format", "maxItems", "minItems", "required", "additionalProperties"];
+        assert_eq!(rules, all.into_iter().collect());
+
+        for n in 0..200 {
+            let v = schema.violate(&mut Rng::for_case(11, n)).unwrap();
+            breaks_exactly(&validator, &v);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! `[signing]`: HMAC request signatures. The transport stamps the timestamp
+//! and nonce a request does not carry yet, renders the canonical string from
+//! `template` over the request as sent, and adds the signature header.
+//!
+//! With `mutate = "after"`, a mutated case is signed as the request it was
+//! mutated from, so the mutation lands after signing and any 2xx shows a field
+//! the signature check does not cover.
+
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::auth::{base64, base64_decode, Secret};
+use crate::timestamp::{self, Field};
+use crate::transport::Request;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    #[default]
+    HmacSha256,
+    HmacSha512,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Raw bytes (keys) or lowercase hex (signatures)
+    #[default]
+    Hex,
+    Base64,
+    /// The key's UTF-8 bytes as they are
+    Utf8,
+}
+
+/// When mutations are applied relative to signing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Sign the mutated request: tests validation behind a valid signature
+    #[default]
+    Before,
+    /// Sign the unmutated request, then send the mutated one
+    After,
+}
+
+fn default_value() -> String {
+    "{signature}".into()
+}
+
+fn default_key_encoding() -> Encoding {
+    Encoding::Utf8
+}
+
+/// `[signing]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Signing {
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    pub key: Secret,
+    #[serde(default = "default_key_encoding")]
+    pub key_encoding: Encoding,
+    /// Canonical string, e.g. `"{method}\n{path}\n{timestamp}\n{body_sha256}"`
+    pub template: String,
+    /// Header that carries the signature
+    pub header: String,
+    /// The header's value; `{signature}` plus any template placeholder
+    #[serde(default = "default_value")]
+    pub value: String,
+    /// How the signature is written: hex or base64
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// Stamped with the current time unless the request already carries it
+    #[serde(default)]
+    pub timestamp: Option<Field>,
+    /// Stamped with a fresh nonce unless the request already carries it
+    #[serde(default)]
+    pub nonce: Option<Field>,
+    #[serde(default)]
+    pub mutate: Stage,
+}
+
+/// Placeholders `template` and `value` may use, besides `{header:NAME}`.
+const PLACEHOLDERS: &[&str] = &["method", "host", "path", "query", "timestamp", "nonce", "body_sha256", "body_sha512"];
+
+/// The `{...}` placeholders of a template, in order.
+fn placeholders(template: &str) -> Result<Vec<&str>> {
+    let mut out = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else { bail!("unclosed '{{' in signing template {template:?}") };
+        out.push(&rest[open + 1..open + close]);
+        rest = &rest[open + close + 1..];
+    }
+    Ok(out)
+}
+
+impl Signing {
+    /// Read the key and check both templates, so mistakes fail before any traffic.
+    pub fn check(&self) -> Result<()> {
+        self.key()?;
+        for (what, template, extra) in [("template", &self.template, None), ("value", &self.value, Some("signature"))] {
+            let names = placeholders(template)?;
+            for name in &names {
+                let known = PLACEHOLDERS.contains(name) || name.starts_with("header:") || Some(*name) == extra;
+                if !known {
+                    bail!("[signing] {what} uses unknown placeholder {{{name}}} (known: {}, header:NAME)", PLACEHOLDERS.join(", "));
+                }
+                if *name == "timestamp" && self.timestamp.is_none() || *name == "nonce" && self.nonce.is_none() {
+                    bail!("[signing] {what} uses {{{name}}} but [signing] has no {name} field");
+                }
+            }
+            if extra.is_some() && !names.contains(&"signature") {
+                bail!("[signing] value must contain {{signature}}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Upper bound on the bytes signing adds to a request: the signature
+    /// header and the timestamp and nonce it stamps.
+    pub fn allowance(&self) -> u64 {
+        // Longest stamped value (an RFC 3339 time or a nonce) with its JSON punctuation.
+        const STAMP: u64 = 64;
+        let placeholders = placeholders(&self.value).unwrap_or_default();
+        let value: u64 = placeholders
+            .iter()
+            .map(|name| match *name {
+                "signature" => 128,
+                "timestamp" | "nonce" => STAMP,
+                _ => 256,
+            })
+            .sum();
+        let stamps = [&self.timestamp, &self.nonce].into_iter().flatten().map(|f| f.name.len() as u64 + STAMP).sum::<u64>();
+        (self.header.len() + self.value.len()) as u64 + value + stamps
+    }
+
+    fn key(&self) -> Result<Vec<u8>> {
+        let raw = self.key.read().context("[signing] key")?;
+        match self.key_encoding {
+            Encoding::Utf8 => Ok(raw.into_bytes()),
+            Encoding::Hex => hex::decode(raw.trim()).context("[signing] key is not hex"),
+            Encoding::Base64 => base64_decode(raw.trim()).context("[signing] key is not base64"),
+        }
+    }
+}
+
+/// `[signing]` with its key read, ready to sign requests for one `base_url`.
+pub struct Signer {
+    cfg: Signing,
+    key: Vec<u8>,
+    host: String,
+    /// `base_url`'s path, which prefixes every request path on the wire
+    base_path: String,
+}
+
+impl Signer {
+    pub fn new(cfg: &Signing, base_url: &str) -> Result<Self> {
+        cfg.check()?;
+        let url = Url::parse(base_url).with_context(|| format!("invalid base_url: {base_url}"))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        Ok(Self { cfg: cfg.clone(), key: cfg.key()?, host, base_path: url.path().trim_end_matches('/').to_string() })
+    }
+
+    /// `req` stamped and signed. A request with an `origin` is signed as that
+    /// origin, carrying the same timestamp and nonce. The body as sent is
+    /// never re-serialized: a body field is only stamped where that leaves
+    /// the existing bytes alone (see `timestamp::stamp_field`), so mutated
+    /// and non-JSON bodies go out exactly as generated.
+    pub fn sign(&self, req: &Request) -> Result<Request> {
+        let mut out = req.clone();
+        let mut origin = out.origin.as_deref().cloned();
+        let fresh = [
+            self.cfg.timestamp.as_ref().map(|f| (f, f.format.render(OffsetDateTime::now_utc()))),
+            self.cfg.nonce.as_ref().map(|f| (f, Value::String(timestamp::fresh_nonce()))),
+        ];
+        for (field, fresh) in fresh.into_iter().flatten() {
+            let value = match timestamp::get_field(&out, field) {
+                Some(value) => value,
+                None => {
+                    if !timestamp::stamp_field(&mut out, field, &fresh) {
+                        tracing::debug!(target: "signing", field = %field.name, "body left as generated; field not stamped");
+                    }
+                    fresh
+                }
+            };
+            // The origin is only signed, never sent, so rewriting its body is harmless.
+            if let Some(origin) = &mut origin {
+                timestamp::set_field(origin, field, &value)?;
+            }
+        }
+        let signed = origin.as_ref().unwrap_or(&out);
+        let canonical = self.render(&self.cfg.template, signed, None)?;
+        let mac = self.mac(canonical.as_bytes());
+        let signature = match self.cfg.encoding {
+            Encoding::Base64 => base64(&mac),
+            Encoding::Hex | Encoding::Utf8 => hex::encode(&mac),
+        };
+        let value = self.render(&self.cfg.value, signed, Some(&signature))?;
+        tracing::trace!(target: "signing", canonical = %canonical.escape_debug(), "signed");
+        out.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(&self.cfg.header));
+        out.headers.push((self.cfg.header.clone(), value));
+        Ok(out)
+    }
+
+    fn mac(&self, message: &[u8]) -> Vec<u8> {
+        match self.cfg.algorithm {
+            Algorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    fn render(&self, template: &str, req: &Request, signature: Option<&str>) -> Result<String> {
+        let field = |f: &Option<Field>| f.as_ref().and_then(|f| timestamp::get_field(req, f)).map(|v| timestamp::text(&v)).unwrap_or_default();
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        for name in placeholders(template)? {
+            let open = rest.find('{').expect("placeholders() found it");
+            out.push_str(&rest[..open]);
+            rest = &rest[open + name.len() + 2..];
+            out.push_str(&match name {
+                "method" => req.method.to_uppercase(),
+                "host" => self.host.clone(),
+                "path" => format!("{}{}", self.base_path, req.path),
+                "query" => url::form_urlencoded::Serializer::new(String::new()).extend_pairs(&req.query).finish(),
+                "timestamp" => field(&self.cfg.timestamp),
+                "nonce" => field(&self.cfg.nonce),
+                "body_sha256" => body_hash::<Sha256>(req),
+                "body_sha512" => body_hash::<Sha512>(req),
+                "signature" => signature.unwrap_or_default().to_string(),
+                other => match other.strip_prefix("header:") {
+                    Some(h) => req.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(h)).map(|(_, v)| v.clone()).unwrap_or_default(),
+                    None => bail!("unknown signing placeholder {{{other}}}"),
+                },
+            });
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// Hex digest of the body as sent (empty when there is none).
+fn body_hash<D: Digest>(req: &Request) -> String {
+    let mut hasher = D::new();
+    if let Some(body) = &req.body {
+        body.for_each_chunk(|chunk| hasher.update(chunk));
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use crate::transport::Body;
+
+    const BASE: &str = "http://127.0.0.1:1";
+
+    fn body(req: &Request) -> &[u8] {
+        match &req.body {
+            Some(Body::Bytes(raw)) => raw,
+            other => panic!("unexpected body {other:?}"),
+        }
+    }
+
+    fn header<'a>(req: &'a Request, name: &str) -> &'a str {
+        req.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str()).unwrap()
+    }
+
+    /// A case whose body is `mutated`, mutated from the test baseline.
+    fn mutated(mutated: &[u8]) -> Request {
+        let base = testutil::request(&testutil::profile(BASE));
+        Request { body: Some(Body::Bytes(mutated.to_vec())), origin: Some(Box::new(base.clone())), ..base }
+    }
+
+    #[test]
+    fn signing_before_mutation_leaves_the_body_bytes_alone() {
+        let mut cfg = testutil::signing("");
+        cfg.timestamp = Some(toml::from_str(r#"location = "body"
+            name = "ts"
+            format = "epoch_seconds""#).unwrap());
+        let signer = Signer::new(&cfg, BASE).unwrap();
+        let raw = br#"{"pin":"A","pin":"B","amount":-0,"rate":1.50}"#;
+        let req = Request { origin: None, ..mutated(raw) };
+
+        let signed = signer.sign(&req).unwrap();
+
+        let ts = timestamp::get_field(&signed, cfg.timestamp.as_ref().unwrap()).unwrap();
+        assert_eq!(body(&signed), format!(r#"{{"ts":{ts},"pin":"A","pin":"B","amount":-0,"rate":1.50}}"#).as_bytes());
+
+        for raw in [&b"not json {"[..], b"[1,2]", b"{\"pin\":\"A\"", b""] {
+            let req = Request { origin: None, ..mutated(raw) };
+            let signed = signer.sign(&req).unwrap();
+            assert_eq!(body(&signed), raw);
+            assert!(!header(&signed, "X-Signature").is_empty());
+        }
+    }
+
+    #[test]
+    fn signing_after_mutation_signs_the_origin_and_sends_the_case_as_is() {
+        let cfg = testutil::signing(r#"mutate = "after""#);
+        let signer = Signer::new(&cfg, BASE).unwrap();
+        for raw in [&b"not json {"[..], br#"{"pin":"A","pin":"B","amount":-0}"#] {
+            let req = mutated(raw);
+
+            let signed = signer.sign(&req).unwrap();
+
+            assert_eq!(body(&signed), raw);
+            // Signing the origin with the same stamps gives the same signature.
+            let mut origin = *req.origin.clone().unwrap();
+            for name in ["X-Timestamp", "X-Nonce"] {
+                origin.headers.push((name.into(), header(&signed, name).into()));
+            }
+            assert_eq!(header(&signed, "X-Signature"), header(&signer.sign(&origin).unwrap(), "X-Signature"));
+        }
+    }
+
+    #[test]
+    fn signing_after_mutation_with_a_body_timestamp_stamps_both_bodies() {
+        let mut cfg = testutil::signing(r#"mutate = "after""#);
+        cfg.timestamp = Some(toml::from_str(r#"location = "body"
+            name = "ts""#).unwrap());
+        let signer = Signer::new(&cfg, BASE).unwrap();
+        let req = mutated(br#"{ "amount" : -0 }"#);
+
+        let signed = signer.sign(&req).unwrap();
+
+        let field = cfg.timestamp.as_ref().unwrap();
+        let ts = timestamp::get_field(&signed, field).unwrap();
+        assert_eq!(body(&signed), format!(r#"{{"ts":{ts}, "amount" : -0 }}"#).as_bytes());
+        let mut origin = *req.origin.clone().unwrap();
+        timestamp::set_field(&mut origin, field, &ts).unwrap();
+        origin.headers.push(("X-Nonce".into(), header(&signed, "X-Nonce").into()));
+        assert_eq!(header(&signed, "X-Signature"), header(&signer.sign(&origin).unwrap(), "X-Signature"));
+    }
+}
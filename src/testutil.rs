@@ -0,0 +1,71 @@
+//! Shared fixtures for the unit tests: a minimal profile aimed at a local mock.
+
+use crate::plan;
+use crate::scheduler::Scheduler;
+use crate::transport::{Request, Transport};
+use crate::Profile;
+
+/// A profile with one POST endpoint and a JSON seed, allowed to target
+/// `base_url` on loopback, with limits loose enough not to slow tests down.
+pub fn profile(base_url: &str) -> Profile {
+    toml::from_str(&format!(
+        r#"
+name = "test"
+base_url = "{base_url}"
+
+[[endpoints]]
+path = "/v1/returns"
+method = "POST"
+[endpoints.seed_body]
+pin = "A000000000B"
+period = "2024-01"
+amount = 150000
+
+[limits]
+concurrency = 4
+rate_per_sec = 1000
+request_budget = 1000
+max_rate_per_sec = 1000
+allowed_methods = ["GET", "POST"]
+payload_ladder = ["1KiB"]
+
+[timeouts]
+connect_ms = 1000
+read_ms = 2000
+
+[safety]
+require_sandbox_flag = false
+allowlist_hosts = ["127.0.0.1"]
+allow_private_targets = true
+force_headers = {{ X-Env = "sandbox" }}
+"#
+    ))
+    .expect("test profile parses")
+}
+
+/// The baseline request of the profile's first endpoint.
+pub fn request(p: &Profile) -> Request {
+    plan::baseline(&p.endpoints[0]).request
+}
+
+/// A transport for `p`, charging token fetches to a scheduler of its own.
+pub fn transport(p: &Profile) -> Transport {
+    Transport::new(p, &Scheduler::new(&p.limits)).expect("test transport builds")
+}
+
+/// A `[signing]` section over `extra` (TOML keys), keyed from an env var the
+/// helper sets.
+pub fn signing(extra: &str) -> crate::signing::Signing {
+    std::env::set_var("FUZZKIT_TEST_SIGNING_KEY", "test-key");
+    toml::from_str(&format!(
+        r#"
+key = {{ env = "FUZZKIT_TEST_SIGNING_KEY" }}
+template = "{{method}}\n{{path}}\n{{timestamp}}\n{{nonce}}\n{{body_sha256}}"
+header = "X-Signature"
+timestamp = {{ location = "header", name = "X-Timestamp", format = "epoch_seconds" }}
+nonce = {{ location = "header", name = "X-Nonce" }}
+{extra}
+"#
+    ))
+    .expect("test signing section parses")
+}
@@ -0,0 +1,322 @@
+//! Timestamp replay and clock-skew mode.
+//!
+//! Captures one valid request, re-sends it byte-for-byte after a delay (same
+//! timestamp, nonce and any signature it carries), then sends fresh variants
+//! with the timestamp skewed into the past and future to map the window the
+//! API accepts.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::oracle::{Signal, Verdict};
+use crate::plan::{self, Case};
+use crate::runner::{self, Outcome};
+use crate::scheduler::Scheduler;
+use crate::transport::{Body, Request, Transport};
+use crate::Profile;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Location {
+    Header,
+    Query,
+    /// `name` is a JSON pointer (`/meta/ts`) or a top-level key
+    Body,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    #[default]
+    Rfc3339,
+    EpochSeconds,
+    EpochMillis,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Field {
+    pub location: Location,
+    pub name: String,
+    #[serde(default)]
+    pub format: TimeFormat,
+}
+
+/// `[clock]`: where the request carries its timestamp and nonce.
+#[derive(Debug, Deserialize)]
+pub struct Clock {
+    /// Path template of the endpoint to capture; the first endpoint if omitted
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    pub timestamp: Field,
+    #[serde(default)]
+    pub nonce: Option<Field>,
+    #[serde(default = "default_replay_after")]
+    pub replay_after_secs: u64,
+    #[serde(default = "default_skews")]
+    pub skews_secs: Vec<i64>,
+}
+
+fn default_replay_after() -> u64 {
+    30
+}
+
+fn default_skews() -> Vec<i64> {
+    vec![-86_400, -3_600, -300, -60, 60, 300, 3_600, 86_400]
+}
+
+impl TimeFormat {
+    pub fn render(self, at: OffsetDateTime) -> Value {
+        match self {
+            TimeFormat::Rfc3339 => Value::String(at.format(&Rfc3339).expect("RFC 3339 formats any UTC time")),
+            TimeFormat::EpochSeconds => Value::from(at.unix_timestamp()),
+            TimeFormat::EpochMillis => Value::from((at.unix_timestamp_nanos() / 1_000_000) as i64),
+        }
+    }
+}
+
+/// A nonce that is unique within this process and across runs.
+pub fn fresh_nonce() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    format!("{nanos:x}{:04x}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A field value as it appears in a header or query string.
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Field {
+    /// `name` as a JSON pointer, for body fields.
+    fn pointer(&self) -> String {
+        if self.name.starts_with('/') { self.name.clone() } else { format!("/{}", self.name) }
+    }
+}
+
+/// The value of `field` in `req`, if it carries one.
+pub fn get_field(req: &Request, field: &Field) -> Option<Value> {
+    match field.location {
+        Location::Header => req.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(&field.name)).map(|(_, v)| Value::String(v.clone())),
+        Location::Query => req.query.iter().find(|(k, _)| k == &field.name).map(|(_, v)| Value::String(v.clone())),
+        Location::Body => {
+            let Some(Body::Bytes(raw)) = &req.body else { return None };
+            let doc: Value = serde_json::from_slice(raw).ok()?;
+            doc.pointer(&field.pointer()).cloned()
+        }
+    }
+}
+
+/// Set `field` on `req` to `value`, replacing any existing occurrence.
+pub fn set_field(req: &mut Request, field: &Field, value: &Value) -> Result<()> {
+    let text = text(value);
+    match field.location {
+        Location::Header => {
+            req.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(&field.name));
+            req.headers.push((field.name.clone(), text));
+        }
+        Location::Query => {
+            req.query.retain(|(k, _)| k != &field.name);
+            req.query.push((field.name.clone(), text));
+        }
+        Location::Body => {
+            let Some(Body::Bytes(raw)) = &req.body else {
+                bail!("clock field '{}' is in the body but the request has no JSON body", field.name);
+            };
+            let mut doc: Value = serde_json::from_slice(raw).context("request body is not JSON")?;
+            let pointer = field.pointer();
+            let (parent, key) = pointer.rsplit_once('/').expect("pointer starts with '/'");
+            let Some(Value::Object(obj)) = doc.pointer_mut(parent) else {
+                bail!("clock field '{}' does not point into a JSON object", field.name);
+            };
+            obj.insert(key.replace("~1", "/").replace("~0", "~"), value.clone());
+            req.body = Some(Body::Bytes(doc.to_string().into_bytes()));
+        }
+    }
+    Ok(())
+}
+
+/// Set `field` on `req` without rewriting any byte of its body that is
+/// already there: a body field is added as the first member of a JSON
+/// object body, or, when nested, only if the body re-serializes unchanged.
+/// Returns whether the field was set; a body that is not JSON, or would lose
+/// its spelling, duplicate keys or `-0`, is left alone.
+pub fn stamp_field(req: &mut Request, field: &Field, value: &Value) -> bool {
+    let Location::Body = field.location else { return set_field(req, field, value).is_ok() };
+    let Some(Body::Bytes(raw)) = &req.body else { return false };
+    let Ok(doc) = serde_json::from_slice::<Value>(raw) else { return false };
+    let pointer = field.pointer();
+    let stamped = match pointer.rsplit_once('/') {
+        Some(("", key)) if doc.is_object() => {
+            let key = key.replace("~1", "/").replace("~0", "~");
+            let open = raw.iter().position(|b| !b.is_ascii_whitespace()).expect("a JSON object has a '{'");
+            let empty = raw[open + 1..].iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'}');
+            let mut out = raw[..=open].to_vec();
+            out.extend(format!("{}:{value}{}", Value::String(key), if empty { "" } else { "," }).into_bytes());
+            out.extend_from_slice(&raw[open + 1..]);
+            out
+        }
+        _ if serde_json::to_vec(&doc).ok().as_ref() == Some(raw) => return set_field(req, field, value).is_ok(),
+        _ => return false,
+    };
+    req.body = Some(Body::Bytes(stamped));
+    true
+}
+
+fn stamped(base: &Case, clock: &Clock, skew: i64, operator: &str, target: String) -> Result<Case> {
+    let mut case = Case { operator: operator.into(), target, request: base.request.clone() };
+    let at = OffsetDateTime::now_utc() + time::Duration::seconds(skew);
+    set_field(&mut case.request, &clock.timestamp, &clock.timestamp.format.render(at))?;
+    if let Some(nonce) = &clock.nonce {
+        set_field(&mut case.request, nonce, &Value::String(fresh_nonce()))?;
+    }
+    Ok(case)
+}
+
+fn accepted(o: &Outcome) -> bool {
+    matches!(&o.result, Ok(r) if (200..300).contains(&r.status))
+}
+
+pub async fn run(p: &Profile, transport: Arc<Transport>, sched: Arc<Scheduler>) -> Result<Vec<Outcome>> {
+    let Some(clock) = &p.clock else {
+        bail!("timestamp replay mode needs a [clock] section in the profile");
+    };
+    let endpoint = match &clock.endpoint {
+        Some(path) => p.endpoints.iter().find(|e| &e.path == path),
+        None => p.endpoints.first(),
+    };
+    let Some(endpoint) = endpoint else {
+        bail!("[clock] endpoint {:?} is not one of the profile's endpoints", clock.endpoint.as_deref().unwrap_or_default());
+    };
+    let base = plan::baseline(endpoint);
+    let retries = p.limits.retries;
+    let mut outcomes = Vec::new();
+
+    // 1) Capture: a fresh, valid request
+    let captured = stamped(&base, clock, 0, "replay.capture", String::new())?;
+    let first = runner::run(Arc::clone(&transport), Arc::clone(&sched), retries, vec![(0, captured.clone())]).await;
+    let Some(first) = first.into_iter().next() else { bail!("request budget exhausted before capture") };
+    if !accepted(&first) {
+        tracing::warn!(target: "replay", "captured request was not accepted; replay results will not be meaningful");
+    }
+    // Under [signing] the transport stamped and signed the capture; replay
+    // exactly what went out, not a re-signed copy with a fresh nonce.
+    let sent = first.result.as_ref().map_or(captured.request, |r| r.sent.clone());
+    outcomes.push(first);
+
+    // 2) Replay the identical request after the delay
+    tracing::info!(target: "replay", secs = clock.replay_after_secs, "waiting before replay");
+    tokio::time::sleep(Duration::from_secs(clock.replay_after_secs)).await;
+    let replay = Case {
+        operator: "replay.resend".into(),
+        target: format!("+{}s", clock.replay_after_secs),
+        request: sent,
+    };
+    outcomes.extend(runner::run(Arc::clone(&transport), Arc::clone(&sched), retries, vec![(0, replay)]).await);
+
+    // 3) Skewed timestamps, each with a fresh nonce so only the clock is under test.
+    //    Stamped one at a time so rate-limit waits don't drift the skew.
+    for &skew in &clock.skews_secs {
+        let case = stamped(&base, clock, skew, "replay.skew", format!("{skew:+}s"))?;
+        outcomes.extend(runner::run(Arc::clone(&transport), Arc::clone(&sched), retries, vec![(0, case)]).await);
+    }
+
+    for (i, o) in outcomes.iter_mut().enumerate() {
+        o.index = i;
+    }
+    judge(clock, &mut outcomes);
+    Ok(outcomes)
+}
+
+/// Flag an accepted replay as `replay.accepted` and log the skew window.
+fn judge(clock: &Clock, outcomes: &mut [Outcome]) {
+    if let Some(replay) = outcomes.iter_mut().find(|o| o.case.operator == "replay.resend") {
+        if accepted(replay) {
+            let detail = format!("replayed request accepted after {}s; timestamp and nonce are not enforced", clock.replay_after_secs);
+            tracing::warn!(target: "replay", "FINDING: {detail}");
+            replay.signals.push(Signal { oracle: "replay.accepted".into(), verdict: Verdict::Finding, detail });
+        } else {
+            tracing::info!(target: "replay", "replayed request rejected");
+        }
+    }
+    match accepted_skews(outcomes) {
+        skews if skews.is_empty() => tracing::info!(target: "replay", "no skewed timestamp accepted"),
+        skews => tracing::info!(target: "replay", "accepted skews: {}", skews.join(", ")),
+    }
+}
+
+/// The skews (`-300s`, `+60s`) whose timestamps the API accepted, in the
+/// order they were sent.
+pub fn accepted_skews(outcomes: &[Outcome]) -> Vec<String> {
+    outcomes.iter().filter(|o| o.case.operator == "replay.skew" && accepted(o)).map(|o| o.case.target.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn replay_resends_the_signed_request_unchanged() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(201)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        // [clock] carries its own timestamp but no nonce; [signing] adds X-Nonce.
+        p.clock = Some(toml::from_str(r#"
+            timestamp = { location = "header", name = "X-Timestamp", format = "epoch_seconds" }
+            replay_after_secs = 0
+            skews_secs = []
+        "#).unwrap());
+        p.signing = Some(testutil::signing(""));
+        let transport = Arc::new(testutil::transport(&p));
+
+        let outcomes = run(&p, transport, Scheduler::new(&p.limits)).await.unwrap();
+
+        assert_eq!(outcomes.len(), 2);
+        let received = server.received_requests().await.unwrap();
+        for name in ["x-signature", "x-nonce", "x-timestamp"] {
+            assert_eq!(received[0].headers.get(name), received[1].headers.get(name), "{name}");
+        }
+        assert_eq!(received[0].body, received[1].body);
+    }
+
+    #[tokio::test]
+    async fn accepted_replay_is_a_finding_and_skews_are_judged_by_the_mode() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(201)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        p.clock = Some(toml::from_str(r#"
+            timestamp = { location = "header", name = "X-Timestamp" }
+            replay_after_secs = 0
+            skews_secs = [-60, 60]
+        "#).unwrap());
+        let transport = Arc::new(testutil::transport(&p));
+        let mut oracles = crate::oracle::Oracles::new(&p.oracles, &plan::baselines(&p)).unwrap();
+
+        let mut outcomes = run(&p, transport, Scheduler::new(&p.limits)).await.unwrap();
+        outcomes.iter_mut().for_each(|o| oracles.judge(o));
+
+        let fired: Vec<(&str, Vec<&str>)> = outcomes
+            .iter()
+            .map(|o| (o.case.operator.as_str(), o.signals.iter().map(|s| s.oracle.as_str()).collect()))
+            .collect();
+        assert_eq!(fired, [
+            ("replay.capture", vec![]),
+            ("replay.resend", vec!["replay.accepted"]),
+            ("replay.skew", vec![]),
+            ("replay.skew", vec![]),
+        ]);
+        assert_eq!(outcomes[1].verdict(), Verdict::Finding);
+        assert_eq!(accepted_skews(&outcomes), ["-60s", "+60s"]);
+    }
+}
@@ -0,0 +1,1001 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use http_body_util::Full;
+use reqwest::{Client, Method};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use url::{Position, Url};
+
+use crate::allowlist::{self, Pinning};
+use crate::auth::{Auth, Credential};
+use crate::bypass::Tamper;
+use crate::scheduler::Scheduler;
+use crate::signing::Signer;
+use crate::wire::{self, Dialer};
+use crate::{Profile, Refused};
+
+/// Which HTTP versions the client may speak.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/1.1, or HTTP/2 when negotiated via ALPN
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 with prior knowledge (also works for cleartext h2c mocks)
+    Http2,
+}
+
+/// One outgoing request, relative to the profile's `base_url`.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Body>,
+    /// How the `[auth]` credential is presented; only the auth-bypass
+    /// family tampers with it
+    pub auth: Tamper,
+    /// The unmutated request a case is signed as under `[signing] mutate =
+    /// "after"`; unset otherwise
+    pub origin: Option<Box<Request>>,
+    /// Already signed: a request as it was sent, replayed byte for byte
+    pub signed: bool,
+}
+
+impl Request {
+    /// Whether both put the same method, path, query, headers and body on the wire.
+    pub fn same_wire(&self, other: &Request) -> bool {
+        let body = |r: &Request| r.body.as_ref().map(|b| b.head(b.len() as usize));
+        self.method == other.method
+            && self.path == other.path
+            && self.query == other.query
+            && self.headers == other.headers
+            && body(self) == body(other)
+    }
+
+    /// Bytes of query, headers and body: what `max_payload_bytes` caps.
+    pub fn size(&self) -> u64 {
+        pairs_len(&self.query) + pairs_len(&self.headers) + self.body.as_ref().map_or(0, Body::len)
+    }
+}
+
+/// Bytes of the names and values of header or query pairs.
+pub fn pairs_len(kv: &[(String, String)]) -> u64 {
+    kv.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum()
+}
+
+#[derive(Debug, Clone)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// `prefix`, then `fill` repeated, then `suffix`: `len` bytes in total,
+    /// generated chunk by chunk while sending so large bodies never sit in memory
+    Filled { prefix: Vec<u8>, fill: u8, len: u64, suffix: Vec<u8> },
+}
+
+impl Body {
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(b) => b.len() as u64,
+            Body::Filled { len, .. } => *len,
+        }
+    }
+
+    fn fill_len(&self) -> u64 {
+        match self {
+            Body::Bytes(_) => 0,
+            Body::Filled { prefix, len, suffix, .. } => len.saturating_sub((prefix.len() + suffix.len()) as u64),
+        }
+    }
+
+    /// Visit the body chunk by chunk, without materialising a `Filled` body.
+    pub fn for_each_chunk(&self, mut f: impl FnMut(&[u8])) {
+        match self {
+            Body::Bytes(b) => f(b),
+            Body::Filled { prefix, fill, suffix, .. } => {
+                f(prefix);
+                let block = [*fill; 4096];
+                let mut left = self.fill_len();
+                while left > 0 {
+                    let n = left.min(block.len() as u64) as usize;
+                    f(&block[..n]);
+                    left -= n as u64;
+                }
+                f(suffix);
+            }
+        }
+    }
+
+    /// The first `n` bytes of the body.
+    pub fn head(&self, n: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(n.min(self.len() as usize));
+        self.for_each_chunk(|chunk| {
+            let take = (n - out.len()).min(chunk.len());
+            out.extend_from_slice(&chunk[..take]);
+        });
+        out
+    }
+
+    /// The body chunk by chunk, a `Filled` one generated as it goes.
+    fn into_chunks(self) -> FilledBody {
+        let fill_left = self.fill_len();
+        match self {
+            Body::Bytes(b) => FilledBody { prefix: Some(b.into()), block: Bytes::new(), fill_left, suffix: None },
+            Body::Filled { prefix, fill, suffix, .. } => FilledBody {
+                prefix: Some(prefix.into()),
+                block: Bytes::from(vec![fill; FILL_CHUNK]),
+                fill_left,
+                suffix: Some(suffix.into()),
+            },
+        }
+    }
+
+    fn into_reqwest(self) -> reqwest::Body {
+        match self {
+            Body::Bytes(b) => b.into(),
+            filled => reqwest::Body::wrap(filled.into_chunks()),
+        }
+    }
+}
+
+const FILL_CHUNK: usize = 64 * 1024;
+
+/// Streaming body behind `Body::Filled`. Reports an exact size so the request
+/// carries a real `Content-Length` rather than chunked encoding.
+struct FilledBody {
+    prefix: Option<Bytes>,
+    block: Bytes,
+    fill_left: u64,
+    suffix: Option<Bytes>,
+}
+
+impl FilledBody {
+    fn remaining(&self) -> u64 {
+        let edge = |b: &Option<Bytes>| b.as_ref().map_or(0, |b| b.len() as u64);
+        edge(&self.prefix) + self.fill_left + edge(&self.suffix)
+    }
+
+    /// The next chunk that is not empty.
+    fn next_chunk(&mut self) -> Option<Bytes> {
+        loop {
+            let chunk = if let Some(prefix) = self.prefix.take() {
+                prefix
+            } else if self.fill_left > 0 {
+                let n = self.fill_left.min(FILL_CHUNK as u64);
+                self.fill_left -= n;
+                self.block.slice(..n as usize)
+            } else {
+                self.suffix.take()?
+            };
+            if !chunk.is_empty() {
+                return Some(chunk);
+            }
+        }
+    }
+}
+
+impl http_body::Body for FilledBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        Poll::Ready(self.next_chunk().map(|chunk| Ok(Frame::data(chunk))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining())
+    }
+}
+
+/// Where a synchronized burst waits. Each request sends everything but the
+/// last byte of its body, then is held here until the gate opens; see
+/// `Transport::send_burst` for how the last bytes are released.
+#[derive(Default)]
+pub struct Gate {
+    held: AtomicUsize,
+    arrival: Notify,
+    open: AtomicBool,
+    opened_at: OnceLock<Instant>,
+    waiting: Mutex<Vec<Waker>>,
+}
+
+impl Gate {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Count one more request as held.
+    pub fn arrive(&self) {
+        self.held.fetch_add(1, Ordering::SeqCst);
+        self.arrival.notify_one();
+    }
+
+    pub fn poll_open(&self, cx: &mut TaskContext<'_>) -> Poll<()> {
+        if self.open.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        self.waiting.lock().expect("gate poisoned").push(cx.waker().clone());
+        // Re-check: `open` may have flipped before the waker was registered.
+        if self.open.load(Ordering::SeqCst) { Poll::Ready(()) } else { Poll::Pending }
+    }
+
+    /// Wait until `n` requests are held or `within` passes; returns how many are.
+    pub async fn held(&self, n: usize, within: Duration) -> usize {
+        let deadline = tokio::time::Instant::now() + within;
+        loop {
+            let held = self.held.load(Ordering::SeqCst);
+            if held >= n || tokio::time::timeout_at(deadline, self.arrival.notified()).await.is_err() {
+                return self.held.load(Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn release(&self) {
+        self.opened_at.get_or_init(Instant::now);
+        self.open.store(true, Ordering::SeqCst);
+        for waker in self.waiting.lock().expect("gate poisoned").drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// A body streamed chunk by chunk up to, but not including, its last byte,
+/// which `last_byte` hands over once the chunks run out.
+pub struct Withheld {
+    body: FilledBody,
+    last: Option<Bytes>,
+}
+
+impl Withheld {
+    pub fn new(body: Option<Body>) -> Self {
+        Self { body: body.unwrap_or(Body::Bytes(Vec::new())).into_chunks(), last: None }
+    }
+
+    /// The last byte, or nothing for an empty body.
+    pub fn last_byte(&mut self) -> Bytes {
+        self.last.take().unwrap_or_default()
+    }
+
+    fn remaining(&self) -> u64 {
+        self.body.remaining() + self.last.as_ref().map_or(0, |l| l.len() as u64)
+    }
+}
+
+impl Iterator for Withheld {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        let mut chunk = self.body.next_chunk()?;
+        if self.body.remaining() == 0 {
+            self.last = Some(chunk.split_off(chunk.len() - 1));
+        }
+        // A one-byte final chunk leaves nothing before the last byte.
+        Some(chunk).filter(|c| !c.is_empty())
+    }
+}
+
+/// Body of an HTTP/1.1 request held at a `Gate`, with an exact size so hyper
+/// cannot finish the request before the last byte is released.
+struct HeldBody {
+    body: Withheld,
+    gate: Arc<Gate>,
+    arrived: bool,
+}
+
+impl http_body::Body for HeldBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        if let Some(chunk) = self.body.next() {
+            return Poll::Ready(Some(Ok(Frame::data(chunk))));
+        }
+        if self.body.last.is_none() {
+            return Poll::Ready(None);
+        }
+        if !self.arrived {
+            self.arrived = true;
+            self.gate.arrive();
+        }
+        match self.gate.poll_open(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Ok(Frame::data(self.body.last_byte())))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.remaining() == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.body.remaining())
+    }
+}
+
+/// Set `name` to `value` in place of the first pair `same` matches, dropping
+/// any others, or add it at the end.
+fn put(pairs: &mut Vec<(String, String)>, name: &str, value: String, same: impl Fn(&str, &str) -> bool) {
+    match pairs.iter().position(|(k, _)| same(k, name)) {
+        Some(at) => {
+            pairs[at] = (name.to_string(), value);
+            let mut i = 0;
+            pairs.retain(|(k, _)| {
+                i += 1;
+                i - 1 == at || !same(k, name)
+            });
+        }
+        None => pairs.push((name.to_string(), value)),
+    }
+}
+
+/// Swap the value of the credential `put` under `name` from `from` to `to`,
+/// unless a forced header took its place.
+fn swap(pairs: &mut [(String, String)], name: &str, from: &str, to: &str, same: impl Fn(&str, &str) -> bool) {
+    if let Some(pair) = pairs.iter_mut().find(|(k, v)| same(k, name) && v == from) {
+        pair.1 = to.to_string();
+    }
+}
+
+/// Whether `e` came from the network (connect, TLS, timeout, reset) rather
+/// than a refusal before anything was sent.
+pub fn is_transport(e: &anyhow::Error) -> bool {
+    e.chain().any(|c| c.is::<reqwest::Error>() || c.is::<hyper::Error>() || c.is::<h2::Error>() || c.is::<io::Error>())
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub elapsed: Duration,
+    /// The request as it went out: stamped and signed under `[signing]`,
+    /// with the forced headers, and the credential in place as `REDACTED`.
+    /// Sending it again puts the credential back where it was.
+    pub sent: Request,
+}
+
+/// What the credential's value reads in `Response::sent` and the artifacts.
+pub const REDACTED: &str = "[redacted]";
+
+pub struct Transport {
+    client: Client,
+    /// For what `client` cannot send as asked
+    dialer: Dialer,
+    base_url: String,
+    auth: Option<Auth>,
+    /// Charged for the token fetches `auth` makes
+    sched: Arc<Scheduler>,
+    signer: Option<Signer>,
+    /// `force_headers`, checked and sorted by name
+    forced: Vec<(String, String)>,
+    max_payload_bytes: u64,
+    http_version: HttpVersion,
+}
+
+impl Transport {
+    pub fn new(p: &Profile, sched: &Arc<Scheduler>) -> Result<Self> {
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_millis(p.timeouts.connect_ms))
+            .read_timeout(Duration::from_millis(p.timeouts.read_ms))
+            .use_rustls_tls();
+        let pinning = Pinning::resolve(p, &*allowlist::resolver(p)).context(Refused)?;
+        builder = pinning.apply(builder);
+        if let Some((identity, ca)) = p.auth.as_ref().map(|a| a.identity()).transpose()?.flatten() {
+            builder = builder.identity(identity);
+            if let Some(ca) = ca {
+                builder = builder.add_root_certificate(ca);
+            }
+        }
+        builder = match p.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+        let client = builder.build().context("failed to build HTTP client")?;
+
+        let mut forced = Vec::new();
+        for (k, v) in &p.safety.force_headers {
+            HeaderName::from_bytes(k.as_bytes()).with_context(|| format!("invalid forced header name: {k}"))?;
+            HeaderValue::from_str(v).with_context(|| format!("invalid value for forced header {k}"))?;
+            forced.push((k.clone(), v.clone()));
+        }
+        forced.sort();
+
+        Ok(Self {
+            client,
+            dialer: Dialer::new(p, &pinning)?,
+            base_url: p.base_url.trim_end_matches('/').to_string(),
+            auth: p.auth.as_ref().map(Auth::new).transpose()?,
+            sched: Arc::clone(sched),
+            signer: p.signing.as_ref().map(|s| Signer::new(s, &p.base_url)).transpose()?,
+            forced,
+            max_payload_bytes: p.safety.max_payload_bytes.0,
+            http_version: p.http_version,
+        })
+    }
+
+    pub async fn send(&self, req: &Request) -> Result<Response> {
+        let out = self.prepare(req).await?;
+        // reqwest lower-cases header names, so a re-cased credential goes out
+        // on a connection of our own.
+        if let Some(name) = &out.recase {
+            let body = out.wire.body.as_ref().map_or_else(Vec::new, |b| b.head(b.len() as usize));
+            let started = Instant::now();
+            let reply = self.dialer.http1(out.origin_form(Full::new(Bytes::from(body)))?, Some(name), None).await;
+            return out.respond(reply, started);
+        }
+
+        let Prepared { method, url, wire, headers, sent, .. } = out;
+        let mut builder = self.client.request(method, &url).headers(headers);
+        if !wire.query.is_empty() {
+            builder = builder.query(&wire.query);
+        }
+        if let Some(body) = wire.body.filter(|b| b.len() > 0) {
+            builder = builder.body(body.into_reqwest());
+        }
+
+        let started = Instant::now();
+        let resp = builder
+            .send()
+            .await
+            .map_err(|e| e.without_url())
+            .with_context(|| format!("{} {url} failed", wire.method))?;
+        let status = resp.status().as_u16();
+        let headers = resp
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+            .collect();
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|e| e.without_url())
+            .with_context(|| format!("reading response body from {url}"))?
+            .to_vec();
+        let elapsed = started.elapsed();
+
+        tracing::debug!(target: "transport", %url, status, ms = elapsed.as_millis() as u64, "response");
+        Ok(Response { status, headers, body, elapsed, sent })
+    }
+
+    /// Send a synchronized burst, each request held at `gate` until it opens.
+    /// With `http_version = "http2"` this is the single-packet attack: the
+    /// requests are streams of one connection, and the final DATA frames of
+    /// all of them leave in one write. Otherwise it is last-byte sync, each
+    /// request on an HTTP/1.1 connection of its own.
+    pub async fn send_burst(self: &Arc<Self>, reqs: Vec<Request>, gate: &Arc<Gate>) -> Vec<Result<Response>> {
+        if self.http_version == HttpVersion::Http2 {
+            return self.send_streams(&reqs, gate).await;
+        }
+        let tasks: Vec<_> = reqs
+            .into_iter()
+            .map(|req| {
+                let (transport, gate) = (Arc::clone(self), Arc::clone(gate));
+                tokio::spawn(async move { transport.send_held(&req, &gate).await })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap_or_else(|e| Err(anyhow!("request task failed: {e}"))));
+        }
+        results
+    }
+
+    /// Send `req` over HTTP/1.1, held at `gate` before its last body byte, or
+    /// before it is sent at all when it has no body.
+    async fn send_held(&self, req: &Request, gate: &Arc<Gate>) -> Result<Response> {
+        let out = self.prepare(req).await?;
+        let body = out.wire.body.clone().filter(|b| b.len() > 0);
+        if body.is_none() {
+            // Nothing to hold back: wait at the gate and send whole, less tightly synced.
+            gate.arrive();
+            std::future::poll_fn(|cx| gate.poll_open(cx)).await;
+        }
+        let held = HeldBody { body: Withheld::new(body), gate: Arc::clone(gate), arrived: false };
+        let started = Instant::now();
+        let reply = self.dialer.http1(out.origin_form(held)?, out.recase.as_deref(), Some(gate)).await;
+        // Time held at the gate is not the server's latency.
+        out.respond(reply, gate.opened_at.get().map_or(started, |&opened| opened.max(started)))
+    }
+
+    /// Send `reqs` as streams of one HTTP/2 connection, held at `gate`.
+    async fn send_streams(&self, reqs: &[Request], gate: &Gate) -> Vec<Result<Response>> {
+        let mut prepared = Vec::new();
+        let mut streams = Vec::new();
+        for req in reqs {
+            match self.prepare(req).await.and_then(|out| Ok((out.h2_head()?, out))) {
+                Ok((head, out)) => {
+                    streams.push((head, Withheld::new(out.wire.body.clone())));
+                    prepared.push(Ok(out));
+                }
+                Err(e) => prepared.push(Err(e)),
+            }
+        }
+        let count = streams.len();
+        let mut replies = match self.dialer.h2_burst(streams, gate).await {
+            Ok(replies) => replies,
+            // The connection failed every stream on it.
+            Err(e) => (0..count).map(|_| Err(io::Error::other(format!("{e:#}")).into())).collect(),
+        }
+        .into_iter();
+        let opened = gate.opened_at.get().copied().unwrap_or_else(Instant::now);
+        prepared.into_iter().map(|out| out?.respond(replies.next().expect("one reply per stream"), opened)).collect()
+    }
+
+    /// `req` as it goes out: with the credential, the forced headers and the
+    /// signature, checked against `max_payload_bytes`.
+    async fn prepare(&self, req: &Request) -> Result<Prepared> {
+        let method = Method::from_bytes(req.method.as_bytes())
+            .with_context(|| format!("invalid HTTP method: {}", req.method))?;
+        let url = format!("{}{}", self.base_url, req.path);
+
+        // Everything the transport adds counts against the ceiling, not only the case.
+        let mut wire = req.clone();
+        let credential = match &self.auth {
+            Some(auth) => auth.credential(&self.client, &self.sched, &req.auth).await?,
+            None => None,
+        };
+        let secret = match &credential {
+            Some(Credential::Header(k, _)) => Some(k.clone()),
+            _ => None,
+        };
+        // The credential goes in as `REDACTED` and forced headers always win
+        // over whatever the case carries; the origin a case is signed as gets
+        // the same.
+        let finish = |r: &mut Request| {
+            match &credential {
+                Some(Credential::Header(k, _)) => put(&mut r.headers, k, REDACTED.into(), |a, b| a.eq_ignore_ascii_case(b)),
+                Some(Credential::Query(k, _)) => put(&mut r.query, k, REDACTED.into(), |a, b| a == b),
+                None => {}
+            }
+            for (k, v) in &self.forced {
+                put(&mut r.headers, k, v.clone(), |a, b| a.eq_ignore_ascii_case(b));
+            }
+        };
+        finish(&mut wire);
+        if let Some(origin) = &mut wire.origin {
+            finish(origin);
+        }
+        // Swaps the credential's value in a request and the origin it is signed as.
+        let show = |r: &mut Request, from: &str, to: &str| {
+            let origin = r.origin.as_deref_mut().map(|o| (&mut o.headers, &mut o.query));
+            for (headers, query) in [(&mut r.headers, &mut r.query)].into_iter().chain(origin) {
+                match &credential {
+                    Some(Credential::Header(k, _)) => swap(headers, k, from, to, |a, b| a.eq_ignore_ascii_case(b)),
+                    Some(Credential::Query(k, _)) => swap(query, k, from, to, |a, b| a == b),
+                    None => {}
+                }
+            }
+        };
+        let value = match &credential {
+            Some(Credential::Header(_, v) | Credential::Query(_, v)) => v.as_str(),
+            None => "",
+        };
+        // Signed last, over every header and parameter as it goes out, the
+        // credential included; the signature stays valid once it is hidden.
+        if let Some(signer) = self.signer.as_ref().filter(|_| !req.signed) {
+            show(&mut wire, REDACTED, value);
+            wire = signer.sign(&wire)?;
+            show(&mut wire, value, REDACTED);
+        }
+        wire.signed = true;
+        let sent = wire.clone();
+        show(&mut wire, REDACTED, value);
+        let size = wire.size();
+        if size > self.max_payload_bytes {
+            bail!("request payload of {size} bytes exceeds max_payload_bytes");
+        }
+
+        let mut headers = HeaderMap::new();
+        for (k, v) in &wire.headers {
+            // Fuzz cases may carry headers reqwest refuses; skip rather than abort the session.
+            match (HeaderName::from_bytes(k.as_bytes()), HeaderValue::from_bytes(v.as_bytes())) {
+                (Ok(name), Ok(mut value)) => {
+                    value.set_sensitive(secret.as_ref().is_some_and(|s| s.eq_ignore_ascii_case(k)));
+                    headers.append(name, value);
+                }
+                _ => tracing::debug!(header = %k, "dropping unencodable header"),
+            }
+        }
+
+        let recase = secret.filter(|_| matches!(req.auth, Tamper::Case(_)));
+        Ok(Prepared { method, url, wire, headers, recase, sent })
+    }
+}
+
+/// A request `Transport::prepare` made ready to send.
+struct Prepared {
+    method: Method,
+    url: String,
+    wire: Request,
+    headers: HeaderMap,
+    /// The credential's header name, to be spelled as given under `Tamper::Case`
+    recase: Option<String>,
+    sent: Request,
+}
+
+impl Prepared {
+    /// The URL with the query.
+    fn target(&self) -> Result<Url> {
+        let mut url = Url::parse(&self.url).with_context(|| format!("invalid URL {}", self.url))?;
+        if !self.wire.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.wire.query);
+        }
+        Ok(url)
+    }
+
+    /// An HTTP/1.1 request: origin-form target and a `Host` header.
+    fn origin_form<B>(&self, body: B) -> Result<http::Request<B>> {
+        let url = self.target()?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let mut request = http::Request::builder()
+            .method(self.method.clone())
+            .uri(&url[Position::BeforePath..])
+            .header(http::header::HOST, host);
+        if let Some(map) = request.headers_mut() {
+            map.extend(self.headers.clone());
+        }
+        Ok(request.body(body)?)
+    }
+
+    /// The head of an HTTP/2 request, whose body is sent apart.
+    fn h2_head(&self) -> Result<http::Request<()>> {
+        let mut request = http::Request::builder().method(self.method.clone()).uri(self.target()?.as_str());
+        if let Some(map) = request.headers_mut() {
+            map.extend(self.headers.clone());
+            if let Some(len) = self.wire.body.as_ref().map(Body::len).filter(|&n| n > 0) {
+                map.insert(http::header::CONTENT_LENGTH, len.into());
+            }
+        }
+        Ok(request.body(())?)
+    }
+
+    fn respond(self, reply: Result<wire::Reply>, started: Instant) -> Result<Response> {
+        let reply = reply.with_context(|| format!("{} {} failed", self.wire.method, self.url))?;
+        let elapsed = reply.done.saturating_duration_since(started);
+        tracing::debug!(target: "transport", url = %self.url, status = reply.status, ms = elapsed.as_millis() as u64, "response");
+        Ok(Response { status: reply.status, headers: reply.headers, body: reply.body, elapsed, sent: self.sent })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oversize::ByteSize;
+    use crate::testutil;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn forced_headers_override_case_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).and(path("/v1/returns")).respond_with(ResponseTemplate::new(201)).mount(&server).await;
+        let p = testutil::profile(&server.uri());
+        let mut req = testutil::request(&p);
+        req.headers.push(("x-env".into(), "production".into()));
+
+        let resp = testutil::transport(&p).send(&req).await.unwrap();
+
+        assert_eq!(resp.status, 201);
+        let received = server.received_requests().await.unwrap();
+        let values: Vec<_> = received[0].headers.get_all("x-env").iter().map(|v| v.to_str().unwrap().to_string()).collect();
+        assert_eq!(values, ["sandbox"]);
+    }
+
+    #[tokio::test]
+    async fn sent_request_is_redacted_and_resends_identically() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(201)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        std::env::set_var("FUZZKIT_TEST_SENT_TOKEN", "s3cret");
+        p.auth = Some(toml::from_str(r#"
+            type = "bearer"
+            token = { env = "FUZZKIT_TEST_SENT_TOKEN" }
+        "#).unwrap());
+        p.signing = Some(testutil::signing(""));
+        let transport = testutil::transport(&p);
+
+        let sent = transport.send(&testutil::request(&p)).await.unwrap().sent;
+        transport.send(&sent).await.unwrap();
+
+        assert!(!format!("{sent:?}").contains("s3cret"));
+        let header = |name: &str| sent.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+        assert_eq!(header("authorization"), Some(REDACTED));
+        assert_eq!(header("x-env"), Some("sandbox"));
+        assert!(header("x-signature").is_some());
+        let received = server.received_requests().await.unwrap();
+        assert_eq!(received[0].headers.get("authorization").unwrap(), "Bearer s3cret");
+        for name in ["authorization", "x-signature", "x-nonce", "x-timestamp", "x-env"] {
+            assert_eq!(received[0].headers.get(name), received[1].headers.get(name), "{name}");
+        }
+        assert_eq!(received[0].body, received[1].body);
+    }
+
+    #[tokio::test]
+    async fn signature_covers_forced_headers_and_the_query_credential() {
+        use hmac::{Hmac, Mac};
+        use sha2::{Digest, Sha256};
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        std::env::set_var("FUZZKIT_TEST_SIGNED_KEY", "s3cret");
+        p.auth = Some(toml::from_str(r#"
+            type = "api_key"
+            name = "api_key"
+            in = "query"
+            key = { env = "FUZZKIT_TEST_SIGNED_KEY" }
+        "#).unwrap());
+        let mut signing = testutil::signing("");
+        signing.template = "{method}\n{path}\n{query}\n{header:X-Env}\n{timestamp}\n{nonce}\n{body_sha256}".into();
+        p.signing = Some(signing);
+        let transport = testutil::transport(&p);
+        let mut req = testutil::request(&p);
+        req.headers.push(("X-Env".into(), "production".into()));
+        req.query = vec![("page".into(), "1".into()), ("api_key".into(), "guess".into())];
+
+        let sent = transport.send(&req).await.unwrap().sent;
+        transport.send(&sent).await.unwrap();
+
+        assert_eq!(sent.query, [("page".to_string(), "1".to_string()), ("api_key".to_string(), REDACTED.to_string())]);
+        let received = server.received_requests().await.unwrap();
+        let header = |name: &str| received[0].headers.get(name).unwrap().to_str().unwrap().to_string();
+        assert_eq!(received[0].url.query(), Some("page=1&api_key=s3cret"));
+        assert_eq!(header("x-env"), "sandbox");
+        let canonical = format!(
+            "POST\n/v1/returns\npage=1&api_key=s3cret\nsandbox\n{}\n{}\n{}",
+            header("x-timestamp"),
+            header("x-nonce"),
+            hex::encode(Sha256::digest(&received[0].body))
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"test-key").unwrap();
+        mac.update(canonical.as_bytes());
+        assert_eq!(header("x-signature"), hex::encode(mac.finalize().into_bytes()));
+        // The re-sent request is the same, signature and credential included.
+        assert_eq!(received[1].url, received[0].url);
+        assert_eq!(received[1].headers.get("x-signature"), received[0].headers.get("x-signature"));
+    }
+
+    #[tokio::test]
+    async fn read_timeout_fails_the_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(1500)))
+            .mount(&server)
+            .await;
+        let mut p = testutil::profile(&server.uri());
+        p.timeouts.read_ms = 200;
+
+        let started = Instant::now();
+        let err = testutil::transport(&p).send(&testutil::request(&p)).await.unwrap_err();
+
+        assert!(err.chain().any(|c| c.is::<reqwest::Error>()), "{err:#}");
+        assert!(started.elapsed() < Duration::from_millis(1200));
+    }
+
+    #[tokio::test]
+    async fn oversized_request_is_refused_before_sending() {
+        let server = MockServer::start().await;
+        Mock::given(header("x-env", "sandbox")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        p.safety.max_payload_bytes = ByteSize(64);
+        let mut req = testutil::request(&p);
+        req.body = Some(Body::Filled { prefix: Vec::new(), fill: b'A', len: 65, suffix: Vec::new() });
+
+        let err = testutil::transport(&p).send(&req).await.unwrap_err();
+
+        assert!(err.to_string().contains("exceeds max_payload_bytes"), "{err:#}");
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn payload_ceiling_covers_body_headers_and_query_together() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let mut p = testutil::profile(&server.uri());
+        p.safety.max_payload_bytes = ByteSize(300);
+        // The forced `X-Env: sandbox` and `api_key=s3cret` count too: 12 + 13 bytes.
+        std::env::set_var("FUZZKIT_TEST_CEILING_KEY", "s3cret");
+        p.auth = Some(toml::from_str(r#"
+            type = "api_key"
+            name = "api_key"
+            in = "query"
+            key = { env = "FUZZKIT_TEST_CEILING_KEY" }
+        "#).unwrap());
+        let transport = testutil::transport(&p);
+        let mut req = testutil::request(&p);
+        req.headers = vec![("X-Pad".into(), "h".repeat(83))];
+        req.query = vec![("pad".into(), "q".repeat(84))];
+        req.body = Some(Body::Bytes(vec![b'b'; 100]));
+        assert_eq!(req.size(), 300 - 12 - 13);
+
+        transport.send(&req).await.unwrap();
+        req.body = Some(Body::Bytes(vec![b'b'; 101]));
+        let err = transport.send(&req).await.unwrap_err();
+
+        assert!(err.to_string().contains("301 bytes exceeds max_payload_bytes"), "{err:#}");
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn filled_body_is_sent_with_exact_length() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let p = testutil::profile(&server.uri());
+        let mut req = testutil::request(&p);
+        req.body = Some(Body::Filled { prefix: b"{\"a\":\"".to_vec(), fill: b'A', len: 100_000, suffix: b"\"}".to_vec() });
+
+        testutil::transport(&p).send(&req).await.unwrap();
+
+        let received = server.received_requests().await.unwrap();
+        assert_eq!(received[0].body.len(), 100_000);
+        assert_eq!(received[0].headers.get("content-length").unwrap(), "100000");
+        assert!(received[0].body.ends_with(b"AA\"}"));
+    }
+
+    /// A local server that records, per request, the HTTP version, the body
+    /// length and when the last byte of its body arrived.
+    async fn arrivals(http2: bool) -> (String, Arc<Mutex<Vec<(hyper::Version, usize, Instant)>>>) {
+        use http_body_util::{BodyExt, Empty};
+        use hyper::service::service_fn;
+        use hyper_util::rt::{TokioExecutor, TokioIo};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&log);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let seen = Arc::clone(&seen);
+                let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                    let seen = Arc::clone(&seen);
+                    async move {
+                        let version = req.version();
+                        let body = req.into_body().collect().await?.to_bytes();
+                        seen.lock().unwrap().push((version, body.len(), Instant::now()));
+                        Ok::<_, hyper::Error>(hyper::Response::new(Empty::<Bytes>::new()))
+                    }
+                });
+                let io = TokioIo::new(stream);
+                tokio::spawn(async move {
+                    if http2 {
+                        hyper::server::conn::http2::Builder::new(TokioExecutor::new()).serve_connection(io, service).await
+                    } else {
+                        hyper::server::conn::http1::Builder::new().serve_connection(io, service).await
+                    }
+                });
+            }
+        });
+        (url, log)
+    }
+
+    /// Relays one connection to `to`, recording each read from the client
+    /// with the time it arrived.
+    async fn tap(to: &str) -> (String, Arc<Mutex<Vec<(Instant, Vec<u8>)>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let to = to.trim_start_matches("http://").to_string();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&log);
+        tokio::spawn(async move {
+            let (client, _) = listener.accept().await.unwrap();
+            let server = tokio::net::TcpStream::connect(to).await.unwrap();
+            let (mut client_rx, mut client_tx) = client.into_split();
+            let (mut server_rx, mut server_tx) = server.into_split();
+            tokio::spawn(async move { tokio::io::copy(&mut server_rx, &mut client_tx).await });
+            let mut buf = vec![0; 1 << 16];
+            loop {
+                let n = client_rx.read(&mut buf).await.unwrap_or(0);
+                if n == 0 {
+                    break;
+                }
+                seen.lock().unwrap().push((Instant::now(), buf[..n].to_vec()));
+                server_tx.write_all(&buf[..n]).await.unwrap();
+            }
+        });
+        (url, log)
+    }
+
+    /// A burst of `n` requests with bodies past a default HTTP/2 window, held
+    /// at a gate and released; returns when they were released.
+    async fn burst(p: &Profile, n: usize) -> Instant {
+        let transport = Arc::new(testutil::transport(p));
+        let gate = Gate::new();
+        let mut req = testutil::request(p);
+        req.body = Some(Body::Filled { prefix: b"{\"a\":\"".to_vec(), fill: b'A', len: 100_000, suffix: b"\"}".to_vec() });
+        let sending = {
+            let gate = Arc::clone(&gate);
+            tokio::spawn(async move { transport.send_burst(vec![req; n], &gate).await })
+        };
+
+        assert_eq!(gate.held(n, Duration::from_secs(2)).await, n);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let released = Instant::now();
+        gate.release();
+        for result in sending.await.unwrap() {
+            assert_eq!(result.unwrap().status, 200);
+        }
+        released
+    }
+
+    /// Check that no request of a held burst completed before the release,
+    /// that every body arrived whole, and that the last bytes landed close
+    /// together.
+    async fn last_bytes_land_together(version: HttpVersion, expected: hyper::Version) {
+        let (url, log) = arrivals(version == HttpVersion::Http2).await;
+        let mut p = testutil::profile(&url);
+        p.http_version = version;
+        p.safety.max_payload_bytes = ByteSize(1 << 20);
+        let n = 4;
+        let released = burst(&p, n).await;
+
+        let log = log.lock().unwrap().clone();
+        assert_eq!(log.len(), n);
+        assert!(log.iter().all(|(v, len, _)| *v == expected && *len == 100_000), "{log:?}");
+        let first = log.iter().map(|(_, _, at)| *at).min().unwrap();
+        let last = log.iter().map(|(_, _, at)| *at).max().unwrap();
+        assert!(first >= released, "a held request completed before the gate opened");
+        assert!(last - first < Duration::from_millis(50), "last bytes spread over {:?}", last - first);
+    }
+
+    #[tokio::test]
+    async fn held_http1_requests_finish_together_on_release() {
+        last_bytes_land_together(HttpVersion::Http1, hyper::Version::HTTP_11).await;
+    }
+
+    #[tokio::test]
+    async fn held_http2_requests_finish_together_on_release() {
+        last_bytes_land_together(HttpVersion::Http2, hyper::Version::HTTP_2).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn http2_burst_sends_every_final_frame_in_one_write() {
+        let (server, _) = arrivals(true).await;
+        let (url, log) = tap(&server).await;
+        let mut p = testutil::profile(&url);
+        p.http_version = HttpVersion::Http2;
+        p.safety.max_payload_bytes = ByteSize(1 << 20);
+        let n = 4;
+        let released = burst(&p, n).await;
+
+        // The first read after the release holds one END_STREAM DATA frame
+        // (type 0, flag 1) per stream.
+        let log = log.lock().unwrap().clone();
+        let (_, first) = log.iter().find(|(at, _)| *at >= released).expect("the final frames arrived");
+        let mut ends = Vec::new();
+        let mut at = 0;
+        while at + 9 <= first.len() {
+            let len = u32::from_be_bytes([0, first[at], first[at + 1], first[at + 2]]) as usize;
+            if first[at + 3] == 0 && first[at + 4] & 1 == 1 {
+                ends.push(u32::from_be_bytes([first[at + 5], first[at + 6], first[at + 7], first[at + 8]]));
+            }
+            at += 9 + len;
+        }
+        assert_eq!(ends.len(), n, "final frames of streams {ends:?} in the first write after release");
+    }
+}
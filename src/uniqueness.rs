@@ -0,0 +1,458 @@
+//! Uniqueness mode: does the API refuse to act twice on one request?
+//!
+//! Three checks against one creating endpoint: a used `Idempotency-Key` sent
+//! again with a different body, a nonce re-used within the validity window,
+//! and the same request fired several times at once. A profile-declared
+//! read-back GET then counts the resources that exist, so duplicate side
+//! effects show up as findings rather than guesses from status codes.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+use crate::bypass::Tamper;
+use crate::endpoint;
+use crate::mutate;
+use crate::oracle::{Signal, Verdict};
+use crate::plan::{self, Case};
+use crate::runner::{self, Outcome};
+use crate::scheduler::Scheduler;
+use crate::timestamp::{self, Field};
+use crate::transport::{Body, Request, Transport};
+use crate::Profile;
+
+/// `[uniqueness]`
+#[derive(Debug, Deserialize)]
+pub struct Uniqueness {
+    /// Path template of the endpoint that creates resources; the first endpoint if omitted
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default = "default_key_header")]
+    pub idempotency_header: String,
+    /// Where the request carries its nonce; `[signing]`'s or `[clock]`'s if omitted
+    #[serde(default)]
+    pub nonce: Option<Field>,
+    /// How many identical requests to fire at once
+    #[serde(default = "default_duplicates")]
+    pub duplicates: usize,
+    #[serde(default)]
+    pub readback: Option<Readback>,
+}
+
+/// `[uniqueness.readback]`: how to count what the creating requests left behind.
+#[derive(Debug, Deserialize)]
+pub struct Readback {
+    /// GET path relative to `base_url`. With `{id}`, fetched once per ID the
+    /// creating responses returned; without, a listing counted before and after.
+    pub path: String,
+    /// JSON pointer to the new resource's ID in a create response
+    #[serde(default)]
+    pub id: Option<String>,
+    /// JSON pointer to the item count or item array in the listing; the whole
+    /// response when omitted
+    #[serde(default)]
+    pub count: Option<String>,
+}
+
+fn default_key_header() -> String {
+    "Idempotency-Key".into()
+}
+
+fn default_duplicates() -> usize {
+    5
+}
+
+fn accepted(o: &Outcome) -> bool {
+    matches!(&o.result, Ok(r) if (200..300).contains(&r.status))
+}
+
+fn status(o: &Outcome) -> String {
+    o.result.as_ref().map_or_else(|_| "error".into(), |r| r.status.to_string())
+}
+
+/// Whether `b` answered as `a` did: same status and body, as a server
+/// replaying a stored response for a used idempotency key would.
+fn same_response(a: &Outcome, b: &Outcome) -> bool {
+    matches!((&a.result, &b.result), (Ok(x), Ok(y)) if x.status == y.status && x.body == y.body)
+}
+
+fn fresh_key() -> String {
+    format!("fuzzkit-{}", timestamp::fresh_nonce())
+}
+
+/// The baseline with one value changed, so it is the same kind of request
+/// with a different body: the first number plus one, else the first string
+/// with a suffix, else an extra member.
+fn variant(base: &Case) -> Option<Case> {
+    let Some(Body::Bytes(raw)) = &base.request.body else { return None };
+    let mut doc: Value = serde_json::from_slice(raw).ok()?;
+    let leaves = mutate::nodes(&doc).into_iter().map(|(at, v)| (mutate::pointer(&at), v.clone())).collect::<Vec<_>>();
+    let number = leaves.iter().find(|(_, v)| v.is_number());
+    let string = leaves.iter().find(|(_, v)| v.is_string());
+    match (number, string) {
+        (Some((at, v)), _) => {
+            let next = v.as_i64().map_or_else(|| Value::from(v.as_f64().unwrap_or_default() + 1.0), |n| Value::from(n.wrapping_add(1)));
+            *doc.pointer_mut(at)? = next;
+        }
+        (None, Some((at, v))) => *doc.pointer_mut(at)? = Value::String(format!("{}-2", v.as_str().unwrap_or_default())),
+        (None, None) => {
+            doc.as_object_mut()?.insert("fuzzkit_variant".into(), Value::from(2));
+        }
+    }
+    let mut case = base.clone();
+    case.request.body = Some(Body::Bytes(doc.to_string().into_bytes()));
+    Some(case)
+}
+
+/// What one check left behind.
+struct Created {
+    /// Resources the read-back found (or distinct IDs returned, without one)
+    count: Option<u64>,
+    /// The IDs the accepted creates returned, in order
+    ids: Vec<String>,
+}
+
+struct Tester<'a> {
+    p: &'a Profile,
+    cfg: &'a Uniqueness,
+    nonce: Option<&'a Field>,
+    transport: Arc<Transport>,
+    sched: Arc<Scheduler>,
+    outcomes: Vec<Outcome>,
+}
+
+impl Tester<'_> {
+    /// Send `cases` at once, as far as the limits allow; returns the
+    /// positions of their outcomes.
+    async fn send(&mut self, cases: Vec<Case>) -> Vec<usize> {
+        let start = self.outcomes.len();
+        let indexed = cases.into_iter().enumerate().map(|(i, c)| (start + i, c)).collect();
+        let sent = runner::run(Arc::clone(&self.transport), Arc::clone(&self.sched), self.p.limits.retries, indexed).await;
+        self.outcomes.extend(sent);
+        (start..self.outcomes.len()).collect()
+    }
+
+    /// `base` with the idempotency key and nonce set and a fresh `[clock]` timestamp.
+    fn stamp(&self, base: &Case, operator: &str, target: &str, key: &str, nonce: &str) -> Result<Case> {
+        let mut case = Case { operator: operator.into(), target: target.into(), request: base.request.clone() };
+        let req = &mut case.request;
+        req.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(&self.cfg.idempotency_header));
+        req.headers.push((self.cfg.idempotency_header.clone(), key.into()));
+        if let Some(field) = self.nonce {
+            timestamp::set_field(req, field, &Value::String(nonce.into()))?;
+        }
+        if let Some(clock) = &self.p.clock {
+            timestamp::set_field(req, &clock.timestamp, &clock.timestamp.format.render(OffsetDateTime::now_utc()))?;
+        }
+        Ok(case)
+    }
+
+    fn readback(&self, path: String, target: String) -> Case {
+        Case {
+            operator: "unique.readback".into(),
+            target,
+            request: Request {
+                method: "GET".into(),
+                path,
+                query: Vec::new(),
+                headers: Vec::new(),
+                body: None,
+                auth: Tamper::None,
+                origin: None,
+                signed: false,
+            },
+        }
+    }
+
+    /// Items in the read-back listing, when it is one.
+    async fn listed(&mut self, label: &str) -> Option<u64> {
+        let rb = self.cfg.readback.as_ref().filter(|rb| !rb.path.contains("{id}"))?;
+        let case = self.readback(rb.path.clone(), label.into());
+        let at = *self.send(vec![case]).await.first()?;
+        let resp = self.outcomes[at].result.as_ref().ok().filter(|r| (200..300).contains(&r.status))?;
+        let doc: Value = serde_json::from_slice(&resp.body).ok()?;
+        let node = match &rb.count {
+            Some(pointer) => doc.pointer(pointer)?,
+            None => &doc,
+        };
+        node.as_u64().or_else(|| node.as_array().map(|items| items.len() as u64))
+    }
+
+    /// Count what the accepted creates among `sent` left behind.
+    async fn created(&mut self, sent: &[usize], before: Option<u64>) -> Created {
+        let id_at = self.cfg.readback.as_ref().and_then(|rb| rb.id.as_deref());
+        let mut ids: Vec<String> = Vec::new();
+        for &i in sent.iter().filter(|&&i| accepted(&self.outcomes[i])) {
+            let Ok(resp) = &self.outcomes[i].result else { continue };
+            let id = serde_json::from_slice::<Value>(&resp.body).ok().and_then(|doc| doc.pointer(id_at?).map(timestamp::text));
+            if let Some(id) = id.filter(|id| !ids.contains(id)) {
+                ids.push(id);
+            }
+        }
+        let count = match &self.cfg.readback {
+            Some(rb) if rb.path.contains("{id}") => {
+                let cases = ids.iter().map(|id| self.readback(rb.path.replace("{id}", &endpoint::encode_segment(id)), format!("id {id}"))).collect();
+                let found = self.send(cases).await;
+                Some(found.iter().filter(|&&i| accepted(&self.outcomes[i])).count() as u64)
+            }
+            Some(_) => match (before, self.listed("after").await) {
+                (Some(before), Some(after)) => Some(after.saturating_sub(before)),
+                _ => None,
+            },
+            None => id_at.map(|_| ids.len() as u64),
+        };
+        Created { count, ids }
+    }
+
+    fn flag(&mut self, at: usize, oracle: &str, verdict: Verdict, detail: String) {
+        self.outcomes[at].signals.push(Signal { oracle: oracle.into(), verdict, detail });
+    }
+
+    async fn idempotency(&mut self, base: &Case) -> Result<()> {
+        let Some(other) = variant(base) else {
+            tracing::info!(target: "uniqueness", "idempotency key: skipped, the endpoint has no JSON body to vary");
+            return Ok(());
+        };
+        let key = fresh_key();
+        let before = self.listed("before idempotency").await;
+        let first = self.send(vec![self.stamp(base, "unique.create", "idempotency", &key, &timestamp::fresh_nonce())?]).await;
+        let second = self.send(vec![self.stamp(&other, "unique.key", "same key, other body", &key, &timestamp::fresh_nonce())?]).await;
+        let created = self.created(&[first.clone(), second.clone()].concat(), before).await;
+        let (Some(&was), Some(&at)) = (first.first(), second.first()) else {
+            bail!("request budget exhausted during the idempotency check")
+        };
+        let header = self.cfg.idempotency_header.clone();
+        if !accepted(&self.outcomes[at]) {
+            tracing::info!(target: "uniqueness", "idempotency key: other body under a used {header} rejected ({})", status(&self.outcomes[at]));
+        } else if created.count.is_some_and(|n| n > 1) {
+            let n = created.count.unwrap_or_default();
+            let detail = format!("{} created a second resource under a used {header}; {n} exist ({})", status(&self.outcomes[at]), created.ids.join(", "));
+            tracing::warn!(target: "uniqueness", "FINDING: {detail}");
+            self.flag(at, "unique.duplicate", Verdict::Finding, detail);
+        } else if same_response(&self.outcomes[was], &self.outcomes[at]) {
+            tracing::info!(target: "uniqueness", "idempotency key: other body under a used {header} got the first response replayed");
+        } else {
+            let detail = format!(
+                "{} for a different body under a used {header}, unlike the first response; expected 409, 422 or a replay",
+                status(&self.outcomes[at])
+            );
+            tracing::info!(target: "uniqueness", "idempotency key: {detail}");
+            self.flag(at, "unique.key-reuse", Verdict::Anomaly, detail);
+        }
+        Ok(())
+    }
+
+    async fn nonce(&mut self, base: &Case) -> Result<()> {
+        if self.nonce.is_none() {
+            tracing::info!(target: "uniqueness", "nonce: skipped, no nonce field in [uniqueness], [signing] or [clock]");
+            return Ok(());
+        }
+        let nonce = timestamp::fresh_nonce();
+        let before = self.listed("before nonce").await;
+        let first = self.send(vec![self.stamp(base, "unique.create", "nonce", &fresh_key(), &nonce)?]).await;
+        let second = self.send(vec![self.stamp(base, "unique.nonce", "reused nonce", &fresh_key(), &nonce)?]).await;
+        let created = self.created(&[first, second.clone()].concat(), before).await;
+        let Some(&at) = second.first() else { bail!("request budget exhausted during the nonce check") };
+        if accepted(&self.outcomes[at]) {
+            let mut detail = format!("{} for a nonce already used within the window", status(&self.outcomes[at]));
+            if let Some(n) = created.count.filter(|&n| n > 1) {
+                detail.push_str(&format!("; {n} resources created"));
+            }
+            tracing::warn!(target: "uniqueness", "FINDING: {detail}");
+            self.flag(at, "unique.nonce-reuse", Verdict::Finding, detail);
+        } else {
+            tracing::info!(target: "uniqueness", "nonce: re-used nonce rejected ({})", status(&self.outcomes[at]));
+        }
+        Ok(())
+    }
+
+    async fn concurrent(&mut self, base: &Case) -> Result<()> {
+        let n = self.cfg.duplicates;
+        let case = self.stamp(base, "unique.concurrent", "", &fresh_key(), &timestamp::fresh_nonce())?;
+        let cases = (1..=n).map(|i| Case { target: format!("{i}/{n}"), ..case.clone() }).collect();
+        let before = self.listed("before concurrent").await;
+        let sent = self.send(cases).await;
+        let created = self.created(&sent, before).await;
+        let winners: Vec<usize> = sent.iter().copied().filter(|&i| accepted(&self.outcomes[i])).collect();
+        match created.count {
+            Some(count) if count > 1 => {
+                let detail = format!("{count} resources from {n} identical requests ({} accepted)", winners.len());
+                tracing::warn!(target: "uniqueness", "FINDING: {detail}");
+                for &at in winners.iter().skip(1) {
+                    self.flag(at, "unique.duplicate", Verdict::Finding, detail.clone());
+                }
+            }
+            None if winners.len() > 1 => {
+                let detail = format!("{} of {n} identical requests accepted; add [uniqueness.readback] to tell replays from duplicates", winners.len());
+                tracing::info!(target: "uniqueness", "concurrent: {detail}");
+                for &at in winners.iter().skip(1) {
+                    self.flag(at, "unique.duplicate", Verdict::Anomaly, detail.clone());
+                }
+            }
+            _ => tracing::info!(target: "uniqueness", "concurrent: {} of {n} identical requests accepted, no duplicates", winners.len()),
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(p: &Profile, transport: Arc<Transport>, sched: Arc<Scheduler>) -> Result<Vec<Outcome>> {
+    let Some(cfg) = &p.uniqueness else {
+        bail!("uniqueness mode needs a [uniqueness] section in the profile");
+    };
+    let endpoint = match &cfg.endpoint {
+        Some(path) => p.endpoints.iter().find(|e| &e.path == path),
+        None => p.endpoints.first(),
+    };
+    let Some(endpoint) = endpoint else {
+        bail!("[uniqueness] endpoint {:?} is not one of the profile's endpoints", cfg.endpoint.as_deref().unwrap_or_default());
+    };
+    if let Some(rb) = &cfg.readback {
+        if rb.path.contains("{id}") && rb.id.is_none() {
+            bail!("[uniqueness.readback] path {} needs an id pointer into the create response", rb.path);
+        }
+        if !p.limits.allowed_methods.iter().any(|m| m.eq_ignore_ascii_case("GET")) {
+            bail!("HTTP method 'GET' for [uniqueness.readback] {} not allowed by policy", rb.path);
+        }
+    }
+    let nonce = cfg
+        .nonce
+        .as_ref()
+        .or(p.signing.as_ref().and_then(|s| s.nonce.as_ref()))
+        .or(p.clock.as_ref().and_then(|c| c.nonce.as_ref()));
+    let base = plan::baseline(endpoint);
+    let mut tester = Tester { p, cfg, nonce, transport, sched, outcomes: Vec::new() };
+    tester.idempotency(&base).await?;
+    tester.nonce(&base).await?;
+    tester.concurrent(&base).await?;
+
+    let mut outcomes = tester.outcomes;
+    for (i, o) in outcomes.iter_mut().enumerate() {
+        o.index = i;
+    }
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn with_body(body: &[u8]) -> Case {
+        let mut case = plan::baseline(&testutil::profile("http://127.0.0.1:1").endpoints[0]);
+        case.request.body = Some(Body::Bytes(body.to_vec()));
+        case
+    }
+
+    fn body(case: &Case) -> Value {
+        let Some(Body::Bytes(raw)) = &case.request.body else { panic!("no body") };
+        serde_json::from_slice(raw).unwrap()
+    }
+
+    #[test]
+    fn variant_changes_one_value_of_the_same_shape() {
+        let number = variant(&with_body(br#"{"pin":"A1","amount":150000}"#)).unwrap();
+        assert_eq!(body(&number), serde_json::json!({"pin": "A1", "amount": 150001}));
+        let string = variant(&with_body(br#"{"pin":"A1","tags":[true]}"#)).unwrap();
+        assert_eq!(body(&string), serde_json::json!({"pin": "A1-2", "tags": [true]}));
+        let member = variant(&with_body(br#"{"ok":true}"#)).unwrap();
+        assert_eq!(body(&member), serde_json::json!({"ok": true, "fuzzkit_variant": 2}));
+
+        assert!(variant(&with_body(b"pin=A1")).is_none());
+        let mut bodiless = with_body(b"");
+        bodiless.request.body = None;
+        assert!(variant(&bodiless).is_none());
+    }
+
+    fn profile(server: &MockServer, uniqueness: &str) -> Profile {
+        let mut p = testutil::profile(&server.uri());
+        p.uniqueness = Some(toml::from_str(uniqueness).unwrap());
+        p
+    }
+
+    fn tester(p: &Profile) -> Tester<'_> {
+        Tester {
+            p,
+            cfg: p.uniqueness.as_ref().unwrap(),
+            nonce: None,
+            transport: Arc::new(testutil::transport(p)),
+            sched: Scheduler::new(&p.limits),
+            outcomes: Vec::new(),
+        }
+    }
+
+    fn created_as(id: &str) -> ResponseTemplate {
+        ResponseTemplate::new(201).set_body_json(serde_json::json!({"id": id}))
+    }
+
+    #[tokio::test]
+    async fn created_counts_the_ids_the_readback_finds() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(created_as("r1")).up_to_n_times(1).mount(&server).await;
+        Mock::given(method("POST")).respond_with(created_as("r/2")).mount(&server).await;
+        Mock::given(method("GET")).and(path("/v1/returns/r1")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        Mock::given(method("GET")).and(path("/v1/returns/r%2F2")).respond_with(ResponseTemplate::new(404)).mount(&server).await;
+        let p = profile(&server, r#"readback = { path = "/v1/returns/{id}", id = "/id" }"#);
+        let mut t = tester(&p);
+        let base = plan::baseline(&p.endpoints[0]);
+
+        let sent = t.send(vec![base.clone(), base.clone(), base]).await;
+        let mut created = t.created(&sent, None).await;
+
+        // The three creates race, so either may come back first.
+        created.ids.sort();
+        assert_eq!(created.ids, ["r/2", "r1"]);
+        assert_eq!(created.count, Some(1));
+        assert_eq!(t.outcomes.iter().filter(|o| o.case.operator == "unique.readback").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn created_counts_a_listing_before_and_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(created_as("r1")).mount(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/v1/returns"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"total": 5})))
+            .mount(&server)
+            .await;
+        let p = profile(&server, r#"readback = { path = "/v1/returns", count = "/total" }"#);
+        let mut t = tester(&p);
+
+        let sent = t.send(vec![plan::baseline(&p.endpoints[0])]).await;
+
+        assert_eq!(t.created(&sent, Some(3)).await.count, Some(2));
+        assert_eq!(t.created(&sent, None).await.count, None);
+    }
+
+    #[tokio::test]
+    async fn a_reused_key_is_flagged_only_when_the_first_response_is_not_replayed() {
+        for (second, flagged) in [(created_as("r1"), false), (created_as("r2"), true)] {
+            let server = MockServer::start().await;
+            Mock::given(method("POST")).respond_with(created_as("r1")).up_to_n_times(1).mount(&server).await;
+            Mock::given(method("POST")).respond_with(second).mount(&server).await;
+            let p = profile(&server, "");
+            let mut t = tester(&p);
+
+            t.idempotency(&plan::baseline(&p.endpoints[0])).await.unwrap();
+
+            let signals: Vec<&str> = t.outcomes.iter().flat_map(|o| &o.signals).map(|s| s.oracle.as_str()).collect();
+            assert_eq!(signals, if flagged { vec!["unique.key-reuse"] } else { vec![] });
+        }
+    }
+
+    #[tokio::test]
+    async fn the_readback_obeys_allowed_methods() {
+        let server = MockServer::start().await;
+        let mut p = profile(&server, r#"readback = { path = "/v1/returns" }"#);
+        p.limits.allowed_methods = vec!["POST".into()];
+        let transport = Arc::new(testutil::transport(&p));
+
+        let err = run(&p, transport, Scheduler::new(&p.limits)).await.err().expect("the readback should be refused");
+
+        assert!(err.to_string().contains("not allowed by policy"), "{err}");
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+//! Connections the transport opens itself, for requests whose bytes or timing
+//! reqwest will not let it choose: a header name in a case of our own, and
+//! synchronized bursts. They go to the addresses `Pinning` chose for the base
+//! host, with the same roots, client certificate and timeouts as the reqwest
+//! client.
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use h2::client::{Connection, ResponseFuture, SendRequest};
+use h2::SendStream;
+use http_body::Body;
+use http_body_util::BodyExt;
+use hyper_util::rt::TokioIo;
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fs;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use crate::allowlist::Pinning;
+use crate::transport::{Gate, Withheld};
+use crate::Profile;
+
+/// A byte stream to the base host, plain or TLS.
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// A response as read off a connection of our own.
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// When the body had been read
+    pub done: Instant,
+}
+
+pub struct Dialer {
+    host: String,
+    addrs: Vec<SocketAddr>,
+    /// `None` for an http:// base URL
+    tls: Option<ClientConfig>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+}
+
+impl Dialer {
+    pub fn new(p: &Profile, pinning: &Pinning) -> Result<Self> {
+        let url = Url::parse(&p.base_url).with_context(|| format!("invalid base_url: {}", p.base_url))?;
+        Ok(Self {
+            host: url.host_str().unwrap_or_default().trim_matches(['[', ']']).to_string(),
+            addrs: pinning.addrs(&url)?,
+            tls: (url.scheme() == "https").then(|| tls_config(p)).transpose()?,
+            connect_timeout: Duration::from_millis(p.timeouts.connect_ms),
+            read_timeout: Duration::from_millis(p.timeouts.read_ms),
+        })
+    }
+
+    /// A fresh connection to the base host, offering only `alpn` over TLS.
+    pub async fn connect(&self, alpn: &[u8]) -> Result<Box<dyn Io>> {
+        let tcp = timeout(self.connect_timeout, async {
+            let mut last = io::Error::new(io::ErrorKind::AddrNotAvailable, "no pinned address");
+            for addr in &self.addrs {
+                match TcpStream::connect(addr).await {
+                    Ok(tcp) => return Ok(tcp),
+                    Err(e) => last = e,
+                }
+            }
+            Err(last)
+        })
+        .await
+        .map_err(io::Error::from)
+        .and_then(|r| r)
+        .with_context(|| format!("failed to connect to {}", self.host))?;
+        tcp.set_nodelay(true)?;
+        let Some(tls) = &self.tls else { return Ok(Box::new(tcp)) };
+        let mut tls = tls.clone();
+        tls.alpn_protocols = vec![alpn.to_vec()];
+        let name = ServerName::try_from(self.host.clone()).with_context(|| format!("invalid TLS name {}", self.host))?;
+        let stream = timeout(self.connect_timeout, TlsConnector::from(Arc::new(tls)).connect(name, tcp))
+            .await
+            .map_err(io::Error::from)
+            .and_then(|r| r)
+            .with_context(|| format!("TLS handshake with {} failed", self.host))?;
+        if alpn == b"h2" && stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+            bail!("{} does not offer HTTP/2", self.host);
+        }
+        Ok(Box::new(stream))
+    }
+
+    /// Send `req` over HTTP/1.1 on a connection of its own, with the header
+    /// `name` spelled exactly as given. The read timeout of a request `held`
+    /// at a gate starts once the gate opens.
+    pub async fn http1<B>(&self, req: http::Request<B>, name: Option<&str>, held: Option<&Gate>) -> Result<Reply>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let mut io = self.connect(b"http/1.1").await?;
+        if let Some(name) = name {
+            io = Box::new(Recase::new(io, name));
+        }
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+        let driver = tokio::spawn(conn);
+        let exchange = async {
+            let resp = sender.send_request(req).await?;
+            let status = resp.status().as_u16();
+            let headers = fields(resp.headers());
+            let body = resp.into_body().collect().await?.to_bytes().to_vec();
+            Ok::<_, hyper::Error>(Reply { status, headers, body, done: Instant::now() })
+        };
+        let expiry = async {
+            if let Some(gate) = held {
+                poll_fn(|cx| gate.poll_open(cx)).await;
+            }
+            tokio::time::sleep(self.read_timeout).await;
+        };
+        let reply = tokio::select! {
+            reply = exchange => Some(reply),
+            () = expiry => None,
+        };
+        driver.abort();
+        Ok(reply.ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut)).context("no response within read_ms")??)
+    }
+
+    /// Send a synchronized burst as streams of one HTTP/2 connection: the
+    /// single-packet attack. Each stream goes out but for its final DATA
+    /// frame and is held at `gate`; once it opens, the final frames of every
+    /// stream are queued before the connection is polled again, so h2
+    /// encodes them together and `Coalesce` hands them on in one write.
+    /// Replies come back in the order of `burst`; an error here is the
+    /// connection's and fails every stream.
+    pub async fn h2_burst(&self, burst: Vec<(http::Request<()>, Withheld)>, gate: &Gate) -> Result<Vec<Result<Reply>>> {
+        let io = self.connect(b"h2").await?;
+        let (mut client, mut conn) = h2::client::handshake(Coalesce::new(io)).await.context("HTTP/2 handshake failed")?;
+        let held = driving(&mut conn, async {
+            let mut held = Vec::new();
+            for (req, body) in burst {
+                let stream = timeout(self.read_timeout, hold(&mut client, req, body))
+                    .await
+                    .map_err(io::Error::from)
+                    .context("stream not sent within read_ms")
+                    .and_then(|r| Ok(r?));
+                if stream.is_ok() {
+                    gate.arrive();
+                }
+                held.push(stream);
+            }
+            poll_fn(|cx| gate.poll_open(cx)).await;
+            held
+        })
+        .await?;
+        // `conn` is not polled again until every final frame is queued, so
+        // its next flush carries them all.
+        let released: Vec<Result<ResponseFuture>> = held
+            .into_iter()
+            .map(|stream| {
+                let (response, mut send, last) = stream?;
+                send.send_data(last, true)?;
+                Ok(response)
+            })
+            .collect();
+
+        let driver = tokio::spawn(conn);
+        let reads: Vec<_> = released
+            .into_iter()
+            .map(|response| response.map(|r| tokio::spawn(timeout(self.read_timeout, read(r)))))
+            .collect();
+        let mut replies = Vec::new();
+        for read in reads {
+            replies.push(match read {
+                Ok(task) => match task.await? {
+                    Ok(reply) => reply.map_err(Into::into),
+                    Err(elapsed) => Err(io::Error::from(elapsed)).context("no response within read_ms"),
+                },
+                Err(e) => Err(e),
+            });
+        }
+        driver.abort();
+        Ok(replies)
+    }
+}
+
+/// Run `f`, polling `conn` alongside it: an h2 connection only reads and
+/// writes while it is polled.
+async fn driving<T: Io, F: Future>(conn: &mut Connection<Coalesce<T>, Bytes>, f: F) -> Result<F::Output> {
+    tokio::select! {
+        out = f => Ok(out),
+        closed = conn => {
+            closed?;
+            bail!("HTTP/2 connection closed before the burst was released")
+        }
+    }
+}
+
+/// Open a stream for `req` and send all of `body` but its last byte, with
+/// capacity for that byte already granted so it goes out the moment it is
+/// sent. Returns the response to come, the stream and the withheld byte.
+async fn hold(
+    client: &mut SendRequest<Bytes>,
+    req: http::Request<()>,
+    mut body: Withheld,
+) -> Result<(ResponseFuture, SendStream<Bytes>, Bytes), h2::Error> {
+    *client = client.clone().ready().await?;
+    let (response, mut send) = client.send_request(req, false)?;
+    for mut chunk in body.by_ref() {
+        while !chunk.is_empty() {
+            let n = capacity(&mut send, chunk.len()).await?;
+            send.send_data(chunk.split_to(n), false)?;
+        }
+    }
+    let last = body.last_byte();
+    if !last.is_empty() {
+        capacity(&mut send, last.len()).await?;
+    }
+    Ok((response, send, last))
+}
+
+/// Wait until `send` may send data, up to `want` bytes; returns how many.
+async fn capacity(send: &mut SendStream<Bytes>, want: usize) -> Result<usize, h2::Error> {
+    send.reserve_capacity(want);
+    loop {
+        if send.capacity() > 0 {
+            return Ok(send.capacity().min(want));
+        }
+        match poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(granted) => granted?,
+            None => return Err(h2::Error::from(h2::Reason::STREAM_CLOSED)),
+        };
+    }
+}
+
+async fn read(response: ResponseFuture) -> Result<Reply, h2::Error> {
+    let resp = response.await?;
+    let status = resp.status().as_u16();
+    let headers = fields(resp.headers());
+    let mut recv = resp.into_body();
+    let mut body = Vec::new();
+    while let Some(data) = recv.data().await {
+        let data = data?;
+        body.extend_from_slice(&data);
+        recv.flow_control().release_capacity(data.len())?;
+    }
+    Ok(Reply { status, headers, body, done: Instant::now() })
+}
+
+fn fields(headers: &http::HeaderMap) -> Vec<(String, String)> {
+    headers.iter().map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned())).collect()
+}
+
+/// Roots and client certificate as the reqwest client has them.
+fn tls_config(p: &Profile) -> Result<ClientConfig> {
+    let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let mtls = p.auth.as_ref().and_then(|a| a.mtls.as_ref());
+    if let Some(path) = mtls.and_then(|m| m.ca_file.as_ref()) {
+        let pem = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        for cert in CertificateDer::pem_slice_iter(&pem) {
+            roots.add(cert.with_context(|| format!("invalid CA certificate {}", path.display()))?)?;
+        }
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+    Ok(match mtls {
+        Some(m) => {
+            let pem = fs::read(&m.cert_file).with_context(|| format!("failed to read {}", m.cert_file.display()))?;
+            let chain = CertificateDer::pem_slice_iter(&pem).collect::<Result<Vec<_>, _>>().context("invalid mTLS certificate")?;
+            let key = PrivateKeyDer::from_pem_slice(m.key.read().context("mTLS key")?.as_bytes()).context("invalid mTLS key")?;
+            builder.with_client_auth_cert(chain, key).context("invalid mTLS certificate or key")?
+        }
+        None => builder.with_no_client_auth(),
+    })
+}
+
+/// Spells one header name as given. hyper lower-cases every name it writes;
+/// this finds the name's line in the request head and writes it over in the
+/// wanted case, which keeps every length and so the framing.
+struct Recase<T> {
+    io: T,
+    name: Vec<u8>,
+    /// Stream offset of the name, once the head has gone by
+    at: Option<usize>,
+    written: usize,
+}
+
+impl<T> Recase<T> {
+    fn new(io: T, name: &str) -> Self {
+        Self { io, name: name.as_bytes().to_vec(), at: None, written: 0 }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Recase<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Recase<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        // hyper writes the whole head in its first write.
+        if this.written == 0 && this.at.is_none() {
+            let head = buf.windows(4).position(|w| w == b"\r\n\r\n").map_or(buf, |end| &buf[..end + 2]);
+            let line = |w: &[u8]| w[..2] == *b"\r\n" && w[2..w.len() - 1].eq_ignore_ascii_case(&this.name) && w[w.len() - 1] == b':';
+            this.at = head.windows(this.name.len() + 3).position(line).map(|i| i + 2);
+        }
+        let mut out = Cow::Borrowed(buf);
+        if let Some(at) = this.at {
+            let (start, end) = (at.max(this.written), (at + this.name.len()).min(this.written + buf.len()));
+            if start < end {
+                out.to_mut()[start - this.written..end - this.written].copy_from_slice(&this.name[start - at..end - at]);
+            }
+        }
+        let n = ready!(Pin::new(&mut this.io).poll_write(cx, &out))?;
+        this.written += n;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// Holds every write until the next flush and then passes it on in one
+/// write, so frames h2 flushes together leave together.
+struct Coalesce<T> {
+    io: T,
+    buf: Vec<u8>,
+    /// How much of `buf` the flush in progress has written
+    written: usize,
+}
+
+impl<T> Coalesce<T> {
+    fn new(io: T) -> Self {
+        Self { io, buf: Vec::new(), written: 0 }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Coalesce<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Coalesce<T> {
+    fn poll_write(mut self: Pin<&mut Self>, _: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.written < this.buf.len() {
+            let n = ready!(Pin::new(&mut this.io).poll_write(cx, &this.buf[this.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.written += n;
+        }
+        this.buf.clear();
+        this.written = 0;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}